@@ -1,7 +1,5 @@
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use std::sync::Arc;
-use telco_core::{TelcoSimulator, QuotaType};
-use std::time::Duration;
+use telco_core::{ResponseDelay, TelcoSimulator, QuotaType};
 
 fn bench_engine(c: &mut Criterion) {
     let temp_db = "/tmp/bench.db";
@@ -17,11 +15,23 @@ fn bench_engine(c: &mut Criterion) {
         })
     });
 
+    let in_memory = TelcoSimulator::ephemeral("bench_user".to_string());
+    let _ = in_memory.handle_command("General 10GB".to_string());
+
+    c.bench_function("Rust Core: Multi-Quota Deduction (Ephemeral)", |b| {
+        b.iter(|| {
+            in_memory.simulate_usage(black_box(1024 * 1024), black_box(QuotaType::General))
+        })
+    });
+
+    // Stands in for the typical JSON serialization + JS context switch lag of the legacy bridge.
+    let legacy = TelcoSimulator::ephemeral("bench_user".to_string());
+    let _ = legacy.handle_command("General 10GB".to_string());
+    legacy.set_response_delay("simulate_usage".to_string(), Some(ResponseDelay { base_ms: 10, jitter_ms: 0 })).unwrap();
+
     c.bench_function("Legacy Bridge Simulator: Mock 10ms Lag", |b| {
         b.iter(|| {
-            // Simulate the typical JSON serialization + JS context switch lag
-            std::thread::sleep(Duration::from_millis(10));
-            sim.simulate_usage(black_box(1024 * 1024), black_box(QuotaType::General))
+            legacy.simulate_usage(black_box(1024 * 1024), black_box(QuotaType::General))
         })
     });
 }
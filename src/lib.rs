@@ -1,13 +1,20 @@
+use std::collections::HashMap;
 use std::sync::Arc;
-use parking_lot::RwLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use parking_lot::{Mutex, RwLock};
 use thiserror::Error;
 #[cfg(feature = "sqlite")]
 use rusqlite::{params, Connection};
 #[cfg(not(target_arch = "wasm32"))]
 use std::thread;
 use regex::Regex;
+#[cfg(not(target_arch = "wasm32"))]
 use std::time::{SystemTime, UNIX_EPOCH};
 use secrecy::SecretString;
+#[cfg(feature = "sqlcipher")]
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Serialize};
+use chrono::Timelike;
 use wasm_bindgen::prelude::*;
 
 #[wasm_bindgen]
@@ -16,385 +23,8823 @@ pub fn init_panic_hook() {
     console_error_panic_hook::set_once();
 }
 
-#[cfg(not(target_arch = "wasm32"))]
+#[cfg(all(feature = "sqlite", not(target_arch = "wasm32")))]
 use std::sync::mpsc;
 
 uniffi::setup_scaffolding!();
 
+// Formats lazily so disabled levels cost one atomic load.
+macro_rules! engine_log {
+    ($level:expr, $target:expr, $($arg:tt)*) => {
+        if log_enabled($level) { emit_log($level, $target, format!($($arg)*)); }
+    };
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, PartialOrd)]
+pub enum LogLevel { Trace, Debug, Info, Warn, Error, Off }
+
+/// Receives engine log lines; install one to route them into the host's logger (Logcat, os_log, ...).
+#[uniffi::export(callback_interface)]
+pub trait LogHandler: Send + Sync {
+    fn on_log(&self, level: LogLevel, target: String, message: String);
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Warn as u8);
+static LOG_HANDLER: RwLock<Option<Box<dyn LogHandler>>> = RwLock::new(None);
+
+/// Process-wide; defaults to `Warn`.
+#[uniffi::export]
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Without a handler, logs go to stderr (or the browser console on wasm).
+#[uniffi::export]
+pub fn set_log_handler(handler: Box<dyn LogHandler>) {
+    *LOG_HANDLER.write() = Some(handler);
+}
+
+#[wasm_bindgen(js_name = setLogLevel)]
+pub fn set_log_level_js(level: u8) {
+    LOG_LEVEL.store(level.min(LogLevel::Off as u8), Ordering::Relaxed);
+}
+
+// Debug builds keep values readable for development; release demo builds redact by default.
+static REDACTION_ENABLED: AtomicBool = AtomicBool::new(!cfg!(debug_assertions));
+static SENSITIVE_VALUES: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Controls whether db paths, keys, verification codes and account ids are masked in logs and error strings.
+#[uniffi::export]
+pub fn set_redaction_enabled(enabled: bool) {
+    REDACTION_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Masks every registered sensitive value in `text`. Hosts can run their own log lines through it too.
+#[uniffi::export]
+pub fn redact_sensitive(text: String) -> String {
+    if !REDACTION_ENABLED.load(Ordering::Relaxed) { return text; }
+    SENSITIVE_VALUES.read().iter().fold(text, |text, value| text.replace(value.as_str(), "[redacted]"))
+}
+
+fn register_sensitive(value: &str) {
+    // Very short values would mask unrelated text (e.g. an account called "1").
+    if value.len() < 4 { return; }
+    let mut values = SENSITIVE_VALUES.write();
+    if !values.iter().any(|v| v == value) {
+        values.push(value.to_string());
+        // Longest first so a path containing an account id is masked as a whole.
+        values.sort_by_key(|v| std::cmp::Reverse(v.len()));
+    }
+}
+
+// For short-lived values such as a verification code once it's used up.
+fn unregister_sensitive(value: &str) {
+    SENSITIVE_VALUES.write().retain(|v| v != value);
+}
+
+#[cfg(feature = "sqlite")]
+fn db_err(e: rusqlite::Error) -> TelcoError {
+    use rusqlite::ErrorCode;
+    let failure = match e.sqlite_error_code() {
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => StorageFailure::Busy,
+        Some(ErrorCode::DatabaseCorrupt | ErrorCode::NotADatabase) => StorageFailure::Corrupt,
+        Some(ErrorCode::DiskFull) => StorageFailure::Full,
+        Some(ErrorCode::SystemIoFailure | ErrorCode::CannotOpen) => StorageFailure::Io,
+        Some(ErrorCode::ReadOnly) => StorageFailure::ReadOnly,
+        Some(ErrorCode::ConstraintViolation) => StorageFailure::Constraint,
+        _ => StorageFailure::Other,
+    };
+    TelcoError::DatabaseError { failure, message: redact_sensitive(e.to_string()) }
+}
+
+// File errors outside SQLite (exports, backups) surface as storage failures too.
+#[cfg(feature = "sqlite")]
+fn io_err(e: std::io::Error) -> TelcoError {
+    let failure = match e.kind() {
+        std::io::ErrorKind::StorageFull => StorageFailure::Full,
+        std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem => StorageFailure::ReadOnly,
+        _ => StorageFailure::Io,
+    };
+    TelcoError::DatabaseError { failure, message: redact_sensitive(e.to_string()) }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = console, js_name = log)]
+    fn console_log(s: &str);
+}
+
+fn log_enabled(level: LogLevel) -> bool {
+    level != LogLevel::Off && level as u8 >= LOG_LEVEL.load(Ordering::Relaxed)
+}
+
+fn emit_log(level: LogLevel, target: &str, message: String) {
+    let message = redact_sensitive(message);
+    if let Some(handler) = &*LOG_HANDLER.read() {
+        handler.on_log(level, target.to_string(), message);
+        return;
+    }
+    #[cfg(target_arch = "wasm32")]
+    console_log(&format!("[{:?} {}] {}", level, target, message));
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("[{:?} {}] {}", level, target, message);
+}
+
 #[derive(Debug, Error, uniffi::Error)]
 pub enum TelcoError {
     #[error("Insufficient balance for this transaction.")]
     InsufficientBalance,
+    #[error("Insufficient funds: {required_cents} needed, {available_cents} in the wallet.")]
+    InsufficientFunds { required_cents: u64, available_cents: u64 },
     #[error("Account is inactive.")]
     AccountInactive,
     #[error("Device is locked via biometrics.")]
     Locked,
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+    #[error("Flight mode is on.")]
+    FlightMode,
+    #[error("Not registered on the network ({0:?}).")]
+    NotRegistered(RegistrationState),
+    #[error("No data connection: {0}")]
+    NoDataConnection(String),
     #[error("Invalid command: {0}")]
     InvalidCommand(String),
-    #[error("Database error: {0}")]
-    DatabaseError(String),
-    #[error("Internal error")]
-    InternalError,
+    #[error("Database error: {message}")]
+    DatabaseError { failure: StorageFailure, message: String },
+    #[error("Database key rejected.")]
+    WrongDatabaseKey,
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    #[error("Purchase declined: {0}")]
+    PurchaseDeclined(String),
+    #[error("Monthly {cap:?} cap reached ({used} of {limit} used).")]
+    CapExceeded { cap: CapKind, limit: u64, used: u64 },
+    /// The background writer has shut down, so the database can't be reached from this simulator any more, or the
+    /// build has no `sqlite` feature.
+    #[error("Storage is unavailable.")]
+    StorageUnavailable,
+    /// A backup failed verification: missing manifest, checksum mismatch or another schema version.
+    #[error("Backup rejected: {0}")]
+    BackupRejected(String),
+    #[error("Serialization failed: {0}")]
+    SerializationFailed(String),
+    #[error("Internal error: {0}")]
+    InternalError(String),
+}
+
+// A host backend that threw something other than a `TelcoError`.
+impl From<uniffi::UnexpectedUniFFICallbackError> for TelcoError {
+    fn from(e: uniffi::UnexpectedUniFFICallbackError) -> Self {
+        TelcoError::InternalError(e.reason)
+    }
+}
+
+/// Why a database call failed, from SQLite's primary result code.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum StorageFailure { Busy, Corrupt, Full, Io, ReadOnly, Constraint, Other }
+
+/// Broad class of a `TelcoError`, for hosts that handle errors per class rather than per variant.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum ErrorKind {
+    /// The request itself was wrong; retrying it unchanged fails the same way.
+    Validation,
+    /// Not enough quota, allowance or money.
+    Balance,
+    /// The account or device is in a state that refuses the request (inactive, locked, flight mode).
+    AccountState,
+    /// No network service right now.
+    Connectivity,
+    RateLimit,
+    Storage,
+    Security,
+    Internal,
+}
+
+/// Machine-readable description of a `TelcoError`. `code` is stable across releases; `message` is for display only.
+#[derive(Clone, Debug, uniffi::Record, PartialEq, Serialize, Deserialize)]
+pub struct ErrorInfo {
+    pub kind: ErrorKind,
+    pub code: String,
+    /// Whether the same request can succeed later without the user doing anything.
+    pub retryable: bool,
+    pub message: String,
+    pub context: HashMap<String, String>,
+}
+
+impl TelcoError {
+    pub fn info(&self) -> ErrorInfo {
+        let mut context = HashMap::new();
+        let (kind, code, retryable) = match self {
+            TelcoError::InsufficientBalance => (ErrorKind::Balance, "insufficient_balance", false),
+            TelcoError::InsufficientFunds { required_cents, available_cents } => {
+                context.insert("required_cents".to_string(), required_cents.to_string());
+                context.insert("available_cents".to_string(), available_cents.to_string());
+                (ErrorKind::Balance, "insufficient_funds", false)
+            }
+            TelcoError::AccountInactive => (ErrorKind::AccountState, "account_inactive", false),
+            TelcoError::Locked => (ErrorKind::AccountState, "device_locked", false),
+            TelcoError::FlightMode => (ErrorKind::AccountState, "flight_mode", false),
+            TelcoError::RateLimited(reason) => {
+                context.insert("reason".to_string(), reason.clone());
+                (ErrorKind::RateLimit, "rate_limited", true)
+            }
+            TelcoError::NotRegistered(state) => {
+                context.insert("registration".to_string(), format!("{:?}", state));
+                // Denied registrations need the user (or operator) to act; searching resolves on its own.
+                (ErrorKind::Connectivity, "not_registered", *state != RegistrationState::Denied)
+            }
+            TelcoError::NoDataConnection(reason) => {
+                context.insert("reason".to_string(), reason.clone());
+                (ErrorKind::Connectivity, "no_data_connection", true)
+            }
+            TelcoError::InvalidCommand(detail) => {
+                context.insert("detail".to_string(), detail.clone());
+                (ErrorKind::Validation, "invalid_command", false)
+            }
+            TelcoError::DatabaseError { failure, .. } => {
+                context.insert("failure".to_string(), format!("{:?}", failure));
+                let code = match failure {
+                    StorageFailure::Busy => "db_busy",
+                    StorageFailure::Corrupt => "db_corrupt",
+                    StorageFailure::Full => "db_full",
+                    StorageFailure::Io => "db_io",
+                    StorageFailure::ReadOnly => "db_read_only",
+                    StorageFailure::Constraint => "db_constraint",
+                    StorageFailure::Other => "db_error",
+                };
+                (ErrorKind::Storage, code, matches!(failure, StorageFailure::Busy | StorageFailure::Io))
+            }
+            TelcoError::StorageUnavailable => (ErrorKind::Storage, "storage_unavailable", false),
+            TelcoError::WrongDatabaseKey => (ErrorKind::Security, "wrong_database_key", false),
+            TelcoError::PermissionDenied(detail) => {
+                context.insert("detail".to_string(), detail.clone());
+                (ErrorKind::Security, "permission_denied", false)
+            }
+            TelcoError::CapExceeded { cap, limit, used } => {
+                context.insert("cap".to_string(), format!("{:?}", cap));
+                context.insert("limit".to_string(), limit.to_string());
+                context.insert("used".to_string(), used.to_string());
+                (ErrorKind::Balance, "cap_exceeded", false)
+            }
+            TelcoError::PurchaseDeclined(reason) => {
+                context.insert("reason".to_string(), reason.clone());
+                (ErrorKind::Validation, "purchase_declined", false)
+            }
+            TelcoError::BackupRejected(detail) => {
+                context.insert("detail".to_string(), detail.clone());
+                (ErrorKind::Validation, "backup_rejected", false)
+            }
+            TelcoError::SerializationFailed(detail) => {
+                context.insert("detail".to_string(), detail.clone());
+                (ErrorKind::Internal, "serialization_failed", false)
+            }
+            TelcoError::InternalError(detail) => {
+                context.insert("detail".to_string(), detail.clone());
+                (ErrorKind::Internal, "internal", false)
+            }
+        };
+        ErrorInfo { kind, code: code.to_string(), retryable, message: self.to_string(), context }
+    }
+}
+
+/// Structured form of an error thrown by any simulator method.
+#[uniffi::export]
+pub fn describe_error(error: TelcoError) -> ErrorInfo {
+    error.info()
+}
+
+// Errors reach JavaScript as plain `ErrorInfo` objects rather than strings.
+impl From<TelcoError> for JsValue {
+    fn from(error: TelcoError) -> Self {
+        let info = error.info();
+        serde_json::to_string(&info).ok()
+            .and_then(|json| js_sys::JSON::parse(&json).ok())
+            .unwrap_or_else(|| JsValue::from_str(&info.message))
+    }
+}
+
+/// `Voice` buckets count seconds and `Sms` buckets count messages; every other kind counts bytes.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum QuotaType { General, Social, Video, Voice, Sms }
+
+impl QuotaType {
+    fn from_name(s: &str) -> Option<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "general" => Some(QuotaType::General),
+            "social" => Some(QuotaType::Social),
+            "video" => Some(QuotaType::Video),
+            "voice" => Some(QuotaType::Voice),
+            "sms" => Some(QuotaType::Sms),
+            _ => None,
+        }
+    }
+
+    fn is_data(&self) -> bool {
+        !matches!(self, QuotaType::Voice | QuotaType::Sms)
+    }
 }
 
+/// Where a bucket's bytes came from. Short-lived tiers are drained before purchased data.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum BucketSource { Purchase, Rollover, Promo, Roaming }
+
+impl BucketSource {
+    fn tier(&self) -> u8 {
+        match self { BucketSource::Promo | BucketSource::Roaming => 0, BucketSource::Rollover => 1, BucketSource::Purchase => 2 }
+    }
+
+    fn lifetime_secs(&self) -> u64 {
+        match self {
+            BucketSource::Roaming => 86400,
+            BucketSource::Promo => 86400 * 7,
+            BucketSource::Rollover | BucketSource::Purchase => BILLING_CYCLE_SECS,
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn parse(s: &str) -> Self {
+        match s { "Rollover" => BucketSource::Rollover, "Promo" => BucketSource::Promo, "Roaming" => BucketSource::Roaming, _ => BucketSource::Purchase }
+    }
+}
+
+const BILLING_CYCLE_SECS: u64 = 86400 * 30;
+
+/// Who sent a command through `dispatch_external_command`; decides which commands it may run.
 #[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
-pub enum QuotaType { General, Social, Video }
+pub enum CommandSource { Operator, User, TestHarness }
 
-#[derive(Clone, Debug, uniffi::Record)]
-pub struct QuotaBucket {
-    pub name: String,
-    pub remaining_bytes: u64,
-    pub category: QuotaType,
-    pub expiry: u64,
+impl CommandSource {
+    #[cfg(feature = "sqlite")]
+    fn parse(s: &str) -> Self {
+        match s { "Operator" => CommandSource::Operator, "TestHarness" => CommandSource::TestHarness, _ => CommandSource::User }
+    }
 }
 
 #[derive(Clone, Debug, uniffi::Record)]
-pub struct UserAccount {
-    pub id: String,
-    pub is_active: bool,
-    pub biometric_locked: bool,
-    pub buckets: Vec<QuotaBucket>,
-    pub last_traffic_bytes: u64,
-    pub data_balance_bytes: u64,
-    pub current_latency_ms: u32,
+pub struct CommandAuditEntry {
+    pub timestamp: u64,
+    pub source: CommandSource,
+    /// Command name only; arguments can carry tokens and are not recorded.
+    pub command: String,
+    pub allowed: bool,
+    /// Why an allowed command failed.
+    pub error: Option<String>,
 }
 
+/// A cancelled bucket, restorable with `restore_bucket` until the trash retention window passes.
 #[derive(Clone, Debug, uniffi::Record)]
-pub struct UsageRecord {
-    pub timestamp: u64,
-    pub amount: u64,
-    pub category: String,
+pub struct TrashedBucket {
+    pub bucket: QuotaBucket,
+    pub deleted_at: u64,
 }
 
-#[uniffi::export(callback_interface)]
-pub trait TelcoLiveUpdateHandler: Send + Sync {
-    fn on_account_updated(&self, account: UserAccount);
+const DEFAULT_TRASH_RETENTION_SECS: u64 = 86400 * 7;
+
+/// A bucket `process_expirations` took off the account, as it was when it expired; see `get_expired_buckets`.
+#[derive(Clone, Debug, uniffi::Record, Serialize, Deserialize)]
+pub struct ExpiredBucket {
+    pub bucket: QuotaBucket,
+    pub archived_at: u64,
 }
 
-#[cfg(feature = "sqlite")]
-struct PersistenceMsg {
-    account: UserAccount,
-    usage: Option<(u64, QuotaType, u64)>,
+/// A data gift, encoded in `token` for a QR code; see `create_share_token`.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ShareToken {
+    pub token: String,
+    pub bytes: u64,
+    pub category: QuotaType,
+    pub expires_at: u64,
 }
 
-#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
-#[derive(uniffi::Object)]
-pub struct TelcoSimulator {
-    state: Arc<RwLock<UserAccount>>,
-    db_path: String,
-    db_key: Arc<RwLock<Option<SecretString>>>,
-    update_handler: RwLock<Option<Box<dyn TelcoLiveUpdateHandler>>>,
-    #[cfg(feature = "sqlite")]
-    persistence_tx: mpsc::SyncSender<PersistenceMsg>,
+#[derive(Serialize, Deserialize)]
+struct SharePayload {
+    nonce: String,
+    from: String,
+    bytes: u64,
+    category: QuotaType,
+    expires_at: u64,
+    /// Only this account may redeem the token; anyone may when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    to: Option<String>,
 }
 
+const SHARE_TOKEN_PREFIX: &str = "FER1";
+const GIFT_COMMAND_TTL_SECS: u64 = 7 * 86400;
+
+// Shared by every device that redeems tokens; hosts talking to a real backend should set their own.
+static SHARE_SECRET: RwLock<Option<String>> = RwLock::new(None);
+const DEMO_SHARE_SECRET: &str = "ferrum-demo-share-secret";
+
+/// Key used to sign and verify share tokens. Every device in the demo must use the same one.
 #[uniffi::export]
-impl TelcoSimulator {
-    #[uniffi::constructor]
-    pub fn new(id: String, db_path: String) -> Result<Arc<Self>, TelcoError> {
-        #[cfg(feature = "sqlite")]
-        let account = {
-            let conn = Connection::open(&db_path).map_err(|e| TelcoError::DatabaseError(e.to_string()))?;
-            conn.execute_batch(
-                "CREATE TABLE IF NOT EXISTS accounts (id TEXT PRIMARY KEY, is_active BOOLEAN, locked BOOLEAN, last_traffic INTEGER);
-                 CREATE TABLE IF NOT EXISTS buckets (id INTEGER PRIMARY KEY, account_id TEXT, name TEXT, remaining_bytes INTEGER, category TEXT, expiry INTEGER);
-                 CREATE TABLE IF NOT EXISTS usage_history (timestamp INTEGER, amount INTEGER, category TEXT);"
-            ).map_err(|e| TelcoError::DatabaseError(e.to_string()))?;
-
-            load_account_internal(&conn, &id).unwrap_or_else(|_| {
-                UserAccount { 
-                    id: id.clone(), 
-                    is_active: true, 
-                    biometric_locked: false, 
-                    buckets: vec![], 
-                    last_traffic_bytes: 0,
-                    data_balance_bytes: 0,
-                    current_latency_ms: 46,
-                }
-            })
-        };
+pub fn set_share_secret(secret: String) {
+    register_sensitive(&secret);
+    *SHARE_SECRET.write() = Some(secret);
+}
 
-        #[cfg(not(feature = "sqlite"))]
-        let account = UserAccount { 
-            id: id.clone(), 
-            is_active: true, 
-            biometric_locked: false, 
-            buckets: vec![], 
-            last_traffic_bytes: 0,
-            data_balance_bytes: 0,
-            current_latency_ms: 46,
-        };
+fn share_signature(payload: &[u8]) -> [u8; 32] {
+    let secret = SHARE_SECRET.read();
+    hmac_sha256(secret.as_deref().unwrap_or(DEMO_SHARE_SECRET).as_bytes(), payload)
+}
 
-        #[cfg(feature = "sqlite")]
-        let tx = {
-            let (tx, rx) = mpsc::sync_channel::<PersistenceMsg>(1000);
-            let db_path_clone = db_path.clone();
-            thread::spawn(move || {
-                if let Ok(mut conn) = Connection::open(db_path_clone) {
-                    while let Ok(msg) = rx.recv() {
-                        if let Some((bytes, category, now)) = msg.usage {
-                            let _ = conn.execute("INSERT INTO usage_history (timestamp, amount, category) VALUES (?1, ?2, ?3)",
-                                params![now, bytes, format!("{:?}", category)]);
-                        }
-                        if let Ok(tx) = conn.transaction() {
-                            let _ = tx.execute("INSERT OR REPLACE INTO accounts (id, is_active, locked, last_traffic) VALUES (?1, ?2, ?3, ?4)", 
-                                params![msg.account.id, msg.account.is_active, msg.account.biometric_locked, msg.account.last_traffic_bytes]);
-                            let _ = tx.execute("DELETE FROM buckets WHERE account_id = ?1", params![msg.account.id]);
-                            for b in msg.account.buckets {
-                                let _ = tx.execute(
-                                    "INSERT INTO buckets (account_id, name, remaining_bytes, category, expiry) VALUES (?1, ?2, ?3, ?4, ?5)",
-                                    params![msg.account.id, b.name, b.remaining_bytes, format!("{:?}", b.category), b.expiry]
-                                );
-                            }
-                            let _ = tx.commit();
-                        }
-                    }
-                }
-            });
-            tx
-        };
+/// One member's share of a family pool.
+#[derive(Clone, Debug, uniffi::Record, Serialize, Deserialize)]
+pub struct FamilyMemberUsage {
+    pub account_id: String,
+    /// Most the member may draw over the pool's lifetime; `None` for no limit.
+    pub cap_bytes: Option<u64>,
+    pub used_bytes: u64,
+}
 
-        Ok(Arc::new(Self { 
-            state: Arc::new(RwLock::new(account)), 
-            db_path,
-            db_key: Arc::new(RwLock::new(None)),
-            update_handler: RwLock::new(None),
-            #[cfg(feature = "sqlite")]
-            persistence_tx: tx,
-        }))
-    }
+/// Data bought by a primary account and drawn on by secondary accounts in the same database once their own
+/// buckets run dry. It is not part of any member's `data_balance_bytes`.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct FamilyPoolInfo {
+    pub id: String,
+    pub owner_id: String,
+    pub name: String,
+    pub remaining_bytes: u64,
+    pub category: QuotaType,
+    pub expiry: u64,
+    /// Every member for the owner; just the caller for anyone else.
+    pub members: Vec<FamilyMemberUsage>,
+}
 
-    pub fn set_update_handler(&self, handler: Box<dyn TelcoLiveUpdateHandler>) {
-        let mut lock = self.update_handler.write();
-        *lock = Some(handler);
-        let account = self.state.read().clone();
-        if let Some(h) = &*lock { h.on_account_updated(account); }
+#[derive(Clone, Serialize, Deserialize)]
+struct FamilyPool {
+    id: String,
+    owner_id: String,
+    name: String,
+    remaining_bytes: u64,
+    category: QuotaType,
+    expiry: u64,
+    members: Vec<FamilyMemberUsage>,
+    // Bumped on every change. Members persist through their own writers, so the stored copy only moves forward.
+    version: u64,
+}
+
+type SharedFamilyPool = Arc<Mutex<FamilyPool>>;
+
+impl FamilyPool {
+    fn member_mut(&mut self, account_id: &str) -> Result<&mut FamilyMemberUsage, TelcoError> {
+        self.members.iter_mut().find(|m| m.account_id == account_id)
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("'{}' is not in the family pool", account_id)))
     }
 
-    pub fn unlock_with_biometrics(&self) {
-        let mut lock = self.state.write();
-        lock.biometric_locked = false;
-        let account = lock.clone();
-        drop(lock);
-        self.notify_and_persist(account, None);
+    fn available_to(&self, account_id: &str, category: QuotaType, now: u64) -> u64 {
+        let payable = self.category == category || (self.category == QuotaType::General && category.is_data());
+        if !payable || self.expiry <= now { return 0; }
+        let Some(member) = self.members.iter().find(|m| m.account_id == account_id) else { return 0; };
+        member.cap_bytes.map_or(self.remaining_bytes, |cap| cap.saturating_sub(member.used_bytes).min(self.remaining_bytes))
     }
 
-    pub fn secure_initialize(&self, key: String) {
-        let mut lock = self.db_key.write();
-        *lock = Some(SecretString::from(key));
+    fn draw(&mut self, account_id: &str, bytes: u64) {
+        self.remaining_bytes -= bytes;
+        if let Ok(member) = self.member_mut(account_id) { member.used_bytes += bytes; }
+        self.version += 1;
     }
 
-    pub fn get_account_info(&self) -> Result<UserAccount, TelcoError> {
-        let state = self.state.read();
-        if state.biometric_locked { return Err(TelcoError::Locked); }
-        Ok(state.clone())
+    // The row written for this version; a dissolved pool keeps its row with no payload.
+    #[cfg(feature = "sqlite")]
+    fn persistence_msg(&self, dissolved: bool) -> PersistenceMsg {
+        let payload = (!dissolved).then(|| serde_json::to_string(self).ok()).flatten();
+        PersistenceMsg::FamilyPool { pool_id: self.id.clone(), payload, version: self.version }
     }
 
-    pub fn handle_command(&self, command: String) -> String {
-        if self.state.read().biometric_locked { return "Unlock required.".to_string(); }
-        let cmd = command.trim().to_lowercase();
-        if cmd == "status" { return self.generate_insight(); }
-        match self.parse_and_buy_topping(command) {
-            Ok(_) => "Liquid Bubble growing...".to_string(),
-            Err(e) => format!("Error: {}", e),
+    fn info(&self, viewer: &str) -> FamilyPoolInfo {
+        FamilyPoolInfo {
+            id: self.id.clone(),
+            owner_id: self.owner_id.clone(),
+            name: self.name.clone(),
+            remaining_bytes: self.remaining_bytes,
+            category: self.category,
+            expiry: self.expiry,
+            members: self.members.iter().filter(|m| viewer == self.owner_id || m.account_id == viewer).cloned().collect(),
         }
     }
+}
 
-    pub fn simulate_usage(&self, bytes: u64, category: QuotaType) -> Result<(), TelcoError> {
-        let mut lock = self.state.write();
-        if lock.biometric_locked { return Err(TelcoError::Locked); }
-        
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let new_state = (*lock).consume_data(bytes, category)?;
-        *lock = new_state;
-        
-        let account = lock.clone();
-        drop(lock);
-        
-        self.notify_and_persist(account, Some((bytes, category, now)));
-        Ok(())
-    }
+// Family pools per database path, loaded on first use, so every simulator of a member sees the same live pool.
+static FAMILY_POOLS: Mutex<Vec<(String, Vec<SharedFamilyPool>)>> = Mutex::new(Vec::new());
 
-    // Insight Logic
-    fn generate_insight(&self) -> String {
-        let total = self.state.read().data_balance_bytes;
-        
-        #[cfg(feature = "sqlite")]
-        {
-            let daily_avg = self.calculate_daily_average().unwrap_or(0);
-            let mut insight = format!("You have {:.2} GB remaining.", total as f64 / 1e9);
-            if daily_avg > 0 {
-                let days_left = total / daily_avg;
-                insight += &format!(" Based on last 7 days, you have roughly {} days of usage left.", days_left);
-                if days_left < 3 {
-                    insight += " Recommendation: Top up soon to avoid interruption.";
-                }
-            } else {
-                insight += " Start using data to see personalized forecasting.";
-            }
-            insight
-        }
+// Takes a deleted account out of `pools`: a pool it owned is dissolved, any other just loses the member. Returns the
+// changed pools, each with whether it was dissolved.
+fn leave_family_pools(pools: &mut Vec<SharedFamilyPool>, account_id: &str) -> Vec<(FamilyPool, bool)> {
+    let mut changed = Vec::new();
+    pools.retain(|p| {
+        let mut pool = p.lock();
+        if !pool.members.iter().any(|m| m.account_id == account_id) { return true; }
+        pool.version += 1;
+        let dissolved = pool.owner_id == account_id;
+        if !dissolved { pool.members.retain(|m| m.account_id != account_id); }
+        changed.push((pool.clone(), dissolved));
+        !dissolved
+    });
+    changed
+}
 
-        #[cfg(not(feature = "sqlite"))]
-        {
-            format!("You have {:.2} GB remaining. (In-Memory Mode)", total as f64 / 1e9)
+/// A named restore point; see `create_snapshot`.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct AccountSnapshot {
+    pub name: String,
+    pub created_at: u64,
+    pub bucket_count: u32,
+    pub balance_bytes: u64,
+}
+
+// Everything a restore puts back. Usage history is rewound separately, by row, on the writer thread.
+#[derive(Clone, Serialize, Deserialize)]
+struct SnapshotState {
+    account: UserAccount,
+    pending_orders: Vec<ScheduledOrder>,
+    ad_rewards: Vec<(u64, u64)>,
+    achievements: Vec<AchievementRecord>,
+}
+
+/// Written next to a backup as `<path>.manifest.json` and checked before it is restored.
+#[derive(Clone, Debug, uniffi::Record, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub schema_version: u32,
+    pub created_at: u64,
+    pub size_bytes: u64,
+    /// Hex SHA-256 of the backup file.
+    pub sha256: String,
+    pub account_ids: Vec<String>,
+}
+
+/// Bumped when `export_account_json` output changes incompatibly.
+pub const ACCOUNT_EXPORT_VERSION: u32 = 1;
+
+// Payload of `export_account_json`: the account with its buckets, and its usage history oldest first.
+#[derive(Serialize, Deserialize)]
+struct AccountExport {
+    version: u32,
+    exported_at: u64,
+    account: UserAccount,
+    usage: Vec<UsageEntry>,
+}
+
+#[derive(Clone)]
+struct StoredSnapshot {
+    info: AccountSnapshot,
+    state: SnapshotState,
+}
+
+/// A purchase waiting for its activation time. `purchase` is a catalog plan name or a topping command such as "Social 2GB".
+#[derive(Clone, Debug, uniffi::Record, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledOrder {
+    pub id: String,
+    pub purchase: String,
+    pub activate_at: u64,
+    pub created_at: u64,
+}
+
+/// A purchase that buys itself again whenever its bucket expires; see `subscribe`.
+#[derive(Clone, Debug, uniffi::Record, PartialEq, Serialize, Deserialize)]
+pub struct Subscription {
+    pub id: String,
+    pub purchase: String,
+    /// The bucket bought by the latest renewal.
+    pub bucket_id: String,
+    pub next_renewal_at: u64,
+    pub renewals: u32,
+    pub created_at: u64,
+}
+
+/// Order buckets are listed in on account snapshots. Draining order is set separately, by `ConsumptionPolicy`.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum BucketSortOrder { Insertion, Expiry, Size, Category }
+
+impl BucketSortOrder {
+    fn apply(&self, buckets: &mut [QuotaBucket]) {
+        match self {
+            BucketSortOrder::Insertion => {}
+            BucketSortOrder::Expiry => buckets.sort_by_key(|b| b.expiry),
+            BucketSortOrder::Size => buckets.sort_by_key(|b| std::cmp::Reverse(b.remaining_bytes)),
+            BucketSortOrder::Category => buckets.sort_by_key(|b| (b.category as u8, b.expiry)),
         }
     }
+}
 
-    fn calculate_daily_average(&self) -> Result<u64, TelcoError> {
-        #[cfg(feature = "sqlite")]
-        {
-            let conn = Connection::open(&self.db_path).map_err(|e| TelcoError::DatabaseError(e.to_string()))?;
-            let seven_days_ago = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() - (7 * 24 * 60 * 60);
-            
-            let mut stmt = conn.prepare("SELECT SUM(amount) FROM usage_history WHERE timestamp > ?1").map_err(|e| TelcoError::DatabaseError(e.to_string()))?;
-            let total_usage: u64 = stmt.query_row(params![seven_days_ago], |row| row.get(0)).unwrap_or(0);
-            
-            Ok(total_usage / 7)
-        }
-        #[cfg(not(feature = "sqlite"))]
-        {
-            Ok(0)
+#[derive(Clone, Copy, Debug, uniffi::Record, PartialEq)]
+pub struct CategoryWeight {
+    pub category: QuotaType,
+    pub weight: u32,
+}
+
+/// Order usage is deducted from the buckets that can pay for it (its own category, then General).
+#[derive(Clone, Debug, uniffi::Enum, PartialEq, Default)]
+pub enum ConsumptionPolicy {
+    /// Promo, then rollover, then purchased buckets; nearest expiry first within each. The category's own
+    /// buckets are always drained before General ones.
+    #[default]
+    Tiered,
+    NearestExpiryFirst,
+    LargestFirst,
+    SmallestFirst,
+    InsertionOrder,
+    /// Heaviest category first across all payable buckets, nearest expiry within one; unlisted categories weigh 0.
+    Weighted { weights: Vec<CategoryWeight> },
+}
+
+impl ConsumptionPolicy {
+    // Indices of the buckets that can pay for `category`, in draining order. Roaming passes only pay while roaming,
+    // and then before anything else.
+    fn order(&self, buckets: &[QuotaBucket], category: QuotaType, now: u64, roaming: bool) -> Vec<usize> {
+        // General data covers any data category, but never calls or texts.
+        let payable = |c: QuotaType| c == category || (c == QuotaType::General && category.is_data());
+        let minute = local_minute(now);
+        let usable = |b: &QuotaBucket| b.expiry > now && b.active_window.is_none_or(|w| w.contains(minute)) && (roaming || b.source != BucketSource::Roaming);
+        let mut order: Vec<usize> = (0..buckets.len()).filter(|&i| payable(buckets[i].category) && usable(&buckets[i])).collect();
+        let own_first = |i: usize| buckets[i].category != category;
+        match self {
+            ConsumptionPolicy::Tiered => order.sort_by_key(|&i| (own_first(i), buckets[i].source.tier(), buckets[i].expiry)),
+            ConsumptionPolicy::NearestExpiryFirst => order.sort_by_key(|&i| (own_first(i), buckets[i].expiry)),
+            ConsumptionPolicy::LargestFirst => order.sort_by_key(|&i| (own_first(i), std::cmp::Reverse(buckets[i].remaining_bytes))),
+            ConsumptionPolicy::SmallestFirst => order.sort_by_key(|&i| (own_first(i), buckets[i].remaining_bytes)),
+            ConsumptionPolicy::InsertionOrder => order.sort_by_key(|&i| own_first(i)),
+            ConsumptionPolicy::Weighted { weights } => {
+                let weight = |c: QuotaType| weights.iter().find(|w| w.category == c).map_or(0, |w| w.weight);
+                order.sort_by_key(|&i| (std::cmp::Reverse(weight(buckets[i].category)), buckets[i].expiry));
+            }
         }
+        if roaming { order.sort_by_key(|&i| buckets[i].source != BucketSource::Roaming); }
+        order
     }
+}
 
-    fn parse_and_buy_topping(&self, command: String) -> Result<(), TelcoError> {
-        let re = Regex::new(r"(?i)(YouTube|Social|General)\s+(\d+)\s*(GB|MB)").unwrap();
-        if let Some(caps) = re.captures(&command) {
-            let cat_str = caps.get(1).unwrap().as_str().to_lowercase();
-            let amount: u64 = caps.get(2).unwrap().as_str().parse().unwrap();
-            let unit = caps.get(3).unwrap().as_str().to_uppercase();
-            let bytes = if unit == "GB" { amount * 1024 * 1024 * 1024 } else { amount * 1024 * 1024 };
-            let category = match cat_str.as_str() { "youtube" => QuotaType::Video, "social" => QuotaType::Social, _ => QuotaType::General };
-            let topping = QuotaBucket {
-                name: format!("{} {} Topping", amount, unit),
-                remaining_bytes: bytes,
-                category,
-                expiry: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() + 86400 * 30,
-            };
-            let mut lock = self.state.write();
-            lock.buckets.push(topping);
-            lock.data_balance_bytes = lock.buckets.iter().map(|b| b.remaining_bytes).sum();
-            let account = lock.clone();
-            drop(lock);
-            self.notify_and_persist(account, None);
+#[derive(Clone, Debug, uniffi::Record, PartialEq, Serialize, Deserialize)]
+pub struct QuotaBucket {
+    /// Stable across restarts; use it to target a specific bucket.
+    #[serde(default = "new_id")]
+    pub id: String,
+    pub name: String,
+    /// Seconds for `Voice` buckets, messages for `Sms` ones.
+    pub remaining_bytes: u64,
+    /// Size the bucket was issued with, in the same unit; quota notifications are measured against it. Zero when
+    /// unknown, e.g. for buckets stored before it was recorded.
+    #[serde(default)]
+    pub initial_bytes: u64,
+    pub category: QuotaType,
+    pub expiry: u64,
+    pub source: BucketSource,
+    /// Local time of day the bucket can be drawn from, e.g. a night pack; usable around the clock when `None`.
+    #[serde(default)]
+    pub active_window: Option<ValidityWindow>,
+    /// Set on unlimited buckets, which never run dry; `remaining_bytes` is then the full-speed headroom left this cycle.
+    #[serde(default)]
+    pub fair_usage: Option<FairUsage>,
+}
+
+/// Fair-usage policy (FUP) of an unlimited bucket: past `threshold_bytes` in a billing cycle the bucket only
+/// carries traffic nothing else can, and the account is throttled until the next cycle.
+#[derive(Clone, Copy, Debug, uniffi::Record, PartialEq, Serialize, Deserialize)]
+pub struct FairUsage {
+    pub threshold_bytes: u64,
+    pub used_bytes: u64,
+    pub period_started_at: u64,
+}
+
+impl FairUsage {
+    fn new(threshold_bytes: u64, now: u64) -> Self {
+        Self { threshold_bytes, used_bytes: 0, period_started_at: now }
+    }
+
+    fn roll_period(&mut self, now: u64) {
+        let elapsed = now.saturating_sub(self.period_started_at) / BILLING_CYCLE_SECS;
+        if elapsed == 0 { return; }
+        self.period_started_at += elapsed * BILLING_CYCLE_SECS;
+        self.used_bytes = 0;
+    }
+
+    fn exceeded(&self) -> bool {
+        self.used_bytes >= self.threshold_bytes
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn from_columns(threshold: Option<u64>, used: Option<u64>, started: Option<u64>) -> Option<Self> {
+        Some(Self { threshold_bytes: threshold?, used_bytes: used.unwrap_or(0), period_started_at: started.unwrap_or(0) })
+    }
+}
+
+/// Minutes since local midnight, end exclusive. Windows may wrap past midnight (e.g. 23:00-06:00).
+#[derive(Clone, Copy, Debug, uniffi::Record, PartialEq, Serialize, Deserialize)]
+pub struct ValidityWindow {
+    pub start_minute: u32,
+    pub end_minute: u32,
+}
+
+impl ValidityWindow {
+    fn contains(&self, minute: u32) -> bool {
+        QuietHours { start_minute: self.start_minute, end_minute: self.end_minute }.contains(minute)
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn from_columns(start: Option<u32>, end: Option<u32>) -> Option<Self> {
+        Some(Self { start_minute: start?, end_minute: end? })
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record, Serialize, Deserialize)]
+pub struct UserAccount {
+    pub id: String,
+    pub is_active: bool,
+    pub biometric_locked: bool,
+    pub buckets: Vec<QuotaBucket>,
+    pub last_traffic_bytes: u64,
+    pub data_balance_bytes: u64,
+    #[serde(default)]
+    pub voice_balance_secs: u64,
+    #[serde(default)]
+    pub sms_balance: u64,
+    pub current_latency_ms: u32,
+    pub registration: RegistrationState,
+    /// Set while usage is being carried by the grace policy after the buckets ran dry.
+    #[serde(default)]
+    pub grace: Option<GraceState>,
+    /// Set once buckets and grace are exhausted and the throttle policy keeps data flowing at reduced speed.
+    #[serde(default)]
+    pub throttled: bool,
+    /// Money available for priced toppings, in the operator's currency.
+    #[serde(default)]
+    pub wallet_cents: u64,
+    #[serde(default = "default_signal_dbm")]
+    pub signal_dbm: i32,
+    /// 0-4, derived from `signal_dbm`. 0 means no coverage.
+    #[serde(default = "default_signal_bars")]
+    pub signal_bars: u8,
+}
+
+const DEFAULT_SIGNAL_DBM: i32 = -80;
+// The random walk stays inside coverage; only `set_signal_strength` takes the line below it.
+const SIGNAL_WALK_RANGE: (i32, i32) = (-115, -60);
+const SIGNAL_WALK_STEP_DB: u64 = 3;
+
+fn default_signal_dbm() -> i32 { DEFAULT_SIGNAL_DBM }
+
+fn default_signal_bars() -> u8 { signal_bars(DEFAULT_SIGNAL_DBM) }
+
+fn signal_bars(dbm: i32) -> u8 {
+    match dbm {
+        d if d >= -85 => 4,
+        d if d >= -95 => 3,
+        d if d >= -105 => 2,
+        d if d >= -120 => 1,
+        _ => 0,
+    }
+}
+
+/// How `tick` moves the signal: a bounded random walk, or held where `set_signal_strength` (or a scenario) put it.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum SignalMode { RandomWalk, Fixed }
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct SignalReading {
+    pub dbm: i32,
+    pub bars: u8,
+    pub generation: NetworkGeneration,
+}
+
+/// For live signal gauges. Called on every change of `signal_dbm`, and once with the current reading when set.
+#[uniffi::export(callback_interface)]
+pub trait SignalHandler: Send + Sync {
+    fn on_signal(&self, reading: SignalReading);
+}
+
+#[derive(Clone, Debug, uniffi::Record, PartialEq, Serialize, Deserialize)]
+pub struct GraceState {
+    pub used_bytes: u64,
+    pub allowance_bytes: u64,
+    pub started_at: u64,
+}
+
+/// What happens once the buckets can't cover usage. The default (no allowance, nothing zero-rated) fails hard.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct GracePolicy {
+    /// Bytes of any category allowed on credit until the next top-up.
+    pub allowance_bytes: u64,
+    /// Latency applied while in grace, to mimic a throttled connection.
+    pub throttled_latency_ms: u32,
+    /// Categories that stay usable for free while in grace.
+    pub zero_rated: Vec<QuotaType>,
+}
+
+impl Default for GracePolicy {
+    fn default() -> Self {
+        Self { allowance_bytes: 0, throttled_latency_ms: 600, zero_rated: vec![] }
+    }
+}
+
+/// Drops the line to a trickle instead of failing usage once buckets and grace run out, like a real plan's
+/// 128 kbps fallback. Disabled by default.
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct ThrottlePolicy {
+    pub enabled: bool,
+    pub speed_kbps: u32,
+    pub latency_ms: u32,
+}
+
+impl Default for ThrottlePolicy {
+    fn default() -> Self {
+        Self { enabled: false, speed_kbps: 128, latency_ms: 600 }
+    }
+}
+
+/// What happens to unused bytes when a bucket expires or its plan is renewed. The default carries nothing.
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct RolloverPolicy {
+    /// Most bytes carried by one rollover, across all retiring buckets.
+    pub max_bytes: u64,
+    /// Lifetime of the "Rollover" buckets created.
+    pub validity_days: u32,
+}
+
+impl Default for RolloverPolicy {
+    fn default() -> Self {
+        Self { max_bytes: 0, validity_days: 30 }
+    }
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum RegistrationState { Searching, Registered, Roaming, EmergencyOnly, Denied, Off }
+
+impl RegistrationState {
+    #[cfg(feature = "sqlite")]
+    fn parse(s: &str) -> Self {
+        match s {
+            "Searching" => RegistrationState::Searching,
+            "Roaming" => RegistrationState::Roaming,
+            "EmergencyOnly" => RegistrationState::EmergencyOnly,
+            "Denied" => RegistrationState::Denied,
+            "Off" => RegistrationState::Off,
+            _ => RegistrationState::Registered,
+        }
+    }
+
+    // Every state can drop back to Searching; leaving Denied requires a fresh search.
+    // Off is entered and left only through flight mode.
+    fn can_transition_to(&self, next: RegistrationState) -> bool {
+        use RegistrationState::*;
+        match (self, next) {
+            (a, b) if *a == b => false,
+            (Off, _) | (_, Off) => false,
+            (_, Searching) => true,
+            (Searching, _) => true,
+            (Registered, Roaming) | (Roaming, Registered) => true,
+            (Registered | Roaming, EmergencyOnly) => true,
+            (EmergencyOnly, Registered | Roaming) => true,
+            _ => false,
+        }
+    }
+
+    fn allows_data(&self) -> bool {
+        matches!(self, RegistrationState::Registered | RegistrationState::Roaming)
+    }
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum UsageSource {
+    Manual,
+    Sensor,
+    Import,
+    Vpn,
+    /// A day's total for one category, left behind by `prune_history`. Timestamped at the start of the UTC day.
+    Rollup,
+}
+
+impl UsageSource {
+    #[cfg(feature = "sqlite")]
+    fn parse(s: &str) -> Self {
+        match s {
+            "Sensor" => UsageSource::Sensor,
+            "Import" => UsageSource::Import,
+            "Vpn" => UsageSource::Vpn,
+            "Rollup" => UsageSource::Rollup,
+            _ => UsageSource::Manual,
+        }
+    }
+}
+
+/// How the sensor treats tunnel interfaces, whose traffic also crosses the underlying physical interface.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum VpnPolicy {
+    /// Count physical interfaces only; tunnel counters are ignored.
+    CountOnce,
+    /// Record tunnel bytes with `UsageSource::Vpn` and subtract them from the physical total.
+    TagAsVpn,
+    /// Count both (double-attributes tunnelled traffic).
+    CountBoth,
+}
+
+impl VpnPolicy {
+    fn attribute(&self, physical: u64, tunnel: u64) -> Vec<(u64, UsageSource)> {
+        match self {
+            VpnPolicy::CountOnce => vec![(physical, UsageSource::Sensor)],
+            VpnPolicy::TagAsVpn => vec![(physical.saturating_sub(tunnel), UsageSource::Sensor), (tunnel, UsageSource::Vpn)],
+            VpnPolicy::CountBoth => vec![(physical, UsageSource::Sensor), (tunnel, UsageSource::Sensor)],
+        }
+    }
+}
+
+/// Which interfaces the network sensor counts; see `set_sensor_interfaces`.
+#[derive(Clone, Debug, uniffi::Enum, PartialEq)]
+pub enum InterfaceSelection {
+    /// Every interface except loopback and container/VM bridges.
+    Auto,
+    /// Interfaces matching any of `patterns`: exact names or globs with `*` and `?` (e.g. "wl*", "eth?").
+    Matching { patterns: Vec<String> },
+}
+
+// An `InterfaceSelection` with its globs compiled once, since the sensor checks every interface on each sample.
+#[derive(Clone)]
+struct InterfaceFilter {
+    selection: InterfaceSelection,
+    globs: Option<Regex>,
+}
+
+impl InterfaceFilter {
+    fn new(selection: InterfaceSelection) -> Self {
+        let globs = match &selection {
+            InterfaceSelection::Auto => None,
+            InterfaceSelection::Matching { patterns } => {
+                let alternatives: Vec<String> = patterns.iter()
+                    .map(|p| regex::escape(p.trim()).replace(r"\*", ".*").replace(r"\?", "."))
+                    .collect();
+                Regex::new(&format!("^(?:{})$", alternatives.join("|"))).ok()
+            }
+        };
+        Self { selection, globs }
+    }
+
+    fn selects(&self, name: &str) -> bool {
+        match &self.selection {
+            InterfaceSelection::Auto => !is_virtual_interface(name),
+            InterfaceSelection::Matching { patterns } => !patterns.is_empty() && self.globs.as_ref().is_some_and(|re| re.is_match(name)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct NetworkInterface {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    /// Counted as VPN traffic under `VpnPolicy`.
+    pub tunnel: bool,
+    /// Counted by the sensor under the current `InterfaceSelection`.
+    pub selected: bool,
+}
+
+/// Maps a destination domain (from DNS or TLS SNI) to a quota category. `pattern` matches the domain itself
+/// and any subdomain; the highest priority match wins.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct CategoryRule {
+    pub pattern: String,
+    pub category: QuotaType,
+    pub priority: i32,
+}
+
+impl CategoryRule {
+    fn matches(&self, domain: &str) -> bool {
+        let pattern = self.pattern.trim_start_matches("*.").to_lowercase();
+        domain == pattern || domain.ends_with(&format!(".{}", pattern))
+    }
+}
+
+fn default_category_rules() -> Vec<CategoryRule> {
+    let video = ["youtube.com", "googlevideo.com", "ytimg.com", "netflix.com", "nflxvideo.net", "twitch.tv", "tiktokv.com", "vimeo.com"];
+    let social = ["facebook.com", "fbcdn.net", "instagram.com", "cdninstagram.com", "whatsapp.net", "twitter.com", "x.com", "twimg.com", "snapchat.com"];
+    video.iter().map(|d| (d, QuotaType::Video)).chain(social.iter().map(|d| (d, QuotaType::Social)))
+        .map(|(d, category)| CategoryRule { pattern: d.to_string(), category, priority: 0 })
+        .collect()
+}
+
+fn categorize_with(rules: &[CategoryRule], domain: &str) -> QuotaType {
+    let domain = domain.trim().trim_end_matches('.').to_lowercase();
+    rules.iter().filter(|r| r.matches(&domain)).max_by_key(|r| r.priority).map(|r| r.category).unwrap_or(QuotaType::General)
+}
+
+fn is_tunnel_interface(name: &str) -> bool {
+    ["tun", "tap", "wg", "utun", "ppp", "ipsec"].iter().any(|p| name.starts_with(p))
+}
+
+fn is_virtual_interface(name: &str) -> bool {
+    name == "lo" || ["lo0", "docker", "veth", "br-", "virbr", "vmnet", "vboxnet", "ifb", "awdl", "llw", "gif", "stf", "anpi", "bridge"]
+        .iter().any(|p| name.starts_with(p))
+}
+
+/// Cumulative per-interface byte counters, sampled by the network sensor and `list_interfaces`. The built-in
+/// source reads /proc/net/dev on Linux and Android, the `NET_RT_IFLIST2` sysctl on macOS and iOS, and `GetIfTable2`
+/// on Windows. Hosts with their own counters (e.g. Android's `TrafficStats`) install one with `set_traffic_source`.
+#[uniffi::export(callback_interface)]
+pub trait TrafficSource: Send + Sync {
+    fn read_counters(&self) -> Vec<InterfaceCounters>;
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct InterfaceCounters {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn platform_counters() -> Vec<InterfaceCounters> {
+    let Ok(content) = std::fs::read_to_string("/proc/net/dev") else { return Vec::new(); };
+    content.lines().filter_map(|line| {
+        let (name, counters) = line.split_once(':')?;
+        let fields: Vec<u64> = counters.split_whitespace().map(|f| f.parse().unwrap_or(0)).collect();
+        Some(InterfaceCounters { name: name.trim().to_string(), rx_bytes: *fields.first()?, tx_bytes: *fields.get(8)? })
+    }).collect()
+}
+
+// One RTM_IFINFO2 message per interface, carrying 64-bit counters (getifaddrs' if_data wraps at 4 GiB).
+#[cfg(any(target_os = "macos", target_os = "ios"))]
+fn platform_counters() -> Vec<InterfaceCounters> {
+    let mut mib = [libc::CTL_NET, libc::PF_ROUTE, 0, 0, libc::NET_RT_IFLIST2, 0];
+    let mut len: libc::size_t = 0;
+    // SAFETY: the first call only sizes the buffer; the second fills at most `len` bytes of it. Messages are read
+    // unaligned and only when they fit in what the kernel returned.
+    unsafe {
+        if libc::sysctl(mib.as_mut_ptr(), mib.len() as u32, std::ptr::null_mut(), &mut len, std::ptr::null_mut(), 0) != 0 { return Vec::new(); }
+        let mut buf = vec![0u8; len];
+        if libc::sysctl(mib.as_mut_ptr(), mib.len() as u32, buf.as_mut_ptr().cast(), &mut len, std::ptr::null_mut(), 0) != 0 { return Vec::new(); }
+        let mut counters = Vec::new();
+        let mut offset = 0;
+        // Every routing message starts with its length (u16) and, after the version byte, its type.
+        while offset + 4 <= len {
+            let msg_len = u16::from_ne_bytes([buf[offset], buf[offset + 1]]) as usize;
+            if msg_len == 0 { break; }
+            if buf[offset + 3] as libc::c_int == libc::RTM_IFINFO2 && offset + std::mem::size_of::<libc::if_msghdr2>() <= len {
+                let info = std::ptr::read_unaligned(buf.as_ptr().add(offset) as *const libc::if_msghdr2);
+                let mut name = [0 as libc::c_char; libc::IF_NAMESIZE];
+                if !libc::if_indextoname(info.ifm_index as libc::c_uint, name.as_mut_ptr()).is_null() {
+                    let data = info.ifm_data;
+                    counters.push(InterfaceCounters {
+                        name: std::ffi::CStr::from_ptr(name.as_ptr()).to_string_lossy().into_owned(),
+                        rx_bytes: data.ifi_ibytes,
+                        tx_bytes: data.ifi_obytes,
+                    });
+                }
+            }
+            offset += msg_len;
+        }
+        counters
+    }
+}
+
+#[cfg(windows)]
+mod iphlpapi {
+    // MIB_IF_ROW2 and MIB_IF_TABLE2 from netioapi.h. Only the alias, type, flags and octet counters are read.
+    #[repr(C)]
+    pub struct MibIfRow2 {
+        pub interface_luid: u64,
+        pub interface_index: u32,
+        pub interface_guid: [u8; 16],
+        pub alias: [u16; 257],
+        pub description: [u16; 257],
+        pub physical_address_length: u32,
+        pub physical_address: [u8; 32],
+        pub permanent_physical_address: [u8; 32],
+        pub mtu: u32,
+        pub if_type: u32,
+        pub tunnel_type: u32,
+        pub media_type: u32,
+        pub physical_medium_type: u32,
+        pub access_type: u32,
+        pub direction_type: u32,
+        pub flags: u8,
+        pub oper_status: u32,
+        pub admin_status: u32,
+        pub media_connect_state: u32,
+        pub network_guid: [u8; 16],
+        pub connection_type: u32,
+        pub transmit_link_speed: u64,
+        pub receive_link_speed: u64,
+        pub in_octets: u64,
+        pub in_other: [u64; 8],
+        pub out_octets: u64,
+        pub out_other: [u64; 8],
+    }
+
+    const _: () = assert!(std::mem::size_of::<MibIfRow2>() == 1352);
+
+    #[repr(C)]
+    pub struct MibIfTable2 {
+        pub num_entries: u32,
+        pub table: [MibIfRow2; 1],
+    }
+
+    pub const IF_TYPE_SOFTWARE_LOOPBACK: u32 = 24;
+    // Bit 1 of InterfaceAndOperStatusFlags: a filter driver's view of another interface, which would double count.
+    pub const FILTER_INTERFACE: u8 = 0x2;
+
+    #[link(name = "iphlpapi")]
+    extern "system" {
+        pub fn GetIfTable2(table: *mut *mut MibIfTable2) -> u32;
+        pub fn FreeMibTable(memory: *const std::ffi::c_void);
+    }
+}
+
+#[cfg(windows)]
+fn platform_counters() -> Vec<InterfaceCounters> {
+    use iphlpapi::*;
+    let mut table: *mut MibIfTable2 = std::ptr::null_mut();
+    // SAFETY: GetIfTable2 hands back a table of `num_entries` rows that stays valid until FreeMibTable.
+    unsafe {
+        if GetIfTable2(&mut table) != 0 || table.is_null() { return Vec::new(); }
+        let rows = std::slice::from_raw_parts(std::ptr::addr_of!((*table).table).cast::<MibIfRow2>(), (*table).num_entries as usize);
+        let counters = rows.iter()
+            .filter(|row| row.if_type != IF_TYPE_SOFTWARE_LOOPBACK && row.flags & FILTER_INTERFACE == 0)
+            .map(|row| {
+                let alias = &row.alias[..row.alias.iter().position(|&c| c == 0).unwrap_or(row.alias.len())];
+                InterfaceCounters { name: String::from_utf16_lossy(alias), rx_bytes: row.in_octets, tx_bytes: row.out_octets }
+            })
+            .collect();
+        FreeMibTable(table.cast());
+        counters
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "macos", target_os = "ios", windows)))]
+fn platform_counters() -> Vec<InterfaceCounters> {
+    Vec::new()
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct UsageRecord {
+    pub timestamp: u64,
+    pub amount: u64,
+    pub category: String,
+    pub source: UsageSource,
+    /// How sure the sensor was when it inferred `category` from traffic shape (0-1). `None` when the category
+    /// was known: entered, imported or matched to a destination.
+    pub confidence: Option<f64>,
+}
+
+/// One deduction for `simulate_usage_batch`.
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct UsageEvent {
+    pub bytes: u64,
+    pub category: QuotaType,
+    /// When the traffic happened, for replayed traces. `None` means now.
+    pub timestamp: Option<u64>,
+}
+
+/// An event `simulate_usage_batch` skipped, by its position in the batch.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct RejectedUsage {
+    pub index: u32,
+    pub error: ErrorInfo,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct UsageBatchReport {
+    pub applied: u32,
+    pub applied_bytes: u64,
+    pub rejected: Vec<RejectedUsage>,
+}
+
+/// Typed counterpart of the text accepted by `handle_command`; see `execute_command`.
+#[derive(Clone, Debug, uniffi::Enum)]
+pub enum TelcoCommand {
+    /// `category` must be a data category (General, Social or Video).
+    BuyTopping { category: QuotaType, bytes: u64 },
+    Status,
+    /// The most recent `limit` usage records, newest first.
+    History { limit: u32 },
+    CancelTopping { bucket_id: String },
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct TelcoCommandResult {
+    /// What `handle_command` would have replied.
+    pub message: String,
+    pub account: UserAccount,
+    /// The topping bought or cancelled.
+    pub bucket: Option<QuotaBucket>,
+    pub history: Vec<UsageRecord>,
+}
+
+/// Screen shown by `handle_ussd`. While `session_active`, the next input is a menu choice.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct UssdResponse {
+    pub message: String,
+    pub session_active: bool,
+}
+
+// Menu choices taken so far in an interactive USSD session.
+struct UssdSession {
+    path: Vec<u32>,
+    last_input_at: u64,
+}
+
+const USSD_SESSION_TIMEOUT_SECS: u64 = 180;
+const USSD_TOPPINGS: [&str; 3] = ["General 1GB", "Social 2GB", "YouTube 2GB"];
+
+enum UssdNode {
+    Menu { title: String, options: Vec<String> },
+    Action(UssdAction),
+}
+
+// Leaves of the USSD menu; they only run once the screen is rendered.
+enum UssdAction {
+    BuyPlan(String),
+    BuyTopping(&'static str),
+    Balance,
+    History,
+    Wallet,
+}
+
+/// All fields are optional; `to` is exclusive.
+#[derive(Clone, Debug, Default, uniffi::Record)]
+pub struct UsageFilter {
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+    pub category: Option<QuotaType>,
+    pub min_amount: Option<u64>,
+    pub source: Option<UsageSource>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ImportReport {
+    pub imported: u64,
+    pub duplicates: u64,
+    pub rejected: u64,
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct SeedReport {
+    pub usage_rows: u64,
+    pub usage_bytes: u64,
+    /// Plans and toppings bought over the seeded period.
+    pub purchases: u32,
+}
+
+/// Operator push payload for `apply_remote_provisioning`, e.g.
+/// `{"id": "bss-42", "actions": [{"type": "plan", "name": "Monthly 15GB"}, {"type": "suspend", "reason": "unpaid"}]}`.
+#[derive(Deserialize)]
+struct ProvisioningPayload {
+    id: Option<String>,
+    actions: Vec<ProvisioningAction>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ProvisioningAction {
+    /// A catalog plan by name, or an ad-hoc one when `bytes` is given (the FUP threshold when `unlimited`).
+    Plan {
+        name: String,
+        bytes: Option<u64>,
+        category: Option<QuotaType>,
+        validity_days: Option<u32>,
+        active_window: Option<ValidityWindow>,
+        #[serde(default)]
+        unlimited: bool,
+    },
+    Bonus { name: String, bytes: u64, category: QuotaType, validity_days: Option<u32> },
+    Suspend { reason: Option<String> },
+    Resume,
+}
+
+/// Payload of `dispatch_external_command`, tagged by `"command"`.
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+enum ExternalCommand {
+    Chat { text: String },
+    BuyPlan { plan: String },
+    CancelBucket { bucket_id: String },
+    RedeemShareToken { token: String },
+    GrantBucket { name: String, bytes: u64, category: QuotaType, source: BucketSource },
+    Provision { provisioning: serde_json::Value },
+    SetRegistration { state: RegistrationState },
+    AdvanceSimRequest { request_id: String },
+    SimulateUsage { bytes: u64, category: QuotaType },
+}
+
+impl ExternalCommand {
+    fn name(&self) -> &'static str {
+        match self {
+            ExternalCommand::Chat { .. } => "chat",
+            ExternalCommand::BuyPlan { .. } => "buy_plan",
+            ExternalCommand::CancelBucket { .. } => "cancel_bucket",
+            ExternalCommand::RedeemShareToken { .. } => "redeem_share_token",
+            ExternalCommand::GrantBucket { .. } => "grant_bucket",
+            ExternalCommand::Provision { .. } => "provision",
+            ExternalCommand::SetRegistration { .. } => "set_registration",
+            ExternalCommand::AdvanceSimRequest { .. } => "advance_sim_request",
+            ExternalCommand::SimulateUsage { .. } => "simulate_usage",
+        }
+    }
+
+    // Subscribers act on their own balance, operators on the network side; only the test harness may fake traffic.
+    fn allowed(&self, source: CommandSource) -> bool {
+        use CommandSource::*;
+        match self {
+            ExternalCommand::Chat { .. } | ExternalCommand::RedeemShareToken { .. } => matches!(source, User | TestHarness),
+            ExternalCommand::BuyPlan { .. } | ExternalCommand::CancelBucket { .. } => true,
+            ExternalCommand::GrantBucket { .. } | ExternalCommand::SetRegistration { .. } => matches!(source, Operator | TestHarness),
+            ExternalCommand::Provision { .. } | ExternalCommand::AdvanceSimRequest { .. } => source == Operator,
+            ExternalCommand::SimulateUsage { .. } => source == TestHarness,
+        }
+    }
+}
+
+/// One timed line of a scenario; see `load_scenario`.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ScenarioStep {
+    /// Offset from the start of the run.
+    pub at_secs: u64,
+    /// 1-based line in the script, for reports.
+    pub line: u32,
+    pub action: ScenarioAction,
+}
+
+#[derive(Clone, Debug, uniffi::Enum)]
+pub enum ScenarioAction {
+    Consume { bytes: u64, category: QuotaType },
+    BuyTopping { category: QuotaType, bytes: u64 },
+    BuyPlan { plan: String },
+    TopUp { cents: u64 },
+    /// Ends the bucket with this id or name now, as if it had run out its validity.
+    ExpireBucket { bucket: String },
+    /// Holds the signal at `dbm`; see `set_signal_strength`.
+    Signal { dbm: i32 },
+    Tick,
+}
+
+/// Steps in the order they run.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct Scenario {
+    pub steps: Vec<ScenarioStep>,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ScenarioFailure {
+    pub line: u32,
+    pub error: ErrorInfo,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ScenarioReport {
+    pub steps_run: u32,
+    /// Simulated time the clock was moved forward by.
+    pub elapsed_secs: u64,
+    pub failures: Vec<ScenarioFailure>,
+}
+
+#[derive(Deserialize)]
+struct ImportedUsage {
+    timestamp: u64,
+    amount: u64,
+    category: String,
+}
+
+/// Keyset position after the last record of a page: newest-first order by (timestamp, row id).
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct HistoryCursor {
+    pub timestamp: u64,
+    pub row_id: i64,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct UsagePage {
+    pub records: Vec<UsageRecord>,
+    pub next_cursor: Option<HistoryCursor>,
+}
+
+/// Width of the periods `get_usage_aggregates` sums over. Periods are aligned to UTC; weeks start on Monday.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum AggregationPeriod { Hour, Day, Week }
+
+impl AggregationPeriod {
+    // (length, offset of a period boundary from the epoch), both in seconds. 1970-01-05 was a Monday.
+    #[cfg(feature = "sqlite")]
+    fn span(&self) -> (u64, u64) {
+        match self {
+            AggregationPeriod::Hour => (3600, 0),
+            AggregationPeriod::Day => (86400, 0),
+            AggregationPeriod::Week => (7 * 86400, 4 * 86400),
+        }
+    }
+}
+
+/// One category's usage summed over one period.
+#[derive(Clone, Copy, Debug, uniffi::Record, PartialEq)]
+pub struct UsageAggregate {
+    pub period_start: u64,
+    pub category: QuotaType,
+    /// In the category's unit: seconds for Voice, messages for Sms.
+    pub amount: u64,
+    pub record_count: u64,
+}
+
+// A batch entry that did not go through, by its index in the batch.
+type RejectedEntry = (usize, TelcoError);
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct UsageEntry {
+    bytes: u64,
+    category: QuotaType,
+    timestamp: u64,
+    source: UsageSource,
+    #[serde(default)]
+    confidence: Option<f64>,
+}
+
+impl From<UsageEntry> for UsageRecord {
+    fn from(u: UsageEntry) -> Self {
+        UsageRecord { timestamp: u.timestamp, amount: u.bytes, category: format!("{:?}", u.category), source: u.source, confidence: u.confidence }
+    }
+}
+
+const BURST_WINDOW: usize = 20;
+// About 2 Mbit/s over one 500 ms sensor sample.
+const BURST_MIN_BYTES: u64 = 125_000;
+
+/// Guesses a category for sensor traffic, which has no destination to classify by. A long, steady high-rate
+/// burst looks like video; anything shorter or spikier is charged to Social as before, with less confidence.
+#[derive(Default)]
+struct BurstDetector {
+    samples: std::collections::VecDeque<u64>,
+}
+
+impl BurstDetector {
+    fn observe(&mut self, bytes: u64) -> (QuotaType, f64) {
+        self.samples.push_back(bytes);
+        if self.samples.len() > BURST_WINDOW { self.samples.pop_front(); }
+        let run: Vec<f64> = self.samples.iter().rev().take_while(|b| **b >= BURST_MIN_BYTES).map(|b| *b as f64).collect();
+        // Three seconds of sustained transfer before calling it a stream.
+        if run.len() >= 6 {
+            let mean = run.iter().sum::<f64>() / run.len() as f64;
+            let deviation = (run.iter().map(|b| (b - mean).powi(2)).sum::<f64>() / run.len() as f64).sqrt();
+            let steadiness = (1.0 - deviation / mean).clamp(0.0, 1.0);
+            let duration = run.len() as f64 / BURST_WINDOW as f64;
+            return (QuotaType::Video, (0.5 + 0.45 * duration * steadiness).min(0.95));
+        }
+        (QuotaType::Social, if bytes >= BURST_MIN_BYTES { 0.5 } else { 0.3 })
+    }
+}
+
+#[uniffi::export(callback_interface)]
+pub trait TelcoLiveUpdateHandler: Send + Sync {
+    fn on_account_updated(&self, account: UserAccount);
+}
+
+/// What changed in one account update, carrying only the affected values.
+#[derive(Clone, Debug, uniffi::Enum)]
+pub enum AccountDelta {
+    /// `remaining` is the balance left in the category's unit (data, voice seconds or messages).
+    UsageRecorded { bytes: u64, category: QuotaType, remaining: u64 },
+    BucketAdded { bucket: QuotaBucket },
+    BucketExpired { bucket_id: String, name: String },
+    /// The data balance dropped below the threshold set with `set_low_balance_threshold`.
+    LowBalance { remaining_bytes: u64, threshold_bytes: u64 },
+    Unlocked,
+}
+
+/// Lighter alternative to `TelcoLiveUpdateHandler` for hosts that patch their own view of the account.
+#[uniffi::export(callback_interface)]
+pub trait AccountDeltaHandler: Send + Sync {
+    fn on_deltas(&self, deltas: Vec<AccountDelta>);
+}
+
+const DEFAULT_LOW_BALANCE_BYTES: u64 = 500 * 1024 * 1024;
+
+fn account_deltas(before: &UserAccount, after: &UserAccount, usage: &[UsageEntry], low_balance_bytes: u64, now: u64) -> Vec<AccountDelta> {
+    let mut deltas = Vec::new();
+    if before.biometric_locked && !after.biometric_locked { deltas.push(AccountDelta::Unlocked); }
+    for u in usage {
+        let remaining = match u.category {
+            QuotaType::Voice => after.voice_balance_secs,
+            QuotaType::Sms => after.sms_balance,
+            _ => after.data_balance_bytes,
+        };
+        deltas.push(AccountDelta::UsageRecorded { bytes: u.bytes, category: u.category, remaining });
+    }
+    for old in before.buckets.iter().filter(|b| b.remaining_bytes > 0) {
+        let expired = match after.buckets.iter().find(|b| b.id == old.id) {
+            Some(new) => new.remaining_bytes == 0 && new.expiry <= now,
+            None => old.expiry <= now,
+        };
+        if expired { deltas.push(AccountDelta::BucketExpired { bucket_id: old.id.clone(), name: old.name.clone() }); }
+    }
+    for new in after.buckets.iter().filter(|b| !before.buckets.iter().any(|o| o.id == b.id)) {
+        deltas.push(AccountDelta::BucketAdded { bucket: new.clone() });
+    }
+    if low_balance_bytes > 0 && before.data_balance_bytes >= low_balance_bytes && after.data_balance_bytes < low_balance_bytes {
+        deltas.push(AccountDelta::LowBalance { remaining_bytes: after.data_balance_bytes, threshold_bytes: low_balance_bytes });
+    }
+    deltas
+}
+
+/// Store for the account and its usage history, for simulators created with `with_backend` (e.g. over sled,
+/// Postgres or an in-memory map). Everything else (snapshots, aggregates, backups, ...) needs SQL and behaves as on
+/// an `ephemeral` simulator. `SqliteBackend` is the bundled implementation.
+#[uniffi::export(callback_interface)]
+pub trait PersistenceBackend: Send + Sync {
+    /// `None` for an account the store has never seen.
+    fn load_account(&self, account_id: String) -> Result<Option<UserAccount>, TelcoError>;
+    /// Called after every change with the whole account, replacing what was stored.
+    fn save_account(&self, account: UserAccount) -> Result<(), TelcoError>;
+    fn append_usage(&self, account_id: String, record: UsageRecord) -> Result<(), TelcoError>;
+    /// Newest first, at most `limit` rows.
+    fn query_history(&self, account_id: String, limit: u32) -> Result<Vec<UsageRecord>, TelcoError>;
+}
+
+/// `PersistenceBackend` over a SQLite file, in the same schema `TelcoSimulator::new` uses.
+#[cfg(feature = "sqlite")]
+pub struct SqliteBackend {
+    conn: Mutex<Connection>,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteBackend {
+    pub fn open(db_path: &str) -> Result<Self, TelcoError> {
+        let conn = open_connection(db_path, None)?;
+        configure_connection(&conn, &DbConfig::default())?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl PersistenceBackend for SqliteBackend {
+    fn load_account(&self, account_id: String) -> Result<Option<UserAccount>, TelcoError> {
+        let conn = self.conn.lock();
+        init_schema(&conn, &account_id)?;
+        let exists = conn.query_row("SELECT 1 FROM accounts WHERE id = ?1", params![account_id], |_| Ok(())).is_ok();
+        if !exists { return Ok(None); }
+        load_account_internal(&conn, &account_id).map(Some)
+    }
+
+    fn save_account(&self, account: UserAccount) -> Result<(), TelcoError> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction().map_err(db_err)?;
+        save_account_rows(&tx, &account, 0, None)?;
+        tx.commit().map_err(db_err)
+    }
+
+    fn append_usage(&self, account_id: String, record: UsageRecord) -> Result<(), TelcoError> {
+        insert_usage_row(&self.conn.lock(), &account_id, &record).map(|_| ()).map_err(db_err)
+    }
+
+    fn query_history(&self, account_id: String, limit: u32) -> Result<Vec<UsageRecord>, TelcoError> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT timestamp, amount, category, source, confidence FROM usage_history WHERE account_id = ?1 ORDER BY timestamp DESC LIMIT ?2")
+            .map_err(db_err)?;
+        let records = stmt.query_map(params![account_id, limit], usage_record_from_row).map_err(db_err)?
+            .filter_map(|r| r.ok())
+            .collect();
+        Ok(records)
+    }
+}
+
+/// `PersistenceBackend` over the browser's IndexedDB, picked by `TelcoSimulator::new` on wasm with `db_path` as the
+/// database name. IndexedDB only answers asynchronously, so the page awaits `hydrateStorage(db_path)` before opening
+/// a simulator; reads come from the hydrated copy and writes go to both.
+#[cfg(target_arch = "wasm32")]
+pub struct IndexedDbBackend {
+    db_name: String,
+}
+
+#[cfg(target_arch = "wasm32")]
+const INDEXED_DB_VERSION: u32 = 1;
+
+#[cfg(target_arch = "wasm32")]
+struct HydratedStore {
+    db: IdbDatabase,
+    accounts: HashMap<String, UserAccount>,
+    /// Oldest first, as appended.
+    usage: HashMap<String, Vec<UsageRecord>>,
+}
+
+#[cfg(target_arch = "wasm32")]
+#[derive(Serialize, Deserialize)]
+struct StoredUsage {
+    account_id: String,
+    timestamp: u64,
+    amount: u64,
+    category: String,
+    source: UsageSource,
+    confidence: Option<f64>,
+}
+
+#[cfg(target_arch = "wasm32")]
+thread_local! {
+    // wasm is single-threaded, so the JS handles live here rather than in the `Send + Sync` backend.
+    static HYDRATED_STORES: std::cell::RefCell<HashMap<String, HydratedStore>> = std::cell::RefCell::new(HashMap::new());
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+extern "C" {
+    type IdbFactory;
+    #[wasm_bindgen(method, catch)]
+    fn open(this: &IdbFactory, name: &str, version: u32) -> Result<IdbRequest, JsValue>;
+
+    type IdbRequest;
+    #[wasm_bindgen(method, setter)]
+    fn set_onsuccess(this: &IdbRequest, handler: &JsValue);
+    #[wasm_bindgen(method, setter)]
+    fn set_onerror(this: &IdbRequest, handler: &JsValue);
+    #[wasm_bindgen(method, setter)]
+    fn set_onupgradeneeded(this: &IdbRequest, handler: &JsValue);
+
+    type IdbDatabase;
+    #[wasm_bindgen(method, catch, js_name = createObjectStore)]
+    fn create_object_store(this: &IdbDatabase, name: &str, options: &JsValue) -> Result<JsValue, JsValue>;
+    #[wasm_bindgen(method, catch)]
+    fn transaction(this: &IdbDatabase, stores: &JsValue, mode: &str) -> Result<IdbTransaction, JsValue>;
+
+    type IdbTransaction;
+    #[wasm_bindgen(method, catch, js_name = objectStore)]
+    fn object_store(this: &IdbTransaction, name: &str) -> Result<IdbObjectStore, JsValue>;
+
+    type IdbObjectStore;
+    #[wasm_bindgen(method, catch)]
+    fn put(this: &IdbObjectStore, value: &JsValue, key: &JsValue) -> Result<IdbRequest, JsValue>;
+    #[wasm_bindgen(method, catch)]
+    fn add(this: &IdbObjectStore, value: &JsValue) -> Result<IdbRequest, JsValue>;
+    #[wasm_bindgen(method, catch, js_name = getAll)]
+    fn get_all(this: &IdbObjectStore) -> Result<IdbRequest, JsValue>;
+}
+
+#[cfg(target_arch = "wasm32")]
+fn js_err(e: JsValue) -> TelcoError {
+    TelcoError::InternalError(format!("IndexedDB: {:?}", e))
+}
+
+// `event.target.result` of an IndexedDB request event.
+#[cfg(target_arch = "wasm32")]
+fn event_result(event: &JsValue) -> JsValue {
+    js_sys::Reflect::get(event, &"target".into())
+        .and_then(|target| js_sys::Reflect::get(&target, &"result".into()))
+        .unwrap_or(JsValue::UNDEFINED)
+}
+
+#[cfg(target_arch = "wasm32")]
+fn idb_store(db: &IdbDatabase, name: &str, mode: &str) -> Result<IdbObjectStore, JsValue> {
+    db.transaction(&JsValue::from_str(name), mode)?.object_store(name)
+}
+
+// Reads every value in `name` once the request succeeds; each is one JSON string.
+#[cfg(target_arch = "wasm32")]
+fn idb_get_all(db: &IdbDatabase, name: &str, reject: &js_sys::Function, done: impl FnOnce(Vec<String>) + 'static) {
+    let request = match idb_store(db, name, "readonly").and_then(|store| store.get_all()) {
+        Ok(request) => request,
+        Err(e) => { let _ = reject.call1(&JsValue::NULL, &e); return; }
+    };
+    request.set_onsuccess(&Closure::once_into_js(move |event: JsValue| {
+        let values = js_sys::Array::from(&event_result(&event));
+        done(values.iter().filter_map(|v| v.as_string()).collect());
+    }));
+    let reject = reject.clone();
+    request.set_onerror(&Closure::once_into_js(move |event: JsValue| { let _ = reject.call1(&JsValue::NULL, &event); }));
+}
+
+/// Opens (creating if needed) the IndexedDB database `db_name` and loads it into memory. Await it before
+/// `TelcoSimulator::new(id, db_name)`; calling it again reloads from the browser's copy.
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen(js_name = hydrateStorage)]
+pub fn hydrate_storage(db_name: String) -> js_sys::Promise {
+    js_sys::Promise::new(&mut |resolve, reject| {
+        let factory = js_sys::Reflect::get(&js_sys::global(), &"indexedDB".into()).ok().filter(|f| !f.is_undefined());
+        let Some(factory) = factory else {
+            let _ = reject.call1(&JsValue::NULL, &TelcoError::StorageUnavailable.into());
+            return;
+        };
+        let request = match factory.unchecked_into::<IdbFactory>().open(&db_name, INDEXED_DB_VERSION) {
+            Ok(request) => request,
+            Err(e) => { let _ = reject.call1(&JsValue::NULL, &e); return; }
+        };
+        request.set_onupgradeneeded(&Closure::once_into_js(|event: JsValue| {
+            let db: IdbDatabase = event_result(&event).unchecked_into();
+            let _ = db.create_object_store("accounts", &JsValue::UNDEFINED);
+            let options = js_sys::Object::new();
+            let _ = js_sys::Reflect::set(&options, &"autoIncrement".into(), &JsValue::TRUE);
+            let _ = db.create_object_store("usage", &options);
+        }));
+        let on_error = reject.clone();
+        request.set_onerror(&Closure::once_into_js(move |event: JsValue| { let _ = on_error.call1(&JsValue::NULL, &event); }));
+        let db_name = db_name.clone();
+        request.set_onsuccess(&Closure::once_into_js(move |event: JsValue| {
+            let db: IdbDatabase = event_result(&event).unchecked_into();
+            let reader = JsValue::from(&db).unchecked_into::<IdbDatabase>();
+            let usage_reject = reject.clone();
+            idb_get_all(&reader, "accounts", &reject, move |accounts| {
+                let accounts: HashMap<String, UserAccount> = accounts.iter()
+                    .filter_map(|json| serde_json::from_str::<UserAccount>(json).ok())
+                    .map(|account| (account.id.clone(), account))
+                    .collect();
+                let reader = JsValue::from(&db).unchecked_into::<IdbDatabase>();
+                idb_get_all(&reader, "usage", &usage_reject, move |rows| {
+                    let mut usage: HashMap<String, Vec<UsageRecord>> = HashMap::new();
+                    for row in rows.iter().filter_map(|json| serde_json::from_str::<StoredUsage>(json).ok()) {
+                        usage.entry(row.account_id).or_default().push(UsageRecord {
+                            timestamp: row.timestamp, amount: row.amount, category: row.category, source: row.source, confidence: row.confidence,
+                        });
+                    }
+                    HYDRATED_STORES.with(|stores| stores.borrow_mut().insert(db_name, HydratedStore { db, accounts, usage }));
+                    let _ = resolve.call0(&JsValue::NULL);
+                });
+            });
+        }));
+    })
+}
+
+#[cfg(target_arch = "wasm32")]
+impl IndexedDbBackend {
+    fn with_store<T>(&self, f: impl FnOnce(&mut HydratedStore) -> Result<T, TelcoError>) -> Result<T, TelcoError> {
+        HYDRATED_STORES.with(|stores| {
+            let mut stores = stores.borrow_mut();
+            let store = stores.get_mut(&self.db_name)
+                .ok_or_else(|| TelcoError::InvalidCommand(format!("Await hydrateStorage('{}') before opening a simulator on it", self.db_name)))?;
+            f(store)
+        })
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl PersistenceBackend for IndexedDbBackend {
+    fn load_account(&self, account_id: String) -> Result<Option<UserAccount>, TelcoError> {
+        self.with_store(|store| Ok(store.accounts.get(&account_id).cloned()))
+    }
+
+    fn save_account(&self, account: UserAccount) -> Result<(), TelcoError> {
+        let json = serde_json::to_string(&account).map_err(|e| TelcoError::SerializationFailed(e.to_string()))?;
+        self.with_store(|store| {
+            idb_store(&store.db, "accounts", "readwrite").and_then(|s| s.put(&json.into(), &JsValue::from_str(&account.id))).map_err(js_err)?;
+            store.accounts.insert(account.id.clone(), account);
+            Ok(())
+        })
+    }
+
+    fn append_usage(&self, account_id: String, record: UsageRecord) -> Result<(), TelcoError> {
+        let row = StoredUsage {
+            account_id: account_id.clone(), timestamp: record.timestamp, amount: record.amount, category: record.category.clone(),
+            source: record.source, confidence: record.confidence,
+        };
+        let json = serde_json::to_string(&row).map_err(|e| TelcoError::SerializationFailed(e.to_string()))?;
+        self.with_store(|store| {
+            idb_store(&store.db, "usage", "readwrite").and_then(|s| s.add(&json.into())).map_err(js_err)?;
+            store.usage.entry(account_id).or_default().push(record);
+            Ok(())
+        })
+    }
+
+    fn query_history(&self, account_id: String, limit: u32) -> Result<Vec<UsageRecord>, TelcoError> {
+        self.with_store(|store| {
+            let mut records = store.usage.get(&account_id).cloned().unwrap_or_default();
+            records.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+            records.truncate(limit as usize);
+            Ok(records)
+        })
+    }
+}
+
+#[uniffi::export(callback_interface)]
+pub trait UsageBatchHandler: Send + Sync {
+    /// Return false to stop the stream early.
+    fn on_batch(&self, records: Vec<UsageRecord>) -> bool;
+}
+
+/// Host-side classifier (e.g. an on-device model) consulted before the domain rules.
+#[uniffi::export(callback_interface)]
+pub trait TrafficClassifier: Send + Sync {
+    /// Return None to fall back to the built-in domain rules.
+    fn classify(&self, domain: String) -> Option<QuotaType>;
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum WorkerKind { Persistence, Sensor }
+
+#[derive(Clone, Debug, uniffi::Enum)]
+pub enum DiagnosticEvent {
+    /// No heartbeat for `silent_ms`; the worker is alive but stuck (e.g. on a locked database).
+    WorkerStalled { worker: WorkerKind, silent_ms: u64 },
+    WorkerRecovered { worker: WorkerKind },
+    WorkerRestarted { worker: WorkerKind },
+}
+
+#[uniffi::export(callback_interface)]
+pub trait DiagnosticsHandler: Send + Sync {
+    fn on_diagnostic(&self, event: DiagnosticEvent);
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum WorkerStatus { NotStarted, Running, Stalled, Dead }
+
+#[derive(Clone, Debug, uniffi::Enum)]
+pub enum PersistenceFailure {
+    /// The write queue was full. A dropped account change is still in the journal and lands on the next open.
+    WriteDropped { dropped_writes: u64 },
+    /// The writer's SQL failed and the write was rolled back.
+    WriteFailed { failure: StorageFailure, message: String },
+}
+
+#[uniffi::export(callback_interface)]
+pub trait PersistenceErrorHandler: Send + Sync {
+    /// Called on the thread that hit the failure: the caller's for drops, the writer's for failed writes.
+    fn on_persistence_error(&self, failure: PersistenceFailure);
+}
+
+/// Counters since the simulator was opened.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct PersistenceStats {
+    pub queue_depth: u64,
+    pub dropped_writes: u64,
+    pub failed_writes: u64,
+    pub last_write_at: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct HealthReport {
+    /// False if any check below failed; hosts can use it to fall back to a degraded mode.
+    pub healthy: bool,
+    pub db_reachable: bool,
+    pub db_writable: bool,
+    pub persistence_queue_depth: u64,
+    pub persistence_worker: WorkerStatus,
+    pub sensor: WorkerStatus,
+    /// When the persistence worker last applied a queued write, if it has since startup.
+    pub last_write_at: Option<u64>,
+}
+
+/// Heartbeat age after which a live worker is reported as stalled.
+const WORKER_STALL_MS: u64 = 10_000;
+
+/// One difference between two account states, as produced by `diff_accounts`.
+#[derive(Clone, Debug, uniffi::Enum, Serialize, Deserialize)]
+pub enum AccountChange {
+    BucketAdded { bucket: QuotaBucket },
+    BucketRemoved { bucket_id: String, name: String },
+    BytesConsumed { bucket_id: String, bytes: u64, remaining_bytes: u64 },
+    BytesAdded { bucket_id: String, bytes: u64, remaining_bytes: u64 },
+    /// Anything about the bucket other than its balance changed (name, expiry, window, FUP cycle).
+    BucketUpdated { bucket: QuotaBucket },
+    LockChanged { locked: bool },
+    ActiveChanged { active: bool },
+    RegistrationChanged { registration: RegistrationState },
+    ThrottleChanged { throttled: bool },
+    GraceChanged { grace: Option<GraceState> },
+    WalletChanged { wallet_cents: u64 },
+}
+
+#[derive(Clone, Debug, uniffi::Record, Serialize, Deserialize)]
+pub struct AccountChangeSet {
+    pub seq: u64,
+    pub timestamp: u64,
+    pub changes: Vec<AccountChange>,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ChangeFeed {
+    pub latest_seq: u64,
+    /// Some requested change sets have already left the log; refetch the account instead of applying these.
+    pub truncated: bool,
+    pub change_sets: Vec<AccountChangeSet>,
+}
+
+const CHANGE_LOG_CAPACITY: usize = 500;
+
+// Diffs of every published account state, for `changes_since`.
+struct ChangeLog {
+    last: Arc<UserAccount>,
+    next_seq: u64,
+    entries: std::collections::VecDeque<AccountChangeSet>,
+}
+
+impl ChangeLog {
+    fn new(account: UserAccount) -> Self {
+        Self { last: Arc::new(account), next_seq: 1, entries: std::collections::VecDeque::new() }
+    }
+
+    // Returns the account as it was before this update.
+    fn record(&mut self, account: &Arc<UserAccount>, now: u64) -> Arc<UserAccount> {
+        let changes = account_changes(&self.last, account);
+        let previous = std::mem::replace(&mut self.last, account.clone());
+        if changes.is_empty() { return previous; }
+        if self.entries.len() == CHANGE_LOG_CAPACITY { self.entries.pop_front(); }
+        self.entries.push_back(AccountChangeSet { seq: self.next_seq, timestamp: now, changes });
+        self.next_seq += 1;
+        previous
+    }
+
+    fn since(&self, seq: u64) -> ChangeFeed {
+        let latest_seq = self.next_seq - 1;
+        let oldest = self.entries.front().map_or(self.next_seq, |e| e.seq);
+        ChangeFeed {
+            latest_seq,
+            truncated: seq < latest_seq && seq + 1 < oldest,
+            change_sets: self.entries.iter().filter(|e| e.seq > seq).cloned().collect(),
+        }
+    }
+}
+
+/// A stored preference; see `set_preference`.
+#[derive(Clone, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum PreferenceValue {
+    Bool { value: bool },
+    Int { value: i64 },
+    Float { value: f64 },
+    Text { value: String },
+}
+
+#[derive(Clone, Debug, uniffi::Enum, Serialize, Deserialize)]
+pub enum TelcoEvent {
+    UsageRecorded { bytes: u64, category: QuotaType, timestamp: u64 },
+    BucketAdded { bucket: QuotaBucket },
+    BucketCancelled { bucket: QuotaBucket },
+    Unlocked,
+    AchievementUnlocked { achievement: Achievement },
+    SimRequestUpdated { request: SimRequest },
+    RegistrationChanged { from: RegistrationState, to: RegistrationState },
+    FlightModeChanged { enabled: bool },
+    NetworkTypeChanged { from: NetworkGeneration, to: NetworkGeneration },
+    /// `None` once the line is back home.
+    RoamingZoneChanged { zone: Option<RoamingZone> },
+    GraceStarted { allowance_bytes: u64 },
+    GraceExhausted,
+    GraceEnded,
+    ThrottleStarted { speed_kbps: u32 },
+    /// Today's pace would overshoot the daily budget. Sent at most once a day.
+    BudgetPaceWarning { used_bytes: u64, projected_bytes: u64, budget_bytes: u64 },
+    BudgetExceeded { used_bytes: u64, budget_bytes: u64 },
+    BudgetDayCompleted { day: u64, used_bytes: u64, budget_bytes: u64, under_budget: bool },
+    ThrottleEnded,
+    OrderActivated { order: ScheduledOrder, bucket: QuotaBucket },
+    OrderFailed { order: ScheduledOrder, reason: String },
+    SubscriptionRenewed { subscription: Subscription, bucket: QuotaBucket },
+    /// The purchase can no longer be bought; the subscription has been cancelled.
+    SubscriptionFailed { subscription: Subscription, reason: String },
+    /// A bucket crossed one of the `consumed_percents` in its `NotificationThresholds`.
+    QuotaThresholdReached { bucket_id: String, name: String, percent_consumed: u8, remaining_bytes: u64 },
+    /// A bucket with balance left is within one of the `expiry_warning_secs` of expiring.
+    BucketExpiringSoon { bucket_id: String, name: String, expiry: u64, remaining_bytes: u64 },
+    /// Sent the first time a cap turns something away in a billing cycle.
+    CapReached { cap: CapKind, limit: u64, used: u64 },
+    /// `value` is `None` when the preference was removed.
+    PreferenceChanged { key: String, value: Option<PreferenceValue> },
+    /// Unused bytes from expired or renewed buckets; `rolled_over` is empty when everything was forfeited.
+    QuotaRolledOver { rolled_over: Vec<QuotaBucket>, forfeited_bytes: u64 },
+    AccountSuspended { reason: Option<String> },
+    AccountResumed,
+    ProvisioningApplied { provisioning_id: Option<String>, actions: u32 },
+    GiftSent { bytes: u64, category: QuotaType, expires_at: u64 },
+    GiftReceived { bucket: QuotaBucket },
+    /// `bucket` is as it was at expiry, unused balance included. Anything carried over follows as `QuotaRolledOver`.
+    BucketExpired { bucket: QuotaBucket },
+}
+
+impl TelcoEvent {
+    // Critical events bypass quiet hours
+    fn is_critical(&self) -> bool {
+        matches!(self, TelcoEvent::Unlocked)
+    }
+}
+
+/// What a purchase is about to add, passed to `LifecycleHooks::before_purchase`.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct PurchaseRequest {
+    pub bucket: QuotaBucket,
+    /// Catalog price of the plan or topping.
+    pub price_cents: u64,
+}
+
+#[derive(Clone, Debug, uniffi::Enum, PartialEq)]
+pub enum PurchaseDecision {
+    Allow,
+    Deny { reason: String },
+}
+
+/// Host-side side effects around account operations. Hooks run on the calling thread, outside the engine's locks.
+#[uniffi::export(callback_interface)]
+pub trait LifecycleHooks: Send + Sync {
+    /// Runs before anything about the account changes; `Deny` leaves it untouched and fails the purchase.
+    fn before_purchase(&self, request: PurchaseRequest) -> PurchaseDecision;
+    fn after_usage(&self, units: u64, category: QuotaType, account: UserAccount);
+    /// Buckets were retired at expiry or renewal; `rolled_over` holds the carried balance.
+    fn on_cycle_rollover(&self, rolled_over: Vec<QuotaBucket>, forfeited_bytes: u64);
+}
+
+#[uniffi::export(callback_interface)]
+pub trait TelcoEventHandler: Send + Sync {
+    fn on_event(&self, event: TelcoEvent);
+    fn on_digest(&self, events: Vec<TelcoEvent>);
+}
+
+/// Local wall-clock window, in minutes since midnight. Windows may wrap past midnight (e.g. 22:00-07:00).
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct QuietHours {
+    pub start_minute: u32,
+    pub end_minute: u32,
+}
+
+impl QuietHours {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start_minute <= self.end_minute {
+            minute >= self.start_minute && minute < self.end_minute
+        } else {
+            minute >= self.start_minute || minute < self.end_minute
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+enum PersistenceMsg {
+    Account { account: Arc<UserAccount>, usage: Vec<UsageEntry>, journal_seq: u64 },
+    EventQueue { account_id: String, events: Vec<TelcoEvent> },
+    AdReward { account_id: String, timestamp: u64, bytes: u64 },
+    Achievement { account_id: String, record: AchievementRecord },
+    ExperimentAssignment { account_id: String, experiment: String, variant: String },
+    CategoryRules { account_id: String, rules: Vec<CategoryRule> },
+    PurgeTrash { account_id: String, before: u64 },
+    /// Keeps a cancelled bucket's row, marked deleted, for `restore_bucket`.
+    TrashBucket { account_id: String, bucket: QuotaBucket, deleted_at: u64 },
+    /// Moves the buckets' rows into the expiry archive.
+    ArchiveBuckets { account_id: String, buckets: Vec<ExpiredBucket> },
+    PendingOrders { account_id: String, orders: Vec<ScheduledOrder> },
+    /// The history cursor is taken when the worker gets here, after every usage row queued before it.
+    CreateSnapshot { account_id: String, name: String, created_at: u64, payload: String },
+    DeleteSnapshot { account_id: String, name: String },
+    RestoreSnapshot { account_id: String, name: String, created_at: u64 },
+    /// Undoes a `ClaimToken` whose bucket couldn't be added.
+    ReleaseToken { nonce: String },
+    DeleteAccount { account_id: String },
+    /// Swaps the account's whole usage history for `usage`, after any rows queued before it.
+    ReplaceUsage { account_id: String, usage: Vec<UsageEntry> },
+    Subscriptions { account_id: String, subscriptions: Vec<Subscription> },
+    Preference { account_id: String, key: String, value: Option<PreferenceValue> },
+    InsightConfig { account_id: String, config: InsightConfig },
+    CommandAudit { account_id: String, entry: CommandAuditEntry },
+    /// `payload` is `None` once the pool is dissolved; the row stays so a late write can't bring it back.
+    FamilyPool { pool_id: String, payload: Option<String>, version: u64 },
+    /// Re-applies the pragmas in `config` to the writer's connection.
+    Configure { config: DbConfig },
+    /// Handled by the worker itself, since it owns the connection that has to be re-keyed.
+    Rekey { key: SecretString, reply: mpsc::SyncSender<Result<(), TelcoError>> },
+    /// Copies the database to `path` once everything queued before it is written.
+    Backup { path: String, reply: mpsc::SyncSender<Result<(), TelcoError>> },
+    /// Overwrites the database with the (already verified) file at `path`. `account_id`'s journal position is
+    /// carried over so older journal entries aren't replayed on top of the restored state.
+    Restore { path: String, account_id: String, reply: mpsc::SyncSender<Result<(), TelcoError>> },
+    /// Records a share token nonce for every account in the file; answers `false` if it was already there.
+    ClaimToken { account_id: String, nonce: String, redeemed_at: u64, reply: mpsc::SyncSender<Result<bool, TelcoError>> },
+    /// Sent by `close`: the worker checkpoints, answers and exits.
+    Shutdown { reply: mpsc::SyncSender<Result<(), TelcoError>> },
+}
+
+#[cfg(feature = "sqlite")]
+impl PersistenceMsg {
+    // Answered by the worker itself rather than written as part of a batch.
+    fn is_request(&self) -> bool {
+        matches!(self, PersistenceMsg::Rekey { .. } | PersistenceMsg::Backup { .. } | PersistenceMsg::Restore { .. } | PersistenceMsg::ClaimToken { .. } | PersistenceMsg::Shutdown { .. })
+    }
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum Achievement { FirstTopUp, WeekUnderBudget, HappyHourSaver }
+
+impl Achievement {
+    #[cfg(feature = "sqlite")]
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "FirstTopUp" => Some(Achievement::FirstTopUp),
+            "WeekUnderBudget" => Some(Achievement::WeekUnderBudget),
+            "HappyHourSaver" => Some(Achievement::HappyHourSaver),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record, Serialize, Deserialize)]
+pub struct AchievementRecord {
+    pub achievement: Achievement,
+    pub unlocked_at: u64,
+}
+
+/// Thresholds used when deriving achievements from usage history.
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct AchievementConfig {
+    pub daily_budget_bytes: u64,
+    pub happy_hour_start_minute: u32,
+    pub happy_hour_end_minute: u32,
+    pub happy_hour_target_bytes: u64,
+}
+
+impl Default for AchievementConfig {
+    fn default() -> Self {
+        Self {
+            daily_budget_bytes: megabytes(500),
+            happy_hour_start_minute: 60,
+            happy_hour_end_minute: 6 * 60,
+            happy_hour_target_bytes: gigabytes(10),
+        }
+    }
+}
+
+/// Where today's usage stands against the daily budget. Days are UTC, as in usage history.
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct BudgetStatus {
+    pub budget_bytes: u64,
+    pub used_today_bytes: u64,
+    /// Today's usage extrapolated to the whole day at the current pace.
+    pub projected_bytes: u64,
+    pub on_track: bool,
+    /// Consecutive complete days at or under budget, ending yesterday.
+    pub streak_days: u32,
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Record, PartialEq)]
+pub struct CategoryBytes {
+    pub category: QuotaType,
+    /// In the category's unit: seconds for Voice, messages for Sms.
+    pub bytes: u64,
+}
+
+/// Per-billing-cycle limits; `None` (or no entry) leaves that limit off. `total_bytes` covers the data categories.
+#[derive(Clone, Debug, Default, uniffi::Record, PartialEq)]
+pub struct UsageCaps {
+    pub total_bytes: Option<u64>,
+    pub category_bytes: Vec<CategoryBytes>,
+    pub spend_cents: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum CapKind {
+    TotalBytes,
+    CategoryBytes { category: QuotaType },
+    Spend,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct CapStatus {
+    pub caps: UsageCaps,
+    pub period_started_at: u64,
+    pub total_bytes: u64,
+    pub category_bytes: Vec<CategoryBytes>,
+    pub spent_cents: u64,
+}
+
+// What has counted against the caps this billing cycle (a fixed 30-day period).
+#[derive(Default)]
+struct CapTracker {
+    caps: UsageCaps,
+    period: u64,
+    total_bytes: u64,
+    category_bytes: Vec<CategoryBytes>,
+    spent_cents: u64,
+    // Caps already reported with `CapReached` this period.
+    reached: Vec<CapKind>,
+}
+
+impl CapTracker {
+    fn roll(&mut self, now: u64) {
+        let period = now / BILLING_CYCLE_SECS;
+        if period != self.period {
+            *self = CapTracker { caps: std::mem::take(&mut self.caps), period, ..CapTracker::default() };
+        }
+    }
+
+    fn used(&self, category: QuotaType) -> u64 {
+        self.category_bytes.iter().find(|c| c.category == category).map_or(0, |c| c.bytes)
+    }
+
+    // The first cap `units` more of `category` would go over, as (cap, limit, used).
+    fn check_usage(&mut self, units: u64, category: QuotaType, now: u64) -> Option<(CapKind, u64, u64)> {
+        self.roll(now);
+        if let Some(limit) = self.caps.category_bytes.iter().find(|c| c.category == category).map(|c| c.bytes) {
+            let used = self.used(category);
+            if used + units > limit { return Some((CapKind::CategoryBytes { category }, limit, used)); }
+        }
+        match self.caps.total_bytes {
+            Some(limit) if category.is_data() && self.total_bytes + units > limit => Some((CapKind::TotalBytes, limit, self.total_bytes)),
+            _ => None,
+        }
+    }
+
+    fn record_usage(&mut self, units: u64, category: QuotaType, now: u64) {
+        self.roll(now);
+        if category.is_data() { self.total_bytes += units; }
+        match self.category_bytes.iter_mut().find(|c| c.category == category) {
+            Some(c) => c.bytes += units,
+            None => self.category_bytes.push(CategoryBytes { category, bytes: units }),
+        }
+    }
+
+    fn check_spend(&mut self, cents: u64, now: u64) -> Option<(CapKind, u64, u64)> {
+        self.roll(now);
+        match self.caps.spend_cents {
+            Some(limit) if cents > 0 && self.spent_cents + cents > limit => Some((CapKind::Spend, limit, self.spent_cents)),
+            _ => None,
+        }
+    }
+
+    // True the first time `cap` is reached this period.
+    fn first_reach(&mut self, cap: CapKind) -> bool {
+        if self.reached.contains(&cap) { return false; }
+        self.reached.push(cap);
+        true
+    }
+}
+
+/// When `QuotaThresholdReached` and `BucketExpiringSoon` are sent.
+#[derive(Clone, Debug, uniffi::Record, PartialEq)]
+pub struct NotificationThresholds {
+    /// Percent of a bucket consumed, e.g. 80 and 95.
+    pub consumed_percents: Vec<u8>,
+    /// Seconds before a bucket's expiry, e.g. 86400 for a day's notice.
+    pub expiry_warning_secs: Vec<u64>,
+}
+
+impl Default for NotificationThresholds {
+    fn default() -> Self {
+        Self { consumed_percents: vec![80, 95], expiry_warning_secs: vec![86400] }
+    }
+}
+
+// Per-bucket notification state. Percentages are measured against the size the bucket was issued with, or a larger
+// balance seen since, so a top-up that refills a bucket re-arms its thresholds.
+#[derive(Default)]
+struct BucketWatch {
+    peak: u64,
+    consumed_sent: Vec<u8>,
+    expiry_sent: Vec<u64>,
+}
+
+struct QuotaWatch {
+    buckets: HashMap<String, BucketWatch>,
+}
+
+impl QuotaWatch {
+    // Starts from each bucket's issued size, so a bucket already part-used when the account is loaded isn't treated
+    // as full. Buckets stored without one fall back to their current balance.
+    fn new(account: &UserAccount) -> Self {
+        let buckets = account.buckets.iter().map(|b| (b.id.clone(), BucketWatch { peak: b.initial_bytes.max(b.remaining_bytes), ..BucketWatch::default() })).collect();
+        Self { buckets }
+    }
+
+    fn evaluate(&mut self, account: &UserAccount, thresholds: &NotificationThresholds, now: u64) -> Vec<TelcoEvent> {
+        self.buckets.retain(|id, _| account.buckets.iter().any(|b| &b.id == id));
+        let mut events = Vec::new();
+        for bucket in account.buckets.iter().filter(|b| b.expiry > now) {
+            let watch = self.buckets.entry(bucket.id.clone()).or_default();
+            watch.peak = watch.peak.max(bucket.initial_bytes).max(bucket.remaining_bytes);
+            if watch.peak > 0 {
+                let consumed = ((watch.peak - bucket.remaining_bytes) as u128 * 100 / watch.peak as u128) as u8;
+                watch.consumed_sent.retain(|&p| p <= consumed);
+                for &percent in thresholds.consumed_percents.iter().filter(|&&p| p <= consumed) {
+                    if watch.consumed_sent.contains(&percent) { continue; }
+                    watch.consumed_sent.push(percent);
+                    events.push(TelcoEvent::QuotaThresholdReached {
+                        bucket_id: bucket.id.clone(), name: bucket.name.clone(), percent_consumed: percent, remaining_bytes: bucket.remaining_bytes,
+                    });
+                }
+            }
+            if bucket.remaining_bytes == 0 { continue; }
+            for &secs in thresholds.expiry_warning_secs.iter().filter(|&&s| bucket.expiry - now <= s) {
+                if watch.expiry_sent.contains(&secs) { continue; }
+                watch.expiry_sent.push(secs);
+                events.push(TelcoEvent::BucketExpiringSoon {
+                    bucket_id: bucket.id.clone(), name: bucket.name.clone(), expiry: bucket.expiry, remaining_bytes: bucket.remaining_bytes,
+                });
+            }
+        }
+        events
+    }
+}
+
+#[derive(Default)]
+struct BudgetTracker {
+    budget_bytes: Option<u64>,
+    day: u64,
+    used_bytes: u64,
+    warned: bool,
+    exceeded: bool,
+}
+
+impl BudgetTracker {
+    // The first hour counts as a full hour so a burst just after midnight doesn't project to a huge day.
+    fn projected(&self, now: u64) -> u64 {
+        self.used_bytes.saturating_mul(86400) / (now % 86400).max(3600)
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct PlanOffer {
+    pub name: String,
+    pub bytes: u64,
+    pub category: QuotaType,
+    pub price_cents: u64,
+    pub validity_days: u32,
+    /// Restricts the plan's bucket to a time of day, for night packs and similar.
+    pub active_window: Option<ValidityWindow>,
+    /// Unlimited plan; `bytes` is its fair-usage threshold rather than an allowance.
+    pub unlimited: bool,
+    /// Roaming pass: only usable while roaming, and then drawn first at face value whatever the zone's rate.
+    pub roaming: bool,
+}
+
+impl PlanOffer {
+    fn new(name: &str, gb: u64, category: QuotaType, price_cents: u64, validity_days: u32) -> Self {
+        Self { name: name.to_string(), bytes: gigabytes(gb), category, price_cents, validity_days, active_window: None, unlimited: false, roaming: false }
+    }
+
+    fn roaming(mut self) -> Self {
+        self.roaming = true;
+        self
+    }
+
+    fn unlimited(mut self) -> Self {
+        self.unlimited = true;
+        self
+    }
+
+    fn between(mut self, start_minute: u32, end_minute: u32) -> Self {
+        self.active_window = Some(ValidityWindow { start_minute, end_minute });
+        self
+    }
+
+    fn to_bucket(&self, now: u64) -> QuotaBucket {
+        QuotaBucket {
+            id: new_id(),
+            name: self.name.clone(),
+            remaining_bytes: self.bytes,
+            initial_bytes: self.bytes,
+            category: self.category,
+            expiry: now + self.validity_days as u64 * 86400,
+            source: if self.roaming { BucketSource::Roaming } else { BucketSource::Purchase },
+            active_window: self.active_window,
+            fair_usage: self.unlimited.then(|| FairUsage::new(self.bytes, now)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct OperatorProfile {
+    pub name: String,
+    pub currency: String,
+    pub plans: Vec<PlanOffer>,
+    /// Upper bound on unused bytes carried into the next cycle.
+    pub max_rollover_bytes: u64,
+    /// Plan granted to accounts that start with no buckets.
+    pub starter_plan: Option<String>,
+    /// What chat toppings cost; categories without a price are free.
+    pub topping_prices: Vec<ToppingPrice>,
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Record, PartialEq)]
+pub struct ToppingPrice {
+    pub category: QuotaType,
+    pub cents_per_gb: u64,
+}
+
+impl OperatorProfile {
+    // Pro rata by bytes, rounded up so a 1MB topping is never free.
+    fn topping_price(&self, bucket: &QuotaBucket) -> u64 {
+        self.topping_prices.iter().find(|p| p.category == bucket.category)
+            .map(|p| u64::try_from((bucket.remaining_bytes as u128 * p.cents_per_gb as u128).div_ceil(gigabytes(1) as u128)).unwrap_or(u64::MAX))
+            .unwrap_or(0)
+    }
+
+    fn price_of(&self, bucket: &QuotaBucket) -> u64 {
+        self.plans.iter().find(|p| p.name == bucket.name).map_or_else(|| self.topping_price(bucket), |p| p.price_cents)
+    }
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum OperatorPreset { Custom, BudgetPrepaid, StreamerPostpaid, SocialYouth }
+
+impl OperatorPreset {
+    fn profile(&self) -> OperatorProfile {
+        let gb = gigabytes(1);
+        match self {
+            OperatorPreset::Custom => OperatorProfile {
+                name: "Custom".to_string(), currency: "USD".to_string(), plans: vec![], max_rollover_bytes: 0, starter_plan: None, topping_prices: vec![],
+            },
+            OperatorPreset::BudgetPrepaid => OperatorProfile {
+                name: "Budget Prepaid".to_string(),
+                currency: "USD".to_string(),
+                plans: vec![
+                    PlanOffer::new("Daily 1GB", 1, QuotaType::General, 100, 1),
+                    PlanOffer::new("Weekly 5GB", 5, QuotaType::General, 400, 7),
+                    PlanOffer::new("Monthly 15GB", 15, QuotaType::General, 1000, 30),
+                    PlanOffer::new("Night Owl 20GB", 20, QuotaType::General, 300, 7).between(0, 6 * 60),
+                    PlanOffer::new("Roaming Day 1GB", 1, QuotaType::General, 700, 1).roaming(),
+                ],
+                max_rollover_bytes: 0,
+                starter_plan: Some("Weekly 5GB".to_string()),
+                topping_prices: vec![
+                    ToppingPrice { category: QuotaType::General, cents_per_gb: 100 },
+                    ToppingPrice { category: QuotaType::Social, cents_per_gb: 60 },
+                    ToppingPrice { category: QuotaType::Video, cents_per_gb: 80 },
+                ],
+            },
+            OperatorPreset::StreamerPostpaid => OperatorProfile {
+                name: "Streamer Postpaid".to_string(),
+                currency: "USD".to_string(),
+                plans: vec![
+                    PlanOffer::new("Monthly 50GB", 50, QuotaType::General, 4500, 30),
+                    PlanOffer::new("Video Pass 30GB", 30, QuotaType::Video, 1500, 30),
+                    PlanOffer::new("Video Boost 5GB", 5, QuotaType::Video, 500, 7),
+                    PlanOffer::new("Unlimited (100GB FUP)", 100, QuotaType::General, 6000, 30).unlimited(),
+                    PlanOffer::new("Roaming Day 2GB", 2, QuotaType::General, 1000, 1).roaming(),
+                ],
+                max_rollover_bytes: 10 * gb,
+                starter_plan: Some("Monthly 50GB".to_string()),
+                topping_prices: vec![
+                    ToppingPrice { category: QuotaType::General, cents_per_gb: 150 },
+                    ToppingPrice { category: QuotaType::Social, cents_per_gb: 100 },
+                    ToppingPrice { category: QuotaType::Video, cents_per_gb: 100 },
+                ],
+            },
+            OperatorPreset::SocialYouth => OperatorProfile {
+                name: "Social Youth".to_string(),
+                currency: "USD".to_string(),
+                plans: vec![
+                    PlanOffer::new("Monthly 10GB", 10, QuotaType::General, 1500, 30),
+                    PlanOffer::new("Social 20GB", 20, QuotaType::Social, 500, 30),
+                    PlanOffer::new("Social Weekly 3GB", 3, QuotaType::Social, 200, 7),
+                    PlanOffer::new("Roaming Day 1GB", 1, QuotaType::General, 800, 1).roaming(),
+                ],
+                max_rollover_bytes: 2 * gb,
+                starter_plan: Some("Monthly 10GB".to_string()),
+                topping_prices: vec![
+                    ToppingPrice { category: QuotaType::General, cents_per_gb: 150 },
+                    ToppingPrice { category: QuotaType::Social, cents_per_gb: 50 },
+                    ToppingPrice { category: QuotaType::Video, cents_per_gb: 120 },
+                ],
+            },
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct RegionProfile {
+    pub name: String,
+    pub currency: String,
+    pub latency_min_ms: u32,
+    pub latency_max_ms: u32,
+    /// Destinations commonly visited from this market; see `set_roaming_zone`.
+    pub roaming_zones: Vec<RoamingZone>,
+}
+
+#[derive(Clone, Debug, uniffi::Record, PartialEq, Serialize, Deserialize)]
+pub struct RoamingZone {
+    pub name: String,
+    /// What usage costs against home buckets while roaming here, in percent (100 = same as home).
+    pub multiplier_percent: u32,
+}
+
+/// Languages with a message catalog. Other tags fall back to English text but keep their number conventions.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Language { En, Id, Es }
+
+// Keys into the message catalog. Templates take positional `{}` arguments.
+#[derive(Clone, Copy, Debug)]
+enum Msg {
+    Remaining,
+    DaysLeft,
+    TopUpSoon,
+    StartForecast,
+    InMemory,
+    UnlockRequired,
+    ToppingAdded,
+    Scheduled,
+    Gift,
+    Cancelled,
+    Bought,
+    UsageRecords,
+    NoBalance,
+    Balance,
+    VoiceMinutes,
+    Sms,
+    NoUsage,
+    Wallet,
+    Error,
+    SessionEnded,
+    InvalidChoice,
+    NoUssdSession,
+    InvalidMmi,
+    Back,
+    Services,
+    BuyData,
+    BalanceMenu,
+    UsageHistory,
+    WalletMenu,
+    Plans,
+    Toppings,
+    Category(QuotaType),
+    ErrorKind(ErrorKind),
+}
+
+impl Language {
+    fn template(self, msg: Msg) -> &'static str {
+        use Language::*;
+        match (msg, self) {
+            (Msg::Remaining, En) => "You have {} remaining.",
+            (Msg::Remaining, Id) => "Sisa kuota Anda {}.",
+            (Msg::Remaining, Es) => "Te quedan {}.",
+            (Msg::DaysLeft, En) => " Based on last {} days, you have roughly {} days of usage left.",
+            (Msg::DaysLeft, Id) => " Berdasarkan {} hari terakhir, kuota Anda cukup untuk sekitar {} hari lagi.",
+            (Msg::DaysLeft, Es) => " Según los últimos {} días, te quedan unos {} días de uso.",
+            (Msg::TopUpSoon, En) => " Recommendation: Top up soon to avoid interruption.",
+            (Msg::TopUpSoon, Id) => " Saran: Segera isi ulang agar layanan tidak terputus.",
+            (Msg::TopUpSoon, Es) => " Recomendación: Recarga pronto para evitar interrupciones.",
+            (Msg::StartForecast, En) => " Start using data to see personalized forecasting.",
+            (Msg::StartForecast, Id) => " Mulai gunakan data untuk melihat perkiraan pribadi.",
+            (Msg::StartForecast, Es) => " Empieza a usar datos para ver previsiones personalizadas.",
+            (Msg::InMemory, En) => " (In-Memory Mode)",
+            (Msg::InMemory, Id) => " (Mode Memori)",
+            (Msg::InMemory, Es) => " (Modo en memoria)",
+            (Msg::UnlockRequired, En) => "Unlock required.",
+            (Msg::UnlockRequired, Id) => "Buka kunci terlebih dahulu.",
+            (Msg::UnlockRequired, Es) => "Se requiere desbloqueo.",
+            (Msg::ToppingAdded, En) => "Liquid Bubble growing...",
+            (Msg::ToppingAdded, Id) => "Gelembung kuota bertambah...",
+            (Msg::ToppingAdded, Es) => "La burbuja está creciendo...",
+            (Msg::Scheduled, En) => "Scheduled {} for {}.",
+            (Msg::Scheduled, Id) => "{} dijadwalkan pukul {}.",
+            (Msg::Scheduled, Es) => "{} programado para las {}.",
+            (Msg::Gift, En) => "Gift of {} for {}: {}",
+            (Msg::Gift, Id) => "Hadiah {} untuk {}: {}",
+            (Msg::Gift, Es) => "Regalo de {} para {}: {}",
+            (Msg::Cancelled, En) => "Cancelled {}.",
+            (Msg::Cancelled, Id) => "{} dibatalkan.",
+            (Msg::Cancelled, Es) => "{} cancelado.",
+            (Msg::Bought, En) => "You bought {}.",
+            (Msg::Bought, Id) => "Anda membeli {}.",
+            (Msg::Bought, Es) => "Compraste {}.",
+            (Msg::UsageRecords, En) => "{} usage records.",
+            (Msg::UsageRecords, Id) => "{} catatan pemakaian.",
+            (Msg::UsageRecords, Es) => "{} registros de consumo.",
+            (Msg::NoBalance, En) => "No balance left.",
+            (Msg::NoBalance, Id) => "Saldo habis.",
+            (Msg::NoBalance, Es) => "No te queda saldo.",
+            (Msg::Balance, En) => "Balance: {}.",
+            (Msg::Balance, Id | Es) => "Saldo: {}.",
+            (Msg::VoiceMinutes, En) => "Voice {} min",
+            (Msg::VoiceMinutes, Id) => "Telepon {} mnt",
+            (Msg::VoiceMinutes, Es) => "Voz {} min",
+            (Msg::Sms, _) => "SMS {}",
+            (Msg::NoUsage, En) => "No usage yet.",
+            (Msg::NoUsage, Id) => "Belum ada pemakaian.",
+            (Msg::NoUsage, Es) => "Aún no hay consumo.",
+            (Msg::Wallet, En) => "Wallet: {} {}",
+            (Msg::Wallet, Id) => "Dompet: {} {}",
+            (Msg::Wallet, Es) => "Monedero: {} {}",
+            (Msg::Error, En | Es) => "Error: {}",
+            (Msg::Error, Id) => "Galat: {}",
+            (Msg::SessionEnded, En) => "Session ended.",
+            (Msg::SessionEnded, Id) => "Sesi berakhir.",
+            (Msg::SessionEnded, Es) => "Sesión finalizada.",
+            (Msg::InvalidChoice, En) => "Invalid choice.",
+            (Msg::InvalidChoice, Id) => "Pilihan tidak valid.",
+            (Msg::InvalidChoice, Es) => "Opción no válida.",
+            (Msg::NoUssdSession, En) => "No USSD session. Dial *100# to start.",
+            (Msg::NoUssdSession, Id) => "Tidak ada sesi USSD. Tekan *100# untuk memulai.",
+            (Msg::NoUssdSession, Es) => "No hay sesión USSD. Marca *100# para empezar.",
+            (Msg::InvalidMmi, En) => "Connection problem or invalid MMI code.",
+            (Msg::InvalidMmi, Id) => "Masalah koneksi atau kode MMI tidak valid.",
+            (Msg::InvalidMmi, Es) => "Problema de conexión o código MMI no válido.",
+            (Msg::Back, En) => "Back",
+            (Msg::Back, Id) => "Kembali",
+            (Msg::Back, Es) => "Atrás",
+            (Msg::Services, En) => "Services",
+            (Msg::Services, Id) => "Layanan",
+            (Msg::Services, Es) => "Servicios",
+            (Msg::BuyData, En) => "Buy data",
+            (Msg::BuyData, Id) => "Beli paket data",
+            (Msg::BuyData, Es) => "Comprar datos",
+            (Msg::BalanceMenu, En) => "Balance",
+            (Msg::BalanceMenu, Id | Es) => "Saldo",
+            (Msg::WalletMenu, En) => "Wallet",
+            (Msg::WalletMenu, Id) => "Dompet",
+            (Msg::WalletMenu, Es) => "Monedero",
+            (Msg::UsageHistory, En) => "Usage history",
+            (Msg::UsageHistory, Id) => "Riwayat pemakaian",
+            (Msg::UsageHistory, Es) => "Historial de consumo",
+            (Msg::Plans, En) => "Plans",
+            (Msg::Plans, Id) => "Paket",
+            (Msg::Plans, Es) => "Planes",
+            (Msg::Toppings, En) => "Toppings",
+            (Msg::Toppings, Id) => "Paket tambahan",
+            (Msg::Toppings, Es) => "Complementos",
+            (Msg::Category(QuotaType::General), En | Es) => "General",
+            (Msg::Category(QuotaType::General), Id) => "Umum",
+            (Msg::Category(QuotaType::Social), Id) => "Sosial",
+            (Msg::Category(QuotaType::Social), _) => "Social",
+            (Msg::Category(QuotaType::Video), Es) => "Vídeo",
+            (Msg::Category(QuotaType::Video), _) => "Video",
+            (Msg::Category(QuotaType::Voice), En) => "Voice",
+            (Msg::Category(QuotaType::Voice), Id) => "Telepon",
+            (Msg::Category(QuotaType::Voice), Es) => "Voz",
+            (Msg::Category(QuotaType::Sms), _) => "SMS",
+            (Msg::ErrorKind(ErrorKind::Validation), Id) => "Permintaan tidak valid",
+            (Msg::ErrorKind(ErrorKind::Validation), _) => "Solicitud no válida",
+            (Msg::ErrorKind(ErrorKind::Balance), Id) => "Saldo tidak mencukupi",
+            (Msg::ErrorKind(ErrorKind::Balance), _) => "Saldo insuficiente",
+            (Msg::ErrorKind(ErrorKind::AccountState), Id) => "Akun tidak dapat memproses permintaan ini",
+            (Msg::ErrorKind(ErrorKind::AccountState), _) => "La cuenta no puede procesar esta solicitud",
+            (Msg::ErrorKind(ErrorKind::Connectivity), Id) => "Tidak ada layanan jaringan",
+            (Msg::ErrorKind(ErrorKind::Connectivity), _) => "Sin servicio de red",
+            (Msg::ErrorKind(ErrorKind::RateLimit), Id) => "Terlalu banyak permintaan",
+            (Msg::ErrorKind(ErrorKind::RateLimit), _) => "Demasiadas solicitudes",
+            (Msg::ErrorKind(ErrorKind::Storage), Id) => "Penyimpanan gagal",
+            (Msg::ErrorKind(ErrorKind::Storage), _) => "Error de almacenamiento",
+            (Msg::ErrorKind(ErrorKind::Security), Id) => "Ditolak demi keamanan",
+            (Msg::ErrorKind(ErrorKind::Security), _) => "Rechazado por seguridad",
+            (Msg::ErrorKind(ErrorKind::Internal), Id) => "Terjadi kesalahan internal",
+            (Msg::ErrorKind(ErrorKind::Internal), _) => "Error interno",
+        }
+    }
+}
+
+/// Number conventions and message catalog for ready-made strings, derived from a BCP 47 tag such as "fr-FR" or "ar".
+#[derive(Clone, Debug)]
+struct Locale {
+    tag: String,
+    language: Language,
+    decimal_comma: bool,
+    octets: bool,
+    rtl: bool,
+}
+
+impl Locale {
+    fn parse(tag: &str) -> Self {
+        let lang = tag.split(['-', '_']).next().unwrap_or("").to_lowercase();
+        Self {
+            tag: tag.to_string(),
+            language: match lang.as_str() { "id" | "in" => Language::Id, "es" => Language::Es, _ => Language::En },
+            decimal_comma: ["fr", "de", "es", "it", "pt", "id", "nl", "ru", "tr", "pl", "sv", "da", "fi", "nb", "cs"].contains(&lang.as_str()),
+            octets: lang == "fr",
+            rtl: ["ar", "he", "fa", "ur"].contains(&lang.as_str()),
+        }
+    }
+
+    fn format_bytes(&self, bytes: u64) -> String {
+        let mut text = format_bytes(bytes);
+        if self.decimal_comma { text = text.replace('.', ","); }
+        if self.octets { text = text.replace('B', "o"); }
+        self.isolate(text)
+    }
+
+    fn format_count(&self, n: u64) -> String {
+        self.isolate(n.to_string())
+    }
+
+    fn format_money(&self, cents: u64) -> String {
+        let text = format!("{}.{:02}", cents / 100, cents % 100);
+        self.isolate(if self.decimal_comma { text.replace('.', ",") } else { text })
+    }
+
+    // Fills the template's `{}` slots in order.
+    fn text(&self, msg: Msg, args: &[&dyn std::fmt::Display]) -> String {
+        let mut text = String::new();
+        for (i, piece) in self.language.template(msg).split("{}").enumerate() {
+            if let Some(arg) = i.checked_sub(1).and_then(|i| args.get(i)) { text += &arg.to_string(); }
+            text += piece;
+        }
+        text
+    }
+
+    // English keeps the error's own text; other languages lead with a translated summary of its kind.
+    fn error(&self, error: &TelcoError) -> String {
+        let detail = match self.language {
+            Language::En => error.to_string(),
+            _ => format!("{} ({})", self.text(Msg::ErrorKind(error.info().kind), &[]), error),
+        };
+        self.text(Msg::Error, &[&detail])
+    }
+
+    // First-strong isolates keep numbers and units in order when embedded in right-to-left text.
+    fn isolate(&self, text: String) -> String {
+        if self.rtl { format!("\u{2068}{}\u{2069}", text) } else { text }
+    }
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum RegionPreset { Global, UnitedStates, Indonesia, EuropeanUnion, Brazil }
+
+impl RegionPreset {
+    fn profile(&self) -> RegionProfile {
+        let (name, currency, latency_min_ms, latency_max_ms, zones): (&str, &str, u32, u32, &[(&str, u32)]) = match self {
+            RegionPreset::Global => ("Global", "USD", 40, 60, &[("Neighbouring", 200), ("Rest of World", 500)]),
+            RegionPreset::UnitedStates => ("United States", "USD", 30, 70, &[("Canada", 150), ("Mexico", 200), ("Europe", 400)]),
+            RegionPreset::Indonesia => ("Indonesia", "IDR", 45, 120, &[("Malaysia", 200), ("Singapore", 200), ("Saudi Arabia", 500)]),
+            RegionPreset::EuropeanUnion => ("European Union", "EUR", 25, 55, &[("EU (Roam like at Home)", 100), ("United Kingdom", 200), ("Switzerland", 400)]),
+            RegionPreset::Brazil => ("Brazil", "BRL", 40, 110, &[("Argentina", 200), ("United States", 300), ("Portugal", 400)]),
+        };
+        RegionProfile {
+            name: name.to_string(),
+            currency: currency.to_string(),
+            latency_min_ms,
+            latency_max_ms,
+            roaming_zones: zones.iter().map(|&(name, multiplier_percent)| RoamingZone { name: name.to_string(), multiplier_percent }).collect(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NetworkGeneration { TwoG, ThreeG, FourG, FiveG }
+
+impl NetworkGeneration {
+    fn profile(&self) -> NetworkProfile {
+        let (latency_ms, jitter_ms, max_throughput_kbps) = match self {
+            NetworkGeneration::TwoG => (450, 120, 200),
+            NetworkGeneration::ThreeG => (120, 40, 8_000),
+            NetworkGeneration::FourG => (46, 12, 60_000),
+            NetworkGeneration::FiveG => (14, 4, 900_000),
+        };
+        NetworkProfile { generation: *self, latency_ms, jitter_ms, max_throughput_kbps }
+    }
+}
+
+/// Radio conditions for a network generation. `latency_ms` is for the Global region; other regions scale it by
+/// their own midpoint latency.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct NetworkProfile {
+    pub generation: NetworkGeneration,
+    pub latency_ms: u32,
+    /// Each latency sample lands within this many ms either side of the baseline.
+    pub jitter_ms: u32,
+    pub max_throughput_kbps: u32,
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Eq, Hash)]
+pub enum ApnKind { Default, Ims, Mms }
+
+impl ApnKind {
+    // Access point name the simulated network accepts for each service.
+    fn expected_apn(&self) -> &'static str {
+        match self { ApnKind::Default => "internet", ApnKind::Ims => "ims", ApnKind::Mms => "mms" }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ApnConfig {
+    pub name: String,
+    pub apn: String,
+    pub kind: ApnKind,
+    pub selected: bool,
+}
+
+fn default_apns() -> Vec<ApnConfig> {
+    [ApnKind::Default, ApnKind::Ims, ApnKind::Mms].iter()
+        .map(|kind| ApnConfig { name: format!("Operator {:?}", kind), apn: kind.expected_apn().to_string(), kind: *kind, selected: true })
+        .collect()
+}
+
+#[derive(Clone, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum SimRequestKind {
+    SimSwap { new_iccid: String },
+    PortIn { msisdn: String, donor_operator: String },
+    PortOut { recipient_operator: String },
+}
+
+/// Pending -> Verification -> Complete, or Cancelled from any non-final state.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq, Serialize, Deserialize)]
+pub enum SimRequestState { Pending, Verification, Complete, Cancelled }
+
+#[derive(Clone, Debug, uniffi::Record, Serialize, Deserialize)]
+pub struct SimRequest {
+    pub id: String,
+    pub kind: SimRequestKind,
+    pub state: SimRequestState,
+    pub created_at: u64,
+    /// One-time code "sent" to the subscriber once the request enters verification; cleared when the request completes
+    /// or is cancelled.
+    pub verification_code: Option<String>,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct SimIdentity {
+    pub iccid: String,
+    pub msisdn: String,
+    pub operator: String,
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum LeaderboardMetric { HeaviestUsers, TopSavers }
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct LeaderboardEntry {
+    pub account_id: String,
+    pub value: u64,
+}
+
+/// Usage pattern a `FleetOrchestrator` member is generated with.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum Persona { HeavyStreamer, LightUser, Roamer }
+
+impl Persona {
+    const ALL: [Persona; 3] = [Persona::HeavyStreamer, Persona::LightUser, Persona::Roamer];
+
+    fn preset(&self) -> OperatorPreset {
+        match self {
+            Persona::HeavyStreamer => OperatorPreset::StreamerPostpaid,
+            Persona::LightUser | Persona::Roamer => OperatorPreset::BudgetPrepaid,
+        }
+    }
+
+    fn slug(&self) -> &'static str {
+        match self { Persona::HeavyStreamer => "streamer", Persona::LightUser => "light", Persona::Roamer => "roamer" }
+    }
+
+    // One traffic tick's session, if the persona is online this tick.
+    fn next_session(&self) -> Option<(u64, QuotaType)> {
+        let roll = random_u64() % 100;
+        let mb = |lo: u64, hi: u64| megabytes(lo + random_u64() % (hi - lo + 1));
+        match self {
+            Persona::HeavyStreamer if roll < 70 => Some((mb(20, 60), QuotaType::Video)),
+            Persona::HeavyStreamer => Some((mb(2, 10), QuotaType::General)),
+            Persona::LightUser if roll < 15 => Some((mb(1, 3), QuotaType::Social)),
+            Persona::LightUser if roll < 30 => Some((mb(1, 2), QuotaType::General)),
+            Persona::LightUser => None,
+            // Roamers mostly sip data abroad, with the occasional map or upload burst.
+            Persona::Roamer if roll < 40 => Some((mb(1, 4), QuotaType::General)),
+            Persona::Roamer if roll < 45 => Some((mb(15, 30), QuotaType::General)),
+            Persona::Roamer => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct FleetMember {
+    pub account_id: String,
+    pub persona: Persona,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct PersonaStats {
+    pub persona: Persona,
+    pub members: u32,
+    pub used_bytes: u64,
+    pub remaining_bytes: u64,
+    pub rejected_sessions: u64,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct FleetStats {
+    pub members: u32,
+    pub ticks: u64,
+    pub used_bytes: u64,
+    pub remaining_bytes: u64,
+    /// Members with no data balance left.
+    pub exhausted_members: u32,
+    pub throttled_members: u32,
+    /// Sessions the engine refused, e.g. for lack of balance.
+    pub rejected_sessions: u64,
+    pub by_persona: Vec<PersonaStats>,
+}
+
+struct FleetSim {
+    member: FleetMember,
+    sim: Arc<TelcoSimulator>,
+    used_bytes: AtomicU64,
+    rejected: AtomicU64,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Deserialize)]
+struct JournalEntry {
+    seq: u64,
+    account: UserAccount,
+    #[serde(deserialize_with = "one_or_many_usage")]
+    usage: Vec<UsageEntry>,
+}
+
+// Written form of `JournalEntry`, borrowed so appending doesn't copy the account.
+#[cfg(feature = "sqlite")]
+#[derive(Serialize)]
+struct JournalLine<'a> {
+    seq: u64,
+    account: &'a UserAccount,
+    usage: &'a [UsageEntry],
+}
+
+// Entries journaled before batched usage carry a single optional entry.
+#[cfg(feature = "sqlite")]
+fn one_or_many_usage<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<UsageEntry>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany { Many(Vec<UsageEntry>), One(Option<UsageEntry>) }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::Many(usage) => usage,
+        OneOrMany::One(usage) => usage.into_iter().collect(),
+    })
+}
+
+/// Append-only log of state changes not yet confirmed by the persistence worker.
+#[cfg(feature = "sqlite")]
+struct IntentJournal {
+    file: Option<std::fs::File>,
+    next_seq: u64,
+}
+
+#[cfg(feature = "sqlite")]
+impl IntentJournal {
+    // Returns entries newer than the last seq the database has applied, oldest first.
+    fn open(path: &str, applied_seq: u64) -> (Self, Vec<JournalEntry>) {
+        let recovered: Vec<JournalEntry> = std::fs::read_to_string(path)
+            .map(|content| content.lines().filter_map(|l| serde_json::from_str::<JournalEntry>(l).ok()).filter(|e| e.seq > applied_seq).collect())
+            .unwrap_or_default();
+        let next_seq = recovered.last().map(|e| e.seq).unwrap_or(applied_seq) + 1;
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path).ok();
+        (Self { file, next_seq }, recovered)
+    }
+
+    fn append(&mut self, account: &UserAccount, usage: &[UsageEntry]) -> u64 {
+        use std::io::Write;
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if let (Some(file), Ok(mut line)) = (self.file.as_mut(), serde_json::to_string(&JournalLine { seq, account, usage })) {
+            line.push('\n');
+            let _ = file.write_all(line.as_bytes());
+        }
+        seq
+    }
+
+    // Once the worker has applied the newest entry, the journal holds nothing the db lacks.
+    fn truncate_if_applied(&mut self, applied_seq: u64) {
+        if applied_seq + 1 == self.next_seq {
+            if let Some(file) = &self.file { let _ = file.set_len(0); }
+        }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+struct Persistence {
+    tx: mpsc::SyncSender<PersistenceMsg>,
+    // The receiver outlives any one worker thread so a replacement can pick up the queued writes.
+    rx: Arc<Mutex<mpsc::Receiver<PersistenceMsg>>>,
+    /// `None` once the simulator is closed.
+    worker: Mutex<Option<thread::JoinHandle<()>>>,
+    closed: AtomicBool,
+    stats: Arc<WriterStats>,
+    journal: Arc<Mutex<IntentJournal>>,
+    config: Arc<RwLock<DbConfig>>,
+    read_pool: ReadPool,
+    // An in-memory database is freed once its last connection closes, so one stays open for the simulator's life.
+    _anchor: Option<Mutex<Connection>>,
+}
+
+/// Idle read connections kept open between queries.
+#[cfg(feature = "sqlite")]
+const READ_POOL_SIZE: usize = 4;
+
+#[cfg(feature = "sqlite")]
+#[derive(Default)]
+struct ReadPool {
+    idle: Mutex<Vec<Connection>>,
+    // Bumped when the key or pragmas change, so connections checked out before then are closed instead of returned.
+    generation: AtomicU64,
+}
+
+#[cfg(feature = "sqlite")]
+impl ReadPool {
+    fn reset(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        self.idle.lock().clear();
+    }
+}
+
+/// A read connection that goes back to its pool when dropped. Ephemeral simulators' in-memory ones are just closed.
+#[cfg(feature = "sqlite")]
+struct PooledConnection<'a> {
+    conn: Option<Connection>,
+    pool: Option<(&'a ReadPool, u64)>,
+}
+
+#[cfg(feature = "sqlite")]
+impl std::ops::Deref for PooledConnection<'_> {
+    type Target = Connection;
+    fn deref(&self) -> &Connection { self.conn.as_ref().expect("connection taken") }
+}
+
+#[cfg(feature = "sqlite")]
+impl std::ops::DerefMut for PooledConnection<'_> {
+    fn deref_mut(&mut self) -> &mut Connection { self.conn.as_mut().expect("connection taken") }
+}
+
+#[cfg(feature = "sqlite")]
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        let (Some(conn), Some((pool, generation))) = (self.conn.take(), self.pool) else { return; };
+        if pool.generation.load(Ordering::SeqCst) != generation { return; }
+        let mut idle = pool.idle.lock();
+        if idle.len() < READ_POOL_SIZE { idle.push(conn); }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Persistence {
+    // For requests the worker answers itself; after `close` nothing would ever read them.
+    fn send_request(&self, msg: PersistenceMsg) -> Result<(), TelcoError> {
+        if self.closed.load(Ordering::SeqCst) { return Err(TelcoError::StorageUnavailable); }
+        // Counted before it's sent: the worker may take it, and decrement, before `send` returns.
+        self.stats.queued.fetch_add(1, Ordering::Relaxed);
+        self.tx.send(msg).map_err(|_| {
+            self.stats.queued.fetch_sub(1, Ordering::Relaxed);
+            TelcoError::StorageUnavailable
+        })
+    }
+}
+
+/// Most queued messages the writer applies in one transaction.
+#[cfg(feature = "sqlite")]
+const WRITE_BATCH_MAX: usize = 256;
+
+/// How long `close` waits for the writer to drain its queue.
+#[cfg(feature = "sqlite")]
+const CLOSE_TIMEOUT_SECS: u64 = 5;
+
+// Shared with the writer thread and any replacement the watchdog spawns.
+#[cfg(feature = "sqlite")]
+#[derive(Default)]
+struct WriterStats {
+    heartbeat_ms: AtomicU64,
+    queued: AtomicU64,
+    last_write_at: AtomicU64,
+    dropped_writes: AtomicU64,
+    failed_writes: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    error_handler: RwLock<Option<Box<dyn PersistenceErrorHandler>>>,
+    // `ChaosConfig::write_failure_percent`, read by the worker for each write.
+    write_fault_percent: AtomicU8,
+}
+
+#[cfg(feature = "sqlite")]
+impl WriterStats {
+    fn record_failure(&self, e: TelcoError) {
+        self.failed_writes.fetch_add(1, Ordering::Relaxed);
+        *self.last_error.lock() = Some(e.to_string());
+        let failure = match &e { TelcoError::DatabaseError { failure, .. } => *failure, _ => StorageFailure::Other };
+        self.report(PersistenceFailure::WriteFailed { failure, message: e.to_string() });
+    }
+
+    fn report(&self, failure: PersistenceFailure) {
+        engine_log!(LogLevel::Warn, "persistence", "{:?}", failure);
+        if let Some(handler) = &*self.error_handler.read() { handler.on_persistence_error(failure); }
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn journal_path(db_path: &str, account_id: &str) -> String {
+    format!("{}-{:016x}.journal", db_path, stable_hash(account_id))
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct CompactionReport {
+    pub rows_before: u64,
+    pub rows_after: u64,
+}
+
+/// Bounds on raw usage rows per account; `None` leaves that bound off. Rows past either bound are rolled into
+/// daily `UsageSource::Rollup` rows by `prune_history`, which don't count towards `max_rows`.
+#[derive(Clone, Copy, Debug, Default, uniffi::Record)]
+pub struct RetentionPolicy {
+    pub max_rows: Option<u64>,
+    pub max_age_secs: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct PruneReport {
+    pub rows_pruned: u64,
+    /// Daily rows written (or rewritten) to hold the pruned usage.
+    pub rollup_rows: u64,
+}
+
+/// How often the scheduler applies the retention policy.
+const PRUNE_INTERVAL_SECS: u64 = 3600;
+
+/// Pragmas applied to the writer's connection and every pooled read connection.
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct DbConfig {
+    /// How long a connection waits on another's lock before failing with `SQLITE_BUSY`.
+    pub busy_timeout_ms: u32,
+    pub synchronous: SynchronousMode,
+    /// Write-ahead logging, so reads don't block on (or block) the writer.
+    pub wal: bool,
+}
+
+impl Default for DbConfig {
+    fn default() -> Self {
+        Self { busy_timeout_ms: 5000, synchronous: SynchronousMode::Normal, wal: true }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, uniffi::Enum)]
+pub enum SynchronousMode { Off, Normal, Full }
+
+#[derive(Default)]
+struct PersistedState {
+    events: Vec<TelcoEvent>,
+    ad_rewards: Vec<(u64, u64)>,
+    achievements: Vec<AchievementRecord>,
+    experiment_assignments: HashMap<String, String>,
+    category_rules: Vec<CategoryRule>,
+    trashed_buckets: Vec<TrashedBucket>,
+    pending_orders: Vec<ScheduledOrder>,
+    snapshots: Vec<StoredSnapshot>,
+    redeemed_tokens: Vec<String>,
+    subscriptions: Vec<Subscription>,
+    preferences: HashMap<String, PreferenceValue>,
+    insight_config: Option<InsightConfig>,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct ExperimentAssignment {
+    pub experiment: String,
+    pub variant: String,
+}
+
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct TelemetryCounter {
+    pub name: String,
+    pub count: u64,
+}
+
+/// Aggregate feature counters with no account identifiers, ready for a consented upload.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct TelemetryReport {
+    pub collected_since: u64,
+    pub counters: Vec<TelemetryCounter>,
+}
+
+#[derive(Default)]
+struct Telemetry {
+    enabled: bool,
+    collected_since: u64,
+    counters: HashMap<&'static str, u64>,
+}
+
+const INSIGHT_CACHE_TTL_SECS: u64 = 300;
+
+/// Methods that can be given a simulated backend delay with `set_response_delay`.
+pub const DELAYABLE_APIS: [&str; 10] = ["get_account_info", "simulate_usage", "simulate_usage_batch", "handle_command", "execute_command", "handle_ussd", "buy_plan", "subscribe", "redeem_share_token", "top_up_wallet"];
+
+/// Wall-clock delay added before a call returns: `base_ms` plus up to `jitter_ms` at random.
+#[derive(Clone, Copy, Debug, uniffi::Record, PartialEq)]
+pub struct ResponseDelay {
+    pub base_ms: u32,
+    pub jitter_ms: u32,
+}
+
+/// Faults injected on purpose so hosts can test their retry and recovery UX; see `set_chaos_config`. Percentages
+/// are 0-100 and drawn from the same generator as `set_random_seed`.
+#[derive(Clone, Copy, Debug, Default, uniffi::Record, PartialEq)]
+pub struct ChaosConfig {
+    /// Background writes that fail as a busy database, reported like real ones through `PersistenceErrorHandler`.
+    pub write_failure_percent: u8,
+    /// Database reads that return a transient `DatabaseError` (failure `Busy`) instead of running.
+    pub read_failure_percent: u8,
+    /// Wall-clock wait before each event, update and delta callback. Not applied on wasm.
+    pub callback_delay_ms: u32,
+}
+
+#[cfg(feature = "sqlite")]
+fn injected_fault(percent: u8) -> bool {
+    percent > 0 && random_u64() % 100 < percent as u64
+}
+
+// The 7-day average behind the status insight, with the (simulated) time it was computed. Usage drops it early.
+struct InsightCache {
+    ttl_secs: u64,
+    daily_average: Option<(u64, u64)>,
+}
+
+impl Default for InsightCache {
+    fn default() -> Self {
+        Self { ttl_secs: INSIGHT_CACHE_TTL_SECS, daily_average: None }
+    }
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum RecommendationKind {
+    /// Fewer days left than the recommendation threshold (3, or 7 in the "aggressive" experiment variant).
+    TopUpSoon,
+    /// No data used in the last 7 days, so there is nothing to forecast from yet.
+    StartUsingData,
+}
+
+/// Structured form of the status insight.
+#[derive(Clone, Debug, uniffi::Record)]
+pub struct TelcoInsight {
+    pub remaining_bytes: u64,
+    /// Average data use per day under the forecast config (by default, the flat average of the last 7 days).
+    pub daily_average_bytes: u64,
+    pub estimated_days_left: Option<u64>,
+    pub recommendation: Option<RecommendationKind>,
+}
+
+/// Days-left forecast for one data category against its own buckets.
+#[derive(Clone, Copy, Debug, uniffi::Record, PartialEq)]
+pub struct CategoryForecast {
+    pub category: QuotaType,
+    /// Unexpired balance of buckets of this category; usage that spills into General buckets isn't counted here.
+    pub remaining_bytes: u64,
+    /// Average use per day of this category under the forecast config.
+    pub daily_average_bytes: u64,
+    pub estimated_days_left: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum ForecastStrategy {
+    /// Flat average over the window.
+    SimpleAverage,
+    /// Exponentially weighted average of the window's complete days; an `alpha` near 1 follows the latest days
+    /// closely, one near 0 barely moves.
+    Ewma { alpha: f64 },
+    /// Average of the days in the window that fall on the same weekday as today, for weekly habits. Windows
+    /// shorter than a week fall back to the flat average.
+    WeekdayAware,
+}
+
+/// How the daily average behind days-left estimates is computed. The window comes from `InsightConfig`.
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct ForecastConfig {
+    pub strategy: ForecastStrategy,
+}
+
+impl Default for ForecastConfig {
+    fn default() -> Self {
+        Self { strategy: ForecastStrategy::SimpleAverage }
+    }
+}
+
+impl ForecastConfig {
+    // Averages per-day totals (keyed by day number) of the window's complete days.
+    #[cfg(feature = "sqlite")]
+    fn daily_average(&self, days: &HashMap<u64, u64>, today: u64, window: u64) -> u64 {
+        let on = |day: u64| days.get(&day).copied().unwrap_or(0);
+        match self.strategy {
+            ForecastStrategy::Ewma { alpha } => (today.saturating_sub(window)..today)
+                .map(|day| on(day) as f64)
+                .reduce(|average, x| alpha * x + (1.0 - alpha) * average)
+                .unwrap_or(0.0) as u64,
+            ForecastStrategy::WeekdayAware if window >= 7 => {
+                let same_weekday: Vec<u64> = (1..=window / 7).filter_map(|weeks| today.checked_sub(weeks * 7)).map(on).collect();
+                same_weekday.iter().sum::<u64>() / same_weekday.len().max(1) as u64
+            }
+            _ => days.values().sum::<u64>() / window,
+        }
+    }
+}
+
+/// Tuning for the status insight, saved with the account.
+#[derive(Clone, Copy, Debug, uniffi::Record, PartialEq, Serialize, Deserialize)]
+pub struct InsightConfig {
+    /// Days of history the daily average is taken over.
+    pub window_days: u32,
+    /// Recommend a top-up when fewer days than this are left. The "aggressive" arm of `RECOMMENDATION_EXPERIMENT`
+    /// raises it to at least 7.
+    pub top_up_threshold_days: u32,
+    /// No forecast until the account's first usage is at least this many days old.
+    pub min_history_days: u32,
+}
+
+impl Default for InsightConfig {
+    fn default() -> Self {
+        Self { window_days: 7, top_up_threshold_days: 3, min_history_days: 0 }
+    }
+}
+
+/// Experiment gating how early the status insight recommends a top-up. Variants: "control", "aggressive".
+pub const RECOMMENDATION_EXPERIMENT: &str = "recommendation_aggressiveness";
+
+#[derive(Clone, Copy, Debug, uniffi::Record)]
+pub struct AdRewardPolicy {
+    pub max_per_day: u32,
+    pub cooldown_secs: u64,
+    pub max_bytes_per_cycle: u64,
+}
+
+impl Default for AdRewardPolicy {
+    fn default() -> Self {
+        Self { max_per_day: 5, cooldown_secs: 300, max_bytes_per_cycle: 1_000_000_000 }
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen)]
+#[derive(uniffi::Object)]
+pub struct TelcoSimulator {
+    state: Arc<RwLock<UserAccount>>,
+    db_path: String,
+    db_key: Arc<RwLock<Option<SecretString>>>,
+    update_handler: RwLock<Option<Box<dyn TelcoLiveUpdateHandler>>>,
+    delta_handler: RwLock<Option<Box<dyn AccountDeltaHandler>>>,
+    low_balance_bytes: AtomicU64,
+    event_handler: RwLock<Option<Box<dyn TelcoEventHandler>>>,
+    lifecycle_hooks: RwLock<Option<Box<dyn LifecycleHooks>>>,
+    quiet_hours: RwLock<Option<QuietHours>>,
+    event_buffer: Mutex<Vec<TelcoEvent>>,
+    ad_reward_policy: RwLock<AdRewardPolicy>,
+    ad_rewards: Mutex<Vec<(u64, u64)>>,
+    achievement_config: RwLock<AchievementConfig>,
+    budget: Mutex<BudgetTracker>,
+    caps: Mutex<CapTracker>,
+    notification_thresholds: RwLock<NotificationThresholds>,
+    quota_watch: Mutex<QuotaWatch>,
+    achievements: Mutex<Vec<AchievementRecord>>,
+    experiments: RwLock<HashMap<String, Vec<String>>>,
+    experiment_assignments: Mutex<HashMap<String, String>>,
+    telemetry: Mutex<Telemetry>,
+    insight_cache: Mutex<InsightCache>,
+    forecast_config: RwLock<ForecastConfig>,
+    insight_config: RwLock<InsightConfig>,
+    response_delays: RwLock<HashMap<String, ResponseDelay>>,
+    chaos: RwLock<ChaosConfig>,
+    operator: RwLock<OperatorProfile>,
+    region: RwLock<RegionProfile>,
+    network: RwLock<NetworkGeneration>,
+    roaming_zone: RwLock<Option<RoamingZone>>,
+    signal_mode: RwLock<SignalMode>,
+    signal_handler: RwLock<Option<Box<dyn SignalHandler>>>,
+    // Where registration was when the signal dropped out of coverage, to return there once it's back.
+    coverage_lost_from: Mutex<Option<RegistrationState>>,
+    apns: RwLock<Vec<ApnConfig>>,
+    sim_identity: RwLock<SimIdentity>,
+    sim_requests: Mutex<Vec<SimRequest>>,
+    vpn_policy: RwLock<VpnPolicy>,
+    sensor_interfaces: RwLock<InterfaceFilter>,
+    category_rules: RwLock<Vec<CategoryRule>>,
+    traffic_classifier: RwLock<Option<Box<dyn TrafficClassifier>>>,
+    traffic_source: RwLock<Option<Box<dyn TrafficSource>>>,
+    bucket_order: RwLock<BucketSortOrder>,
+    trash: Mutex<Vec<TrashedBucket>>,
+    ussd_session: Mutex<Option<UssdSession>>,
+    backend: RwLock<Option<Box<dyn PersistenceBackend>>>,
+    // None follows the process clock (`set_clock_source`, `set_time_scale`).
+    clock: RwLock<Option<Arc<dyn ClockSource>>>,
+    // How far `run_scenario` has moved this simulator ahead of its clock.
+    clock_offset_secs: AtomicU64,
+    pending_orders: Mutex<Vec<ScheduledOrder>>,
+    snapshots: Mutex<Vec<StoredSnapshot>>,
+    redeemed_tokens: Mutex<Vec<String>>,
+    subscriptions: Mutex<Vec<Subscription>>,
+    preferences: RwLock<HashMap<String, PreferenceValue>>,
+    grace_policy: RwLock<GracePolicy>,
+    throttle_policy: RwLock<ThrottlePolicy>,
+    rollover_policy: RwLock<RolloverPolicy>,
+    consumption_policy: RwLock<ConsumptionPolicy>,
+    change_log: Mutex<ChangeLog>,
+    trash_retention_secs: AtomicU64,
+    retention_policy: RwLock<RetentionPolicy>,
+    last_prune_at: AtomicU64,
+    locale: RwLock<Locale>,
+    diagnostics_handler: RwLock<Option<Box<dyn DiagnosticsHandler>>>,
+    sensor_heartbeat: AtomicU64,
+    #[cfg(not(target_arch = "wasm32"))]
+    sensor_worker: Mutex<Option<thread::JoinHandle<()>>>,
+    /// None for ephemeral simulators.
+    #[cfg(feature = "sqlite")]
+    persistence: Option<Persistence>,
+}
+
+impl Drop for TelcoSimulator {
+    fn drop(&mut self) {
+        if let Err(e) = self.close() { engine_log!(LogLevel::Warn, "persistence", "close on drop failed: {}", e); }
+    }
+}
+
+#[uniffi::export]
+impl TelcoSimulator {
+    /// Like `new`, but uses a bundled operator profile and grants its starter plan to empty accounts.
+    #[uniffi::constructor]
+    pub fn with_preset(id: String, db_path: String, preset: OperatorPreset) -> Result<Arc<Self>, TelcoError> {
+        let sim = Self::new(id, db_path)?;
+        sim.install_preset(preset)?;
+        Ok(sim)
+    }
+
+    #[uniffi::constructor]
+    pub fn new(id: String, db_path: String) -> Result<Arc<Self>, TelcoError> {
+        Self::open(id, db_path, None)
+    }
+
+    /// Opens a database encrypted with `key`. Fails with `WrongDatabaseKey` if the file was written with another key
+    /// or unencrypted.
+    #[uniffi::constructor]
+    pub fn new_encrypted(id: String, db_path: String, key: String) -> Result<Arc<Self>, TelcoError> {
+        register_sensitive(&key);
+        Self::open(id, db_path, Some(SecretString::from(key)))
+    }
+
+    /// A purely in-memory simulator: no database file, journal or writer thread, even when built with `sqlite`.
+    /// History queries see an empty store and nothing survives the instance. Meant for benchmarks and throwaway demos;
+    /// `new_in_memory` keeps its history.
+    #[uniffi::constructor]
+    pub fn ephemeral(id: String) -> Arc<Self> {
+        let account = UserAccount::fresh(&id);
+        Self::assemble(id, String::new(), account, PersistedState::default(), Arc::new(RwLock::new(None)), #[cfg(feature = "sqlite")] None)
+    }
+
+    /// Like `new`, but the database lives in memory: no file, journal or temp path, while history, snapshots and
+    /// backups work as they do on disk. Everything is gone once the simulator is dropped. Without `sqlite` this is
+    /// `ephemeral`.
+    #[uniffi::constructor]
+    pub fn new_in_memory(id: String) -> Result<Arc<Self>, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            register_sensitive(&id);
+            // Unlike `:memory:`, a memdb database is shared by every connection that opens the same name.
+            let db_path = format!("file:/telco-{}?vfs=memdb", new_id());
+            let db_key = Arc::new(RwLock::new(None));
+            let (account, persisted, persistence) = open_persistence(&id, &db_path, db_key.clone(), true)?;
+            Ok(Self::assemble(id, db_path, account, persisted, db_key, Some(persistence)))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            Ok(Self::ephemeral(id))
+        }
+    }
+
+    /// Keeps the account and usage history in `backend` instead of the built-in database. Saves happen inline on
+    /// every change; a failing save is logged and the in-memory state carries on.
+    #[uniffi::constructor]
+    pub fn with_backend(id: String, backend: Box<dyn PersistenceBackend>) -> Result<Arc<Self>, TelcoError> {
+        register_sensitive(&id);
+        let account = backend.load_account(id.clone())?.unwrap_or_else(|| UserAccount::fresh(&id));
+        let sim = Self::assemble(id, String::new(), account, PersistedState::default(), Arc::new(RwLock::new(None)), #[cfg(feature = "sqlite")] None);
+        *sim.backend.write() = Some(backend);
+        Ok(sim)
+    }
+
+    /// Like `new`, but this simulator's time comes from `clock` instead of the process clock, so several simulators
+    /// (or tests) can run on their own time. `set_clock_source` and `set_time_scale` don't affect it.
+    #[uniffi::constructor]
+    pub fn with_clock(id: String, db_path: String, clock: Box<dyn ClockSource>) -> Result<Arc<Self>, TelcoError> {
+        let sim = Self::new(id, db_path)?;
+        *sim.clock.write() = Some(Arc::from(clock));
+        Ok(sim)
+    }
+
+    pub fn set_update_handler(&self, handler: Box<dyn TelcoLiveUpdateHandler>) {
+        let mut lock = self.update_handler.write();
+        *lock = Some(handler);
+        let account = self.snapshot(self.state.read().clone());
+        if let Some(h) = &*lock { h.on_account_updated(account); }
+    }
+
+    pub fn set_delta_handler(&self, handler: Box<dyn AccountDeltaHandler>) {
+        *self.delta_handler.write() = Some(handler);
+    }
+
+    pub fn clear_delta_handler(&self) {
+        *self.delta_handler.write() = None;
+    }
+
+    /// Data balance below which `AccountDelta::LowBalance` is sent (default 500 MiB; 0 turns it off).
+    pub fn set_low_balance_threshold(&self, bytes: u64) {
+        self.low_balance_bytes.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn set_lifecycle_hooks(&self, hooks: Box<dyn LifecycleHooks>) {
+        *self.lifecycle_hooks.write() = Some(hooks);
+    }
+
+    pub fn clear_lifecycle_hooks(&self) {
+        *self.lifecycle_hooks.write() = None;
+    }
+
+    pub fn set_event_handler(&self, handler: Box<dyn TelcoEventHandler>) {
+        *self.event_handler.write() = Some(handler);
+        self.flush_event_digest();
+    }
+
+    pub fn set_quiet_hours(&self, quiet_hours: Option<QuietHours>) {
+        *self.quiet_hours.write() = quiet_hours;
+        self.flush_event_digest();
+    }
+
+    /// Delivers buffered events as a single digest once quiet hours are over.
+    pub fn flush_event_digest(&self) {
+        if self.in_quiet_hours() { return; }
+        let handler = self.event_handler.read();
+        let Some(h) = &*handler else { return; };
+        let events = std::mem::take(&mut *self.event_buffer.lock());
+        if events.is_empty() { return; }
+        self.persist_event_queue(Vec::new());
+        self.delay_callback();
+        h.on_digest(events);
+    }
+
+    pub fn unlock_with_biometrics(&self) {
+        let mut lock = self.state.write();
+        lock.biometric_locked = false;
+        let account = lock.clone();
+        drop(lock);
+        self.notify_and_persist(account, None);
+        self.dispatch_event(TelcoEvent::Unlocked);
+    }
+
+    /// Encrypts the database with `key`, or re-keys it if it is already encrypted. Queued writes land before the switch.
+    pub fn secure_initialize(&self, key: String) -> Result<(), TelcoError> {
+        register_sensitive(&key);
+        let key = SecretString::from(key);
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence {
+            let (reply, result) = mpsc::sync_channel(1);
+            // Idle connections hold the old key (and keep the writer from leaving WAL mode for the switch).
+            p.read_pool.reset();
+            p.send_request(PersistenceMsg::Rekey { key, reply })?;
+            let result = result.recv().map_err(|_| TelcoError::StorageUnavailable)?;
+            p.read_pool.reset();
+            return result;
+        }
+        *self.db_key.write() = Some(key);
+        Ok(())
+    }
+
+    /// Copies the whole database file (every account in it) to `path` with SQLite's online backup, after any queued
+    /// writes, and writes a manifest with its checksum next to it.
+    pub fn backup_to(&self, path: String) -> Result<BackupManifest, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence {
+            let (reply, result) = mpsc::sync_channel(1);
+            p.send_request(PersistenceMsg::Backup { path: path.clone(), reply })?;
+            result.recv().map_err(|_| TelcoError::StorageUnavailable)??;
+            let bytes = std::fs::read(&path).map_err(io_err)?;
+            let account_ids = load_account_ids(&open_connection(&path, self.db_key.read().as_ref())?);
+            let manifest = BackupManifest {
+                schema_version: SCHEMA_VERSION,
+                created_at: self.now_secs(),
+                size_bytes: bytes.len() as u64,
+                sha256: to_hex(&sha256(&bytes)),
+                account_ids,
+            };
+            let json = serde_json::to_string_pretty(&manifest).map_err(|e| TelcoError::SerializationFailed(e.to_string()))?;
+            std::fs::write(backup_manifest_path(&path), json).map_err(io_err)?;
+            engine_log!(LogLevel::Info, "backup", "backed up {} bytes", manifest.size_bytes);
+            return Ok(manifest);
+        }
+        let _ = path;
+        Err(TelcoError::StorageUnavailable)
+    }
+
+    /// Replaces the database with a `backup_to` copy and reloads this simulator from it. Refused unless the manifest
+    /// is present, the checksum matches and the backup has this build's `SCHEMA_VERSION`. Other simulators open on
+    /// the same file keep their in-memory state until reopened.
+    pub fn restore_from(&self, path: String) -> Result<BackupManifest, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence {
+            let manifest: BackupManifest = std::fs::read_to_string(backup_manifest_path(&path)).ok()
+                .and_then(|json| serde_json::from_str(&json).ok())
+                .ok_or_else(|| TelcoError::BackupRejected("missing or unreadable manifest".to_string()))?;
+            if manifest.schema_version != SCHEMA_VERSION {
+                return Err(TelcoError::BackupRejected(format!("schema version {} does not match {}", manifest.schema_version, SCHEMA_VERSION)));
+            }
+            let bytes = std::fs::read(&path).map_err(io_err)?;
+            if bytes.len() as u64 != manifest.size_bytes || to_hex(&sha256(&bytes)) != manifest.sha256 {
+                return Err(TelcoError::BackupRejected("checksum mismatch".to_string()));
+            }
+            let file_version: u32 = open_connection(&path, self.db_key.read().as_ref())?
+                .query_row("PRAGMA user_version", [], |row| row.get(0)).map_err(db_err)?;
+            if file_version != SCHEMA_VERSION {
+                return Err(TelcoError::BackupRejected(format!("schema version {} does not match {}", file_version, SCHEMA_VERSION)));
+            }
+
+            // Holding the account lock keeps new writes from landing between the restore and the reload.
+            let mut lock = self.state.write();
+            if lock.biometric_locked { return Err(TelcoError::Locked); }
+            let id = lock.id.clone();
+            let (reply, result) = mpsc::sync_channel(1);
+            p.send_request(PersistenceMsg::Restore { path, account_id: id.clone(), reply })?;
+            result.recv().map_err(|_| TelcoError::StorageUnavailable)??;
+            let conn = self.open_db()?;
+            init_schema(&conn, &id)?;
+            let account = load_account_internal(&conn, &id).unwrap_or_else(|_| UserAccount::fresh(&id));
+            *lock = account.clone();
+            drop(lock);
+            self.reload_persisted(load_persisted_state(&conn, &id));
+            drop(conn);
+            self.invalidate_insights();
+            let account = self.snapshot(account);
+            if let Some(h) = &*self.update_handler.read() { h.on_account_updated(account); }
+            engine_log!(LogLevel::Info, "backup", "restored backup from {}", manifest.created_at);
+            return Ok(manifest);
+        }
+        let _ = path;
+        Err(TelcoError::StorageUnavailable)
+    }
+
+    /// Makes `api` (one of `DELAYABLE_APIS`) wait like a real operator backend before answering, so hosts can
+    /// exercise loading states. `None` removes the delay. Not applied on wasm, where blocking would freeze the page.
+    pub fn set_response_delay(&self, api: String, delay: Option<ResponseDelay>) -> Result<(), TelcoError> {
+        if !DELAYABLE_APIS.contains(&api.as_str()) {
+            return Err(TelcoError::InvalidCommand(format!("'{}' has no simulated delay; use one of {}", api, DELAYABLE_APIS.join(", "))));
+        }
+        let mut delays = self.response_delays.write();
+        match delay {
+            Some(delay) => { delays.insert(api, delay); }
+            None => { delays.remove(&api); }
+        }
+        Ok(())
+    }
+
+    pub fn get_response_delays(&self) -> HashMap<String, ResponseDelay> {
+        self.response_delays.read().clone()
+    }
+
+    /// Starts (or, with the default config, stops) injecting faults into persistence, reads and callbacks.
+    pub fn set_chaos_config(&self, config: ChaosConfig) -> Result<(), TelcoError> {
+        if config.write_failure_percent > 100 || config.read_failure_percent > 100 {
+            return Err(TelcoError::InvalidCommand("Failure rates are percentages (0-100)".to_string()));
+        }
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence { p.stats.write_fault_percent.store(config.write_failure_percent, Ordering::Relaxed); }
+        *self.chaos.write() = config;
+        if config != ChaosConfig::default() { engine_log!(LogLevel::Warn, "chaos", "fault injection enabled: {:?}", config); }
+        Ok(())
+    }
+
+    pub fn get_chaos_config(&self) -> ChaosConfig {
+        *self.chaos.read()
+    }
+
+    pub fn get_account_info(&self) -> Result<UserAccount, TelcoError> {
+        self.simulate_response_delay("get_account_info");
+        let state = self.state.read();
+        if state.biometric_locked { return Err(TelcoError::Locked); }
+        Ok(self.snapshot(state.clone()))
+    }
+
+    /// Change sets recorded after `seq` (0 for everything still retained). Sequence numbers restart with each instance.
+    pub fn changes_since(&self, seq: u64) -> Result<ChangeFeed, TelcoError> {
+        if self.state.read().biometric_locked { return Err(TelcoError::Locked); }
+        Ok(self.change_log.lock().since(seq))
+    }
+
+    pub fn set_consumption_policy(&self, policy: ConsumptionPolicy) {
+        *self.consumption_policy.write() = policy;
+    }
+
+    pub fn get_consumption_policy(&self) -> ConsumptionPolicy {
+        self.consumption_policy.read().clone()
+    }
+
+    /// Re-publishes the account so live handlers pick up the new order immediately.
+    pub fn set_bucket_order(&self, order: BucketSortOrder) {
+        *self.bucket_order.write() = order;
+        let account = self.snapshot(self.state.read().clone());
+        if let Some(h) = &*self.update_handler.read() { h.on_account_updated(account); }
+    }
+
+    pub fn handle_command(&self, command: String) -> String {
+        self.simulate_response_delay("handle_command");
+        if self.state.read().biometric_locked { return self.locale.read().text(Msg::UnlockRequired, &[]); }
+        let cmd = command.trim().to_lowercase();
+        engine_log!(LogLevel::Debug, "command", "handling '{}'", cmd);
+        if cmd == "status" {
+            self.record_telemetry("command.status");
+            return self.generate_insight();
+        }
+        if cmd == "balance" {
+            self.record_telemetry("command.balance");
+            return self.describe_balance();
+        }
+        let verbs = Regex::new(r"(?i)^(?:history(?:\s+(\d+))?|cancel\s+(.+)|gift\s+(\d+)\s*(gb|mb)(?:\s+(general|social|youtube))?\s+to\s+(\S+))$").unwrap();
+        if let Some(caps) = verbs.captures(command.trim()) {
+            let (telemetry, reply) = if caps.get(2).is_some() {
+                ("command.cancel", self.cancel_by_name(&caps[2]))
+            } else if let Some(amount) = caps.get(3) {
+                let amount: u64 = amount.as_str().parse().unwrap_or(0);
+                let category = match caps.get(5).map(|c| c.as_str().to_lowercase()).as_deref() {
+                    Some("youtube") => QuotaType::Video,
+                    Some("social") => QuotaType::Social,
+                    _ => QuotaType::General,
+                };
+                let gift = scaled_size(amount, &caps[4])
+                    .and_then(|bytes| self.gift_data(caps[6].to_string(), bytes, category, GIFT_COMMAND_TTL_SECS).map(|t| (bytes, t)))
+                    .map(|(bytes, t)| {
+                        let locale = self.locale.read();
+                        locale.text(Msg::Gift, &[&locale.format_bytes(bytes), &&caps[6], &t.token])
+                    });
+                ("command.gift", gift)
+            } else {
+                let limit = caps.get(1).and_then(|n| n.as_str().parse().ok()).unwrap_or(5);
+                ("command.history", self.describe_history(limit))
+            };
+            return match reply {
+                Ok(reply) => {
+                    self.record_telemetry(telemetry);
+                    reply
+                }
+                Err(e) => {
+                    self.record_telemetry("command.invalid");
+                    self.locale.read().error(&e)
+                }
+            };
+        }
+        let roaming_pass = Regex::new(r"(?i)^(?:buy\s+)?roaming(?:\s+day)?\s+pass(?:\s+(.+))?$").unwrap();
+        if let Some(caps) = roaming_pass.captures(command.trim()) {
+            return match self.buy_roaming_pass(caps.get(1).map(|m| m.as_str())) {
+                Ok(plan) => {
+                    self.record_telemetry("command.roaming_pass");
+                    self.locale.read().text(Msg::Bought, &[&plan])
+                }
+                Err(e) => {
+                    self.record_telemetry("command.invalid");
+                    self.locale.read().error(&e)
+                }
+            };
+        }
+        let schedule = Regex::new(r"(?i)^activate\s+(.+?)\s+at\s+(\d{1,2}):(\d{2})$").unwrap();
+        if let Some(caps) = schedule.captures(command.trim()) {
+            let (hour, minute): (u32, u32) = (caps[2].parse().unwrap_or(99), caps[3].parse().unwrap_or(99));
+            let scheduled = next_local_time(hour, minute, self.now_secs())
+                .ok_or_else(|| TelcoError::InvalidCommand(format!("Invalid time {}:{}", &caps[2], &caps[3])))
+                .and_then(|at| self.schedule_purchase(caps[1].to_string(), at));
+            return match scheduled {
+                Ok(order) => {
+                    self.record_telemetry("command.schedule");
+                    self.locale.read().text(Msg::Scheduled, &[&order.purchase, &format!("{:02}:{:02}", hour, minute)])
+                }
+                Err(e) => {
+                    self.record_telemetry("command.invalid");
+                    self.locale.read().error(&e)
+                }
+            };
+        }
+        match self.parse_and_buy_topping(command) {
+            Ok(_) => {
+                self.record_telemetry("command.topping");
+                self.locale.read().text(Msg::ToppingAdded, &[])
+            }
+            Err(e) => {
+                self.record_telemetry("command.invalid");
+                self.locale.read().error(&e)
+            }
+        }
+    }
+
+    /// Runs a command without going through the text parser. Unlike `handle_command`, failures come back as errors.
+    pub fn execute_command(&self, command: TelcoCommand) -> Result<TelcoCommandResult, TelcoError> {
+        self.simulate_response_delay("execute_command");
+        if self.state.read().biometric_locked { return Err(TelcoError::Locked); }
+        let (message, bucket, history) = match command {
+            TelcoCommand::Status => {
+                self.record_telemetry("command.status");
+                (self.generate_insight(), None, Vec::new())
+            }
+            TelcoCommand::BuyTopping { category, bytes } => {
+                let bucket = topping_bucket(bytes, category, self.now_secs()).inspect_err(|_| self.record_telemetry("command.invalid"))?;
+                self.buy_topping(bucket.clone())?;
+                self.record_telemetry("command.topping");
+                (self.locale.read().text(Msg::ToppingAdded, &[]), Some(bucket), Vec::new())
+            }
+            TelcoCommand::History { limit } => {
+                let history = self.query_usage(UsageFilter { limit: Some(limit), ..UsageFilter::default() })?;
+                (self.locale.read().text(Msg::UsageRecords, &[&history.len()]), None, history)
+            }
+            TelcoCommand::CancelTopping { bucket_id } => {
+                let bucket = self.state.read().buckets.iter().find(|b| b.id == bucket_id).cloned();
+                self.cancel_bucket(bucket_id)?;
+                let name = bucket.as_ref().map_or_else(String::new, |b| b.name.clone());
+                (self.locale.read().text(Msg::Cancelled, &[&name]), bucket, Vec::new())
+            }
+        };
+        let account = self.snapshot(self.state.read().clone());
+        Ok(TelcoCommandResult { message, account, bucket, history })
+    }
+
+    /// How long the status insight's usage forecast is reused before it is recomputed (0 recomputes every time).
+    /// New usage always forces a recompute.
+    pub fn set_insight_cache_ttl(&self, ttl_secs: u64) {
+        let mut cache = self.insight_cache.lock();
+        cache.ttl_secs = ttl_secs;
+        cache.daily_average = None;
+    }
+
+    /// The figures behind the status insight, for UIs that write their own copy. Without the `sqlite` feature there
+    /// is no usage history, so only `remaining_bytes` is filled in.
+    pub fn get_insight(&self) -> TelcoInsight {
+        let remaining_bytes = self.state.read().data_balance_bytes;
+        if !cfg!(feature = "sqlite") {
+            return TelcoInsight { remaining_bytes, daily_average_bytes: 0, estimated_days_left: None, recommendation: None };
+        }
+        let config = *self.insight_config.read();
+        let daily_average_bytes = self.cached_daily_average();
+        let estimated_days_left = remaining_bytes.checked_div(daily_average_bytes).filter(|_| self.has_forecast_history());
+        let threshold = match self.get_experiment_variant(RECOMMENDATION_EXPERIMENT.to_string()).as_deref() {
+            Some("aggressive") => config.top_up_threshold_days.max(7),
+            _ => config.top_up_threshold_days,
+        } as u64;
+        let recommendation = match estimated_days_left {
+            None => Some(RecommendationKind::StartUsingData),
+            Some(days_left) if days_left < threshold => Some(RecommendationKind::TopUpSoon),
+            Some(_) => None,
+        };
+        TelcoInsight { remaining_bytes, daily_average_bytes, estimated_days_left, recommendation }
+    }
+
+    /// Per-category version of the status insight's forecast, for General, Social and Video.
+    pub fn get_category_forecasts(&self) -> Result<Vec<CategoryForecast>, TelcoError> {
+        let now = self.now_secs();
+        let buckets = self.state.read().buckets.clone();
+        let has_history = self.has_forecast_history();
+        [QuotaType::General, QuotaType::Social, QuotaType::Video].into_iter().map(|category| {
+            let remaining_bytes: u64 = buckets.iter().filter(|b| b.category == category && b.expiry > now).map(|b| b.remaining_bytes).sum();
+            let daily_average_bytes = self.calculate_daily_average(Some(category))?;
+            let estimated_days_left = remaining_bytes.checked_div(daily_average_bytes).filter(|_| has_history);
+            Ok(CategoryForecast { category, remaining_bytes, daily_average_bytes, estimated_days_left })
+        }).collect()
+    }
+
+    pub fn set_forecast_config(&self, config: ForecastConfig) -> Result<(), TelcoError> {
+        if let ForecastStrategy::Ewma { alpha } = config.strategy {
+            if !(alpha > 0.0 && alpha <= 1.0) { return Err(TelcoError::InvalidCommand(format!("EWMA alpha must be in (0, 1], got {}", alpha))); }
+        }
+        *self.forecast_config.write() = config;
+        self.invalidate_insights();
+        Ok(())
+    }
+
+    pub fn get_forecast_config(&self) -> ForecastConfig {
+        *self.forecast_config.read()
+    }
+
+    pub fn set_insight_config(&self, config: InsightConfig) -> Result<(), TelcoError> {
+        if config.window_days == 0 { return Err(TelcoError::InvalidCommand("Insight window must be at least one day".to_string())); }
+        *self.insight_config.write() = config;
+        self.invalidate_insights();
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            self.persist(PersistenceMsg::InsightConfig { account_id, config });
+        }
+        Ok(())
+    }
+
+    pub fn get_insight_config(&self) -> InsightConfig {
+        *self.insight_config.read()
+    }
+
+    /// Applies `config` to the writer once its queued writes land, and to read connections opened from now on.
+    pub fn set_db_config(&self, config: DbConfig) -> Result<(), TelcoError> {
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence {
+            *p.config.write() = config;
+            p.read_pool.reset();
+            p.send_request(PersistenceMsg::Configure { config })?;
+            return Ok(());
+        }
+        let _ = config;
+        Err(TelcoError::StorageUnavailable)
+    }
+
+    pub fn get_db_config(&self) -> DbConfig {
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence { return *p.config.read(); }
+        DbConfig::default()
+    }
+
+    /// Writes everything still queued, checkpoints the database and stops the background writer, waiting up to
+    /// `CLOSE_TIMEOUT_SECS`. Changes made afterwards stay in memory. Dropping the simulator closes it too; call this
+    /// to see the error. Writes left over after a timeout are replayed from the journal on the next open.
+    pub fn close(&self) -> Result<(), TelcoError> {
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence {
+            if p.closed.swap(true, Ordering::SeqCst) { return Ok(()); }
+            // A dead writer would leave the queue undrained, so a replacement takes over first.
+            {
+                let mut worker = p.worker.lock();
+                if worker.as_ref().is_some_and(|h| h.is_finished()) { *worker = Some(self.spawn_writer(p)); }
+            }
+            let busy = || TelcoError::DatabaseError {
+                failure: StorageFailure::Busy,
+                message: format!("writer still busy after {}s", CLOSE_TIMEOUT_SECS),
+            };
+            // The deadline covers getting into a full queue as well as waiting for the reply.
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(CLOSE_TIMEOUT_SECS);
+            let (reply, result) = mpsc::sync_channel(1);
+            let mut msg = PersistenceMsg::Shutdown { reply };
+            p.stats.queued.fetch_add(1, Ordering::Relaxed);
+            loop {
+                match p.tx.try_send(msg) {
+                    Ok(()) => break,
+                    Err(mpsc::TrySendError::Disconnected(_)) => {
+                        p.stats.queued.fetch_sub(1, Ordering::Relaxed);
+                        return Err(TelcoError::StorageUnavailable);
+                    }
+                    Err(mpsc::TrySendError::Full(back)) => {
+                        if std::time::Instant::now() >= deadline {
+                            p.stats.queued.fetch_sub(1, Ordering::Relaxed);
+                            return Err(busy());
+                        }
+                        msg = back;
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                    }
+                }
+            }
+            let flushed = result.recv_timeout(deadline.saturating_duration_since(std::time::Instant::now()));
+            let worker = p.worker.lock().take();
+            return match flushed {
+                Ok(result) => {
+                    if let Some(handle) = worker { let _ = handle.join(); }
+                    result
+                }
+                Err(_) => Err(busy()),
+            };
+        }
+        Ok(())
+    }
+
+    /// Ephemeral simulators never write, so the handler is never called on them.
+    pub fn set_persistence_error_handler(&self, handler: Box<dyn PersistenceErrorHandler>) {
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence { *p.stats.error_handler.write() = Some(handler); }
+        #[cfg(not(feature = "sqlite"))]
+        let _ = handler;
+    }
+
+    pub fn clear_persistence_error_handler(&self) {
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence { *p.stats.error_handler.write() = None; }
+    }
+
+    pub fn get_persistence_stats(&self) -> PersistenceStats {
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence {
+            return PersistenceStats {
+                queue_depth: p.stats.queued.load(Ordering::Relaxed),
+                dropped_writes: p.stats.dropped_writes.load(Ordering::Relaxed),
+                failed_writes: p.stats.failed_writes.load(Ordering::Relaxed),
+                last_write_at: Some(p.stats.last_write_at.load(Ordering::Relaxed)).filter(|t| *t > 0),
+                last_error: p.stats.last_error.lock().clone(),
+            };
+        }
+        PersistenceStats { queue_depth: 0, dropped_writes: 0, failed_writes: 0, last_write_at: None, last_error: None }
+    }
+
+    /// Recomputes the forecast now and returns the fresh status insight.
+    pub fn refresh_insights(&self) -> String {
+        self.invalidate_insights();
+        self.generate_insight()
+    }
+
+    /// Telemetry is off until the host records user consent; disabling discards collected counters.
+    pub fn set_telemetry_enabled(&self, enabled: bool) {
+        let mut telemetry = self.telemetry.lock();
+        if enabled && !telemetry.enabled { telemetry.collected_since = self.now_secs(); }
+        if !enabled { telemetry.counters.clear(); }
+        telemetry.enabled = enabled;
+    }
+
+    pub fn export_telemetry(&self) -> Option<TelemetryReport> {
+        let telemetry = self.telemetry.lock();
+        if !telemetry.enabled { return None; }
+        let mut counters: Vec<TelemetryCounter> = telemetry.counters.iter()
+            .map(|(name, count)| TelemetryCounter { name: name.to_string(), count: *count })
+            .collect();
+        counters.sort_by(|a, b| a.name.cmp(&b.name));
+        Some(TelemetryReport { collected_since: telemetry.collected_since, counters })
+    }
+
+    /// Clears counters after the host has uploaded a report.
+    pub fn reset_telemetry(&self) {
+        let mut telemetry = self.telemetry.lock();
+        telemetry.counters.clear();
+        telemetry.collected_since = self.now_secs();
+    }
+
+    /// `Voice` and `Sms` go through the same path as `simulate_call` and `simulate_sms`, with `bytes` as seconds or
+    /// messages.
+    pub fn simulate_usage(&self, bytes: u64, category: QuotaType) -> Result<(), TelcoError> {
+        self.simulate_response_delay("simulate_usage");
+        if !category.is_data() { return self.record_service_usage(bytes, category); }
+        self.record_usage(bytes, category, UsageSource::Manual, None)
+    }
+
+    /// Applies `events` in order under one account lock and persists them as a single write, e.g. to replay a
+    /// trace. Events refused on their own (over a cap, out of balance) are skipped and listed in the report;
+    /// refusals that cover the whole account (locked, flight mode, no data APN) fail the batch.
+    pub fn simulate_usage_batch(&self, events: Vec<UsageEvent>) -> Result<UsageBatchReport, TelcoError> {
+        self.simulate_response_delay("simulate_usage_batch");
+        let now = self.now_secs();
+        let entries = events.into_iter()
+            .map(|e| UsageEntry { bytes: e.bytes, category: e.category, timestamp: e.timestamp.unwrap_or(now), source: UsageSource::Manual, confidence: None })
+            .collect();
+        let (applied, rejected) = self.record_usage_batch(entries)?;
+        Ok(UsageBatchReport {
+            applied: applied.len() as u32,
+            applied_bytes: applied.iter().map(|u| u.bytes).sum(),
+            rejected: rejected.into_iter().map(|(index, e)| RejectedUsage { index: index as u32, error: e.info() }).collect(),
+        })
+    }
+
+    /// Feeds a trace (e.g. a customer's exported history) back through the engine in timestamp order, with the
+    /// usual events, caps and grace handling. Records are stamped with the time they are applied; gaps between
+    /// them are waited out `speed` times faster in simulated time, or skipped entirely when `speed` is 0. Records
+    /// that can't apply, an unknown category included, are reported by their index in `records`.
+    pub fn replay_usage(&self, records: Vec<UsageRecord>, speed: f64) -> Result<UsageBatchReport, TelcoError> {
+        if !speed.is_finite() || speed < 0.0 { return Err(TelcoError::InvalidCommand(format!("Replay speed must be 0 or more, got {}", speed))); }
+        let mut order: Vec<usize> = (0..records.len()).collect();
+        order.sort_by_key(|&i| records[i].timestamp);
+        let mut rejected = Vec::new();
+        let mut entries = Vec::new();
+        for &i in &order {
+            let r = &records[i];
+            match QuotaType::from_name(&r.category) {
+                Some(category) => entries.push((i, UsageEntry { bytes: r.amount, category, timestamp: 0, source: r.source, confidence: r.confidence })),
+                None => rejected.push((i, TelcoError::InvalidCommand(format!("Unknown category '{}'", r.category)).info())),
+            }
+        }
+        let mut applied = Vec::new();
+        if speed == 0.0 {
+            let now = self.now_secs();
+            let (indices, batch): (Vec<usize>, Vec<UsageEntry>) = entries.into_iter().map(|(i, e)| (i, UsageEntry { timestamp: now, ..e })).unzip();
+            match self.record_usage_batch(batch) {
+                Ok((ok, failed)) => {
+                    applied = ok;
+                    rejected.extend(failed.into_iter().map(|(j, e)| (indices[j], e.info())));
+                }
+                Err(e) => {
+                    let info = e.info();
+                    rejected.extend(indices.into_iter().map(|i| (i, info.clone())));
+                }
+            }
+        } else {
+            let mut previous = None;
+            for (i, entry) in entries {
+                let at = records[i].timestamp;
+                #[cfg(not(target_arch = "wasm32"))]
+                if let Some(gap) = previous.map(|p: u64| at.saturating_sub(p)).filter(|g| *g > 0) {
+                    sim_sleep((gap as f64 * 1000.0 / speed) as u64);
+                }
+                previous = Some(at);
+                match self.record_usage_batch(vec![UsageEntry { timestamp: self.now_secs(), ..entry }]) {
+                    Ok((ok, failed)) => {
+                        applied.extend(ok);
+                        rejected.extend(failed.into_iter().map(|(_, e)| (i, e.info())));
+                    }
+                    Err(e) => rejected.push((i, e.info())),
+                }
+            }
+        }
+        rejected.sort_by_key(|(i, _)| *i);
+        engine_log!(LogLevel::Info, "usage", "replayed {} of {} records", applied.len(), records.len());
+        Ok(UsageBatchReport {
+            applied: applied.len() as u32,
+            applied_bytes: applied.iter().map(|u| u.bytes).sum(),
+            rejected: rejected.into_iter().map(|(index, error)| RejectedUsage { index: index as u32, error }).collect(),
+        })
+    }
+
+    fn record_usage(&self, bytes: u64, category: QuotaType, source: UsageSource, confidence: Option<f64>) -> Result<(), TelcoError> {
+        let entry = UsageEntry { bytes, category, timestamp: self.now_secs(), source, confidence };
+        match self.record_usage_batch(vec![entry])?.1.pop() {
+            Some((_, e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Charges a call against the Voice buckets. There is no grace or throttling for calls.
+    pub fn simulate_call(&self, seconds: u64) -> Result<(), TelcoError> {
+        self.record_service_usage(seconds, QuotaType::Voice)
+    }
+
+    pub fn simulate_sms(&self, count: u64) -> Result<(), TelcoError> {
+        self.record_service_usage(count, QuotaType::Sms)
+    }
+
+    fn record_service_usage(&self, units: u64, kind: QuotaType) -> Result<(), TelcoError> {
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Err(TelcoError::Locked); }
+        if lock.registration == RegistrationState::Off { return Err(TelcoError::FlightMode); }
+        if !lock.registration.allows_data() { return Err(TelcoError::NotRegistered(lock.registration)); }
+        let now = self.now_secs();
+        let breach = self.caps.lock().check_usage(units, kind, now);
+        if let Some(breach) = breach {
+            drop(lock);
+            return Err(self.cap_exceeded(breach));
+        }
+        let rate_percent = self.roaming_rate(&lock);
+        let (shortfall, undo) = lock.drain(units, kind, &self.consumption_policy.read(), rate_percent, self.now_secs())?;
+        if shortfall > 0 {
+            lock.undo(undo);
+            return Err(TelcoError::InsufficientBalance);
+        }
+        let usage = vec![UsageEntry { bytes: units, category: kind, timestamp: now, source: UsageSource::Manual, confidence: None }];
+        let seq = self.write_intent(&lock, &usage);
+        self.caps.lock().record_usage(units, kind, now);
+        let account = lock.clone();
+        drop(lock);
+
+        self.run_after_usage_hook(units, kind, &account);
+        self.publish(account, usage, seq);
+        self.dispatch_event(TelcoEvent::UsageRecorded { bytes: units, category: kind, timestamp: now });
+        self.record_telemetry(if kind == QuotaType::Voice { "usage.voice" } else { "usage.sms" });
+        Ok(())
+    }
+
+    // Insight Logic
+    fn generate_insight(&self) -> String {
+        let insight = self.get_insight();
+        let locale = self.locale.read().clone();
+        let mut text = locale.text(Msg::Remaining, &[&locale.format_bytes(insight.remaining_bytes)]);
+        if !cfg!(feature = "sqlite") { text += &locale.text(Msg::InMemory, &[]); }
+        if let Some(days_left) = insight.estimated_days_left {
+            let window = self.insight_config.read().window_days.max(1);
+            text += &locale.text(Msg::DaysLeft, &[&locale.format_count(window as u64), &locale.format_count(days_left)]);
+        }
+        match insight.recommendation {
+            Some(RecommendationKind::TopUpSoon) => text += &locale.text(Msg::TopUpSoon, &[]),
+            Some(RecommendationKind::StartUsingData) => text += &locale.text(Msg::StartForecast, &[]),
+            None => {}
+        }
+        text
+    }
+
+    fn cached_daily_average(&self) -> u64 {
+        let now = self.now_secs();
+        let mut cache = self.insight_cache.lock();
+        if let Some((average, computed_at)) = cache.daily_average {
+            if now < computed_at + cache.ttl_secs { return average; }
+        }
+        let average = self.calculate_daily_average(None).unwrap_or(0);
+        cache.daily_average = Some((average, now));
+        average
+    }
+
+    fn invalidate_insights(&self) {
+        self.insight_cache.lock().daily_average = None;
+    }
+
+    // Daily average under the forecast config, for one category or (`None`) all data categories together.
+    fn calculate_daily_average(&self, category: Option<QuotaType>) -> Result<u64, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let config = *self.forecast_config.read();
+            let window = self.insight_config.read().window_days.max(1) as u64;
+            let now = self.now_secs();
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let filter = match category {
+                Some(category) => format!("category = '{:?}'", category),
+                None => "category NOT IN ('Voice', 'Sms')".to_string(),
+            };
+            if config.strategy == ForecastStrategy::SimpleAverage {
+                let mut stmt = conn.prepare(&format!("SELECT SUM(amount) FROM usage_history WHERE account_id = ?1 AND timestamp > ?2 AND {}", filter)).map_err(db_err)?;
+                let total_usage: u64 = stmt.query_row(params![account_id, now.saturating_sub(window * 86400)], |row| row.get(0)).unwrap_or(0);
+                return Ok(total_usage / window);
+            }
+            let today = now / 86400;
+            let mut stmt = conn.prepare(&format!("SELECT day, SUM(amount) FROM usage_history WHERE account_id = ?1 AND day >= ?2 AND day < ?3 AND {} GROUP BY day", filter)).map_err(db_err)?;
+            let days: HashMap<u64, u64> = stmt.query_map(params![account_id, today.saturating_sub(window), today], |row| Ok((row.get(0)?, row.get(1)?)))
+                .map_err(db_err)?
+                .filter_map(|r| r.ok())
+                .collect();
+            Ok(config.daily_average(&days, today, window))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = category;
+            Ok(0)
+        }
+    }
+
+    /// Dialer-style entry point. `*123#` shows the balance and `*100#` opens the services menu; `*123*1*2#` jumps
+    /// straight to option 1, then 2, of that menu. While a session is open, send the next choice alone ("2"),
+    /// "0" to go back or "00" to end it. Sessions close after three idle minutes.
+    pub fn handle_ussd(&self, code: String) -> UssdResponse {
+        self.simulate_response_delay("handle_ussd");
+        if self.state.read().biometric_locked { return UssdResponse { message: self.locale.read().text(Msg::UnlockRequired, &[]), session_active: false }; }
+        let input = code.trim();
+        let now = self.now_secs();
+        let session = self.ussd_session.lock().take().filter(|s| now < s.last_input_at + USSD_SESSION_TIMEOUT_SECS);
+        let path = if let Some(code) = input.strip_prefix('*').and_then(|c| c.strip_suffix('#')) {
+            let mut parts = code.split('*');
+            let choices: Option<Vec<u32>> = parts.clone().skip(1).map(|p| p.parse().ok()).collect();
+            match (parts.next(), choices) {
+                (Some("123"), Some(choices)) if choices.is_empty() => {
+                    self.record_telemetry("ussd.balance");
+                    return UssdResponse { message: self.describe_balance(), session_active: false };
+                }
+                (Some("123" | "100"), Some(choices)) => choices,
+                _ => return UssdResponse { message: self.locale.read().text(Msg::InvalidMmi, &[]), session_active: false },
+            }
+        } else {
+            let Some(mut session) = session else {
+                return UssdResponse { message: self.locale.read().text(Msg::NoUssdSession, &[]), session_active: false };
+            };
+            match input {
+                "00" => return UssdResponse { message: self.locale.read().text(Msg::SessionEnded, &[]), session_active: false },
+                "0" => { session.path.pop(); }
+                choice => match choice.parse() {
+                    Ok(choice) => session.path.push(choice),
+                    Err(_) => {
+                        let menu = self.ussd_screen(&session.path, Some(Msg::InvalidChoice));
+                        *self.ussd_session.lock() = Some(UssdSession { last_input_at: now, ..session });
+                        return menu;
+                    }
+                },
+            }
+            session.path
+        };
+        self.record_telemetry("ussd.menu");
+        let mut path = path;
+        if self.ussd_node(&path).is_none() {
+            path.pop();
+            let menu = self.ussd_screen(&path, Some(Msg::InvalidChoice));
+            if menu.session_active { *self.ussd_session.lock() = Some(UssdSession { path, last_input_at: now }); }
+            return menu;
+        }
+        let screen = self.ussd_screen(&path, None);
+        if screen.session_active { *self.ussd_session.lock() = Some(UssdSession { path, last_input_at: now }); }
+        screen
+    }
+
+    fn describe_balance(&self) -> String {
+        let account = self.state.read().clone();
+        let locale = self.locale.read();
+        let now = self.now_secs();
+        let mut parts: Vec<String> = [QuotaType::General, QuotaType::Social, QuotaType::Video].iter().filter_map(|&category| {
+            let bytes: u64 = account.buckets.iter().filter(|b| b.category == category && b.expiry > now).map(|b| b.remaining_bytes).sum();
+            (bytes > 0).then(|| format!("{} {}", locale.text(Msg::Category(category), &[]), locale.format_bytes(bytes)))
+        }).collect();
+        if account.voice_balance_secs > 0 { parts.push(locale.text(Msg::VoiceMinutes, &[&locale.format_count(account.voice_balance_secs / 60)])); }
+        if account.sms_balance > 0 { parts.push(locale.text(Msg::Sms, &[&locale.format_count(account.sms_balance)])); }
+        if parts.is_empty() { return locale.text(Msg::NoBalance, &[]); }
+        locale.text(Msg::Balance, &[&parts.join(", ")])
+    }
+
+    fn describe_history(&self, limit: u32) -> Result<String, TelcoError> {
+        let records = self.query_usage(UsageFilter { limit: Some(limit), ..UsageFilter::default() })?;
+        let locale = self.locale.read();
+        if records.is_empty() { return Ok(locale.text(Msg::NoUsage, &[])); }
+        Ok(records.iter().map(|r| {
+            let at = chrono::DateTime::from_timestamp(r.timestamp as i64, 0).map(|t| t.with_timezone(&chrono::Local).format("%m-%d %H:%M").to_string()).unwrap_or_default();
+            format!("{} {} {}", at, locale.format_bytes(r.amount), r.category)
+        }).collect::<Vec<_>>().join("\n"))
+    }
+
+    // Names match ignoring case, spaces and a trailing "Topping", so "cancel 2gb" finds "2 GB Topping". Purchased
+    // buckets win over promo ones with the same name.
+    fn cancel_by_name(&self, name: &str) -> Result<String, TelcoError> {
+        let normalize = |n: &str| {
+            let n: String = n.to_lowercase().split_whitespace().collect();
+            n.trim_end_matches("topping").to_string()
+        };
+        let wanted = normalize(name);
+        let matches = |b: &QuotaBucket| normalize(&b.name) == wanted;
+        let buckets = self.state.read().buckets.clone();
+        let bucket = buckets.iter().filter(|b| matches(b)).min_by_key(|b| b.source != BucketSource::Purchase)
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("No bucket named '{}'", name.trim())))?;
+        self.cancel_bucket(bucket.id.clone())?;
+        Ok(self.locale.read().text(Msg::Cancelled, &[&bucket.name]))
+    }
+
+    fn parse_and_buy_topping(&self, command: String) -> Result<(), TelcoError> {
+        self.buy_topping(parse_topping(&command, self.now_secs())?)
+    }
+
+    fn buy_topping(&self, bucket: QuotaBucket) -> Result<(), TelcoError> {
+        let price_cents = self.operator.read().topping_price(&bucket);
+        self.approve_purchase(&bucket, price_cents)?;
+        self.add_paid_bucket(bucket, price_cents)?;
+        self.unlock_achievement(Achievement::FirstTopUp);
+        Ok(())
+    }
+
+    /// What a topping such as "Social 2GB" costs under the current operator.
+    pub fn quote_topping(&self, command: String) -> Result<u64, TelcoError> {
+        Ok(self.operator.read().topping_price(&parse_topping(&command, self.now_secs())?))
+    }
+
+    pub fn set_topping_price(&self, category: QuotaType, cents_per_gb: u64) {
+        let mut operator = self.operator.write();
+        operator.topping_prices.retain(|p| p.category != category);
+        operator.topping_prices.push(ToppingPrice { category, cents_per_gb });
+    }
+
+    pub fn get_wallet_balance(&self) -> u64 {
+        self.state.read().wallet_cents
+    }
+
+    /// Adds money to the wallet and returns the new balance.
+    pub fn top_up_wallet(&self, cents: u64) -> Result<u64, TelcoError> {
+        self.simulate_response_delay("top_up_wallet");
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Err(TelcoError::Locked); }
+        lock.wallet_cents = lock.wallet_cents.saturating_add(cents);
+        let account = lock.clone();
+        drop(lock);
+        let balance = account.wallet_cents;
+        self.notify_and_persist(account, None);
+        Ok(balance)
+    }
+
+    /// Queues `purchase` (a catalog plan name or a topping like "Social 2GB") to activate at `activate_at`.
+    /// It is checked now, so typos fail here rather than at activation.
+    pub fn schedule_purchase(&self, purchase: String, activate_at: u64) -> Result<ScheduledOrder, TelcoError> {
+        self.resolve_purchase(&purchase)?;
+        let order = ScheduledOrder { id: new_id(), purchase: purchase.trim().to_string(), activate_at, created_at: self.now_secs() };
+        let mut orders = self.pending_orders.lock();
+        orders.push(order.clone());
+        orders.sort_by_key(|o| o.activate_at);
+        self.persist_pending_orders(orders.clone());
+        Ok(order)
+    }
+
+    pub fn list_scheduled_orders(&self) -> Vec<ScheduledOrder> {
+        self.pending_orders.lock().clone()
+    }
+
+    /// Changes what an order buys and/or when; `None` keeps the current value.
+    pub fn modify_scheduled_order(&self, order_id: String, purchase: Option<String>, activate_at: Option<u64>) -> Result<ScheduledOrder, TelcoError> {
+        if let Some(purchase) = &purchase { self.resolve_purchase(purchase)?; }
+        let mut orders = self.pending_orders.lock();
+        let order = orders.iter_mut().find(|o| o.id == order_id)
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("No scheduled order '{}'", order_id)))?;
+        if let Some(purchase) = purchase { order.purchase = purchase.trim().to_string(); }
+        if let Some(activate_at) = activate_at { order.activate_at = activate_at; }
+        let order = order.clone();
+        orders.sort_by_key(|o| o.activate_at);
+        self.persist_pending_orders(orders.clone());
+        Ok(order)
+    }
+
+    pub fn cancel_scheduled_order(&self, order_id: String) -> Result<ScheduledOrder, TelcoError> {
+        let mut orders = self.pending_orders.lock();
+        let index = orders.iter().position(|o| o.id == order_id)
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("No scheduled order '{}'", order_id)))?;
+        let order = orders.remove(index);
+        self.persist_pending_orders(orders.clone());
+        Ok(order)
+    }
+
+    /// Scheduler tick: activates every order that is due and returns how many ran. Orders that can't be bought
+    /// (e.g. the plan left the catalog) are dropped with `OrderFailed`; a locked device keeps them pending.
+    pub fn run_scheduled_orders(&self) -> u32 {
+        if self.state.read().biometric_locked { return 0; }
+        let now = self.now_secs();
+        let mut orders = self.pending_orders.lock();
+        if !orders.iter().any(|o| o.activate_at <= now) { return 0; }
+        let (due, pending): (Vec<_>, Vec<_>) = orders.drain(..).partition(|o| o.activate_at <= now);
+        *orders = pending;
+        self.persist_pending_orders(orders.clone());
+        drop(orders);
+
+        let mut activated = 0;
+        for order in due {
+            match self.resolve_purchase(&order.purchase).and_then(|bucket| self.buy_bucket(bucket.clone()).map(|_| bucket)) {
+                Ok(bucket) => {
+                    activated += 1;
+                    engine_log!(LogLevel::Info, "scheduler", "activated order {} ({})", order.id, order.purchase);
+                    self.unlock_achievement(Achievement::FirstTopUp);
+                    self.dispatch_event(TelcoEvent::OrderActivated { order, bucket });
+                }
+                Err(TelcoError::Locked) => {
+                    let mut orders = self.pending_orders.lock();
+                    orders.insert(0, order);
+                    self.persist_pending_orders(orders.clone());
+                }
+                Err(e) => {
+                    engine_log!(LogLevel::Warn, "scheduler", "order {} failed: {}", order.id, e);
+                    self.dispatch_event(TelcoEvent::OrderFailed { order, reason: e.to_string() });
+                }
+            }
+        }
+        activated
+    }
+
+    /// Buys `purchase` (a catalog plan name or a topping like "Social 5GB") now and again each time its bucket expires.
+    pub fn subscribe(&self, purchase: String) -> Result<Subscription, TelcoError> {
+        self.simulate_response_delay("subscribe");
+        let bucket = self.resolve_purchase(&purchase)?;
+        self.buy_bucket(bucket.clone())?;
+        let subscription = Subscription {
+            id: new_id(),
+            purchase: purchase.trim().to_string(),
+            bucket_id: bucket.id,
+            next_renewal_at: bucket.expiry,
+            renewals: 0,
+            created_at: self.now_secs(),
+        };
+        let mut subscriptions = self.subscriptions.lock();
+        subscriptions.push(subscription.clone());
+        subscriptions.sort_by_key(|s| s.next_renewal_at);
+        self.persist_subscriptions(subscriptions.clone());
+        Ok(subscription)
+    }
+
+    /// Stops future renewals; the current bucket runs until it expires.
+    pub fn cancel_subscription(&self, subscription_id: String) -> Result<Subscription, TelcoError> {
+        let mut subscriptions = self.subscriptions.lock();
+        let index = subscriptions.iter().position(|s| s.id == subscription_id)
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("No subscription '{}'", subscription_id)))?;
+        let subscription = subscriptions.remove(index);
+        self.persist_subscriptions(subscriptions.clone());
+        Ok(subscription)
+    }
+
+    /// Soonest renewal first.
+    pub fn list_subscriptions(&self) -> Vec<Subscription> {
+        self.subscriptions.lock().clone()
+    }
+
+    pub fn get_next_renewal(&self, subscription_id: String) -> Result<u64, TelcoError> {
+        self.subscriptions.lock().iter().find(|s| s.id == subscription_id).map(|s| s.next_renewal_at)
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("No subscription '{}'", subscription_id)))
+    }
+
+    /// Scheduler tick: renews every subscription whose bucket has expired and returns how many renewed. A renewal
+    /// missed while the scheduler was off happens once, from now. A locked device keeps renewals pending.
+    pub fn run_subscription_renewals(&self) -> u32 {
+        if self.state.read().biometric_locked { return 0; }
+        let now = self.now_secs();
+        let due: Vec<Subscription> = self.subscriptions.lock().iter().filter(|s| s.next_renewal_at <= now).cloned().collect();
+        let mut renewed = 0;
+        for subscription in due {
+            let result = self.resolve_purchase(&subscription.purchase).and_then(|bucket| self.buy_bucket(bucket.clone()).map(|_| bucket));
+            let mut subscriptions = self.subscriptions.lock();
+            // Cancelled while the bucket was being bought.
+            let Some(index) = subscriptions.iter().position(|s| s.id == subscription.id) else { continue; };
+            match result {
+                Ok(bucket) => {
+                    let current = &mut subscriptions[index];
+                    current.bucket_id = bucket.id.clone();
+                    current.next_renewal_at = bucket.expiry;
+                    current.renewals += 1;
+                    let subscription = current.clone();
+                    subscriptions.sort_by_key(|s| s.next_renewal_at);
+                    self.persist_subscriptions(subscriptions.clone());
+                    drop(subscriptions);
+                    renewed += 1;
+                    engine_log!(LogLevel::Info, "scheduler", "renewed subscription {} ({})", subscription.id, subscription.purchase);
+                    self.dispatch_event(TelcoEvent::SubscriptionRenewed { subscription, bucket });
+                }
+                Err(TelcoError::Locked) => {}
+                Err(e) => {
+                    let subscription = subscriptions.remove(index);
+                    self.persist_subscriptions(subscriptions.clone());
+                    drop(subscriptions);
+                    engine_log!(LogLevel::Warn, "scheduler", "subscription {} failed: {}", subscription.id, e);
+                    self.dispatch_event(TelcoEvent::SubscriptionFailed { subscription, reason: e.to_string() });
+                }
+            }
+        }
+        renewed
+    }
+
+    pub fn set_notification_thresholds(&self, thresholds: NotificationThresholds) {
+        *self.notification_thresholds.write() = thresholds;
+    }
+
+    pub fn get_notification_thresholds(&self) -> NotificationThresholds {
+        self.notification_thresholds.read().clone()
+    }
+
+    /// Sends any `BucketExpiringSoon` warnings now due. Balance thresholds are checked on every account update, but
+    /// expiry only gets closer with time, so the scheduler calls this each tick. Returns how many were sent.
+    pub fn check_expiry_warnings(&self) -> u32 {
+        let account = self.state.read().clone();
+        self.watch_quota(&account)
+    }
+
+    /// Calls `tick` every simulated second. Holds only a weak reference, like the watchdog.
+    pub fn start_scheduler(self: Arc<Self>) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let sim = Arc::downgrade(&self);
+            drop(self);
+            thread::spawn(move || loop {
+                sim_sleep(1000);
+                let Some(sim) = sim.upgrade() else { return; };
+                sim.tick();
+            });
+        }
+    }
+
+    /// One scheduler pass: `process_expirations`, `check_expiry_warnings`, `run_subscription_renewals`,
+    /// `run_scheduled_orders`, a fresh latency sample, a signal step, and `prune_history` when an hour has passed.
+    /// For hosts that drive time themselves (wasm, tests, game loops) instead of calling `start_scheduler`.
+    pub fn tick(&self) {
+        self.process_expirations();
+        self.check_expiry_warnings();
+        self.run_subscription_renewals();
+        self.run_scheduled_orders();
+        self.refresh_latency();
+        self.walk_signal();
+        self.prune_history_if_due();
+    }
+
+    /// Runs `scenario` from the current time. Rather than waiting, this simulator's clock is moved forward to each
+    /// step's offset and `tick` runs, so expiries and renewals due on the way fire first; other simulators keep their
+    /// time. A failing step is reported and the rest still run.
+    pub fn run_scenario(&self, scenario: Scenario) -> ScenarioReport {
+        let mut report = ScenarioReport { steps_run: 0, elapsed_secs: 0, failures: Vec::new() };
+        for step in scenario.steps {
+            if step.at_secs > report.elapsed_secs {
+                self.clock_offset_secs.fetch_add(step.at_secs - report.elapsed_secs, Ordering::Relaxed);
+                report.elapsed_secs = step.at_secs;
+                self.tick();
+            }
+            if let Err(e) = self.run_scenario_action(step.action) {
+                engine_log!(LogLevel::Debug, "scenario", "line {} failed: {}", step.line, e);
+                report.failures.push(ScenarioFailure { line: step.line, error: e.info() });
+            }
+            report.steps_run += 1;
+        }
+        report
+    }
+
+    pub fn set_grace_policy(&self, policy: GracePolicy) {
+        *self.grace_policy.write() = policy;
+    }
+
+    pub fn get_grace_policy(&self) -> GracePolicy {
+        self.grace_policy.read().clone()
+    }
+
+    pub fn set_throttle_policy(&self, policy: ThrottlePolicy) {
+        *self.throttle_policy.write() = policy;
+    }
+
+    pub fn get_throttle_policy(&self) -> ThrottlePolicy {
+        *self.throttle_policy.read()
+    }
+
+    /// Removes a bucket from the account into the trash; see `restore_bucket`.
+    pub fn cancel_bucket(&self, bucket_id: String) -> Result<(), TelcoError> {
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Err(TelcoError::Locked); }
+        let index = lock.buckets.iter().position(|b| b.id == bucket_id)
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("No bucket '{}'", bucket_id)))?;
+        let bucket = lock.buckets.remove(index);
+        lock.refresh_balances();
+        let account = lock.clone();
+        drop(lock);
+        let deleted_at = self.now_secs();
+        self.trash.lock().push(TrashedBucket { bucket: bucket.clone(), deleted_at });
+        self.notify_and_persist(account, None);
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            self.persist(PersistenceMsg::TrashBucket { account_id, bucket: bucket.clone(), deleted_at });
+        }
+        self.purge_trash();
+        self.dispatch_event(TelcoEvent::BucketCancelled { bucket });
+        Ok(())
+    }
+
+    /// Puts a cancelled bucket back with the balance and expiry it had when cancelled.
+    pub fn restore_bucket(&self, bucket_id: String) -> Result<QuotaBucket, TelcoError> {
+        self.purge_trash();
+        let mut trash = self.trash.lock();
+        let index = trash.iter().position(|t| t.bucket.id == bucket_id)
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("No bucket '{}' in trash", bucket_id)))?;
+        let bucket = trash.remove(index).bucket;
+        drop(trash);
+        if let Err(e) = self.add_bucket(bucket.clone()) {
+            self.trash.lock().insert(index, TrashedBucket { bucket, deleted_at: self.now_secs() });
+            return Err(e);
+        }
+        Ok(bucket)
+    }
+
+    pub fn list_trashed_buckets(&self) -> Vec<TrashedBucket> {
+        self.purge_trash();
+        self.trash.lock().clone()
+    }
+
+    /// How long cancelled buckets stay restorable. Defaults to 7 days.
+    pub fn set_trash_retention(&self, secs: u64) {
+        self.trash_retention_secs.store(secs, Ordering::Relaxed);
+        self.purge_trash();
+    }
+
+    /// Saves the account (buckets, grace, pending orders, ad rewards, achievements) and the current end of its
+    /// usage history under `name`, replacing any snapshot with that name.
+    pub fn create_snapshot(&self, name: String) -> Result<AccountSnapshot, TelcoError> {
+        let name = name.trim().to_string();
+        if name.is_empty() { return Err(TelcoError::InvalidCommand("Snapshot name is empty".to_string())); }
+        let account = self.state.read().clone();
+        if account.biometric_locked { return Err(TelcoError::Locked); }
+        let state = SnapshotState {
+            pending_orders: self.pending_orders.lock().clone(),
+            ad_rewards: self.ad_rewards.lock().clone(),
+            achievements: self.achievements.lock().clone(),
+            account,
+        };
+        let info = AccountSnapshot {
+            name: name.clone(),
+            created_at: self.now_secs(),
+            bucket_count: state.account.buckets.len() as u32,
+            balance_bytes: state.account.data_balance_bytes,
+        };
+        #[cfg(feature = "sqlite")]
+        {
+            let payload = serde_json::to_string(&state).map_err(|e| TelcoError::SerializationFailed(e.to_string()))?;
+            self.persist(PersistenceMsg::CreateSnapshot { account_id: state.account.id.clone(), name: name.clone(), created_at: info.created_at, payload });
+        }
+        let mut snapshots = self.snapshots.lock();
+        snapshots.retain(|s| s.info.name != name);
+        snapshots.push(StoredSnapshot { info: info.clone(), state });
+        Ok(info)
+    }
+
+    /// Puts the account back to `name` and drops usage history recorded since. The snapshot is kept for reuse.
+    pub fn restore_snapshot(&self, name: String) -> Result<AccountSnapshot, TelcoError> {
+        let snapshot = self.snapshots.lock().iter().find(|s| s.info.name == name.trim()).cloned()
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("No snapshot '{}'", name.trim())))?;
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Err(TelcoError::Locked); }
+        let account = UserAccount { biometric_locked: false, ..snapshot.state.account.clone() };
+        *lock = account.clone();
+        drop(lock);
+
+        // Buckets cancelled after the snapshot are live again, so they must not stay restorable from the trash.
+        self.trash.lock().retain(|t| !account.buckets.iter().any(|b| b.id == t.bucket.id));
+        *self.ad_rewards.lock() = snapshot.state.ad_rewards.clone();
+        *self.achievements.lock() = snapshot.state.achievements.clone();
+        let orders = snapshot.state.pending_orders.clone();
+        *self.pending_orders.lock() = orders.clone();
+        self.persist_pending_orders(orders);
+        #[cfg(feature = "sqlite")]
+        self.persist(PersistenceMsg::RestoreSnapshot { account_id: account.id.clone(), name: snapshot.info.name.clone(), created_at: snapshot.info.created_at });
+        self.invalidate_insights();
+        self.notify_and_persist(account, None);
+        engine_log!(LogLevel::Info, "snapshot", "restored '{}'", snapshot.info.name);
+        Ok(snapshot.info)
+    }
+
+    pub fn list_snapshots(&self) -> Vec<AccountSnapshot> {
+        self.snapshots.lock().iter().map(|s| s.info.clone()).collect()
+    }
+
+    pub fn delete_snapshot(&self, name: String) -> Result<(), TelcoError> {
+        let mut snapshots = self.snapshots.lock();
+        let index = snapshots.iter().position(|s| s.info.name == name.trim())
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("No snapshot '{}'", name.trim())))?;
+        let _removed = snapshots.remove(index);
+        #[cfg(feature = "sqlite")]
+        self.persist(PersistenceMsg::DeleteSnapshot { account_id: self.state.read().id.clone(), name: _removed.info.name });
+        Ok(())
+    }
+
+    /// `with_preset` starts from the operator's `max_rollover_bytes`.
+    pub fn set_rollover_policy(&self, policy: RolloverPolicy) {
+        *self.rollover_policy.write() = policy;
+    }
+
+    pub fn get_rollover_policy(&self) -> RolloverPolicy {
+        *self.rollover_policy.read()
+    }
+
+    /// Retires expired buckets, carrying their unused bytes into rollover buckets per the policy, then moves them
+    /// off the account into the expiry archive with a `BucketExpired` each. Runs on every scheduler tick; returns
+    /// the rollover buckets created.
+    pub fn process_expirations(&self) -> Vec<QuotaBucket> {
+        let now = self.now_secs();
+        let expired: Vec<QuotaBucket> = self.state.read().buckets.iter().filter(|b| b.expiry <= now).cloned().collect();
+        if expired.is_empty() { return Vec::new(); }
+        let rolled_over = self.retire_buckets(|b| b.expiry <= now);
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return rolled_over; }
+        let archived: Vec<ExpiredBucket> = expired.into_iter()
+            .filter(|e| lock.buckets.iter().any(|b| b.id == e.id && b.expiry <= now))
+            .map(|bucket| ExpiredBucket { bucket, archived_at: now })
+            .collect();
+        lock.buckets.retain(|b| !archived.iter().any(|e| e.bucket.id == b.id));
+        lock.refresh_balances();
+        let account = lock.clone();
+        drop(lock);
+        if archived.is_empty() { return rolled_over; }
+        self.notify_and_persist(account.clone(), None);
+        #[cfg(feature = "sqlite")]
+        self.persist(PersistenceMsg::ArchiveBuckets { account_id: account.id.clone(), buckets: archived.clone() });
+        engine_log!(LogLevel::Info, "expiry", "archived {} expired buckets", archived.len());
+        for expired in archived { self.dispatch_event(TelcoEvent::BucketExpired { bucket: expired.bucket }); }
+        rolled_over
+    }
+
+    /// Buckets `process_expirations` archived, most recent first.
+    pub fn get_expired_buckets(&self, limit: u32) -> Result<Vec<ExpiredBucket>, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let mut stmt = conn.prepare("SELECT archived_at, payload FROM expired_buckets WHERE account_id = ?1 ORDER BY archived_at DESC, rowid DESC LIMIT ?2")
+                .map_err(db_err)?;
+            let rows = stmt.query_map(params![account_id, limit], |row| Ok((row.get::<_, u64>(0)?, row.get::<_, String>(1)?)))
+                .map_err(db_err)?
+                .filter_map(|r| r.ok())
+                .filter_map(|(archived_at, payload)| serde_json::from_str(&payload).ok().map(|bucket| ExpiredBucket { bucket, archived_at }))
+                .collect();
+            Ok(rows)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = limit;
+            Ok(vec![])
+        }
+    }
+
+    /// Adds a non-purchased bucket whose expiry follows its tier (promo: 7 days, rollover: one cycle).
+    pub fn grant_bucket(&self, name: String, bytes: u64, category: QuotaType, source: BucketSource) -> Result<(), TelcoError> {
+        self.add_bucket(QuotaBucket { id: new_id(), name, remaining_bytes: bytes, initial_bytes: bytes, category, expiry: self.now_secs() + source.lifetime_secs(), source, active_window: None, fair_usage: None })
+    }
+
+    pub fn get_operator_profile(&self) -> OperatorProfile {
+        self.operator.read().clone()
+    }
+
+    pub fn get_region_profile(&self) -> RegionProfile {
+        self.region.read().clone()
+    }
+
+    /// Puts the line in one of the region's `roaming_zones` (by name, case-insensitive), moving registration to
+    /// `Roaming`, or home again with `None`. While roaming, roaming passes are used first and everything else is
+    /// charged at the zone's multiplier.
+    pub fn set_roaming_zone(&self, zone: Option<String>) -> Result<(), TelcoError> {
+        let zone = match zone {
+            None => None,
+            Some(name) => Some(self.region.read().roaming_zones.iter().find(|z| z.name.eq_ignore_ascii_case(name.trim())).cloned()
+                .ok_or_else(|| TelcoError::InvalidCommand(format!("Unknown roaming zone '{}'", name.trim())))?),
+        };
+        let target = if zone.is_some() { RegistrationState::Roaming } else { RegistrationState::Registered };
+        if self.state.read().registration != target { self.set_registration_state(target)?; }
+        let previous = std::mem::replace(&mut *self.roaming_zone.write(), zone.clone());
+        if previous != zone { self.dispatch_event(TelcoEvent::RoamingZoneChanged { zone }); }
+        Ok(())
+    }
+
+    /// The zone set with `set_roaming_zone`, while the line is still roaming.
+    pub fn get_roaming_zone(&self) -> Option<RoamingZone> {
+        if self.state.read().registration != RegistrationState::Roaming { return None; }
+        self.roaming_zone.read().clone()
+    }
+
+    /// Stores a host preference (unit policy, thresholds, notification settings, ...) for this account, so every
+    /// binding reads the same value. Emits `PreferenceChanged` when the value actually changes.
+    pub fn set_preference(&self, key: String, value: PreferenceValue) {
+        self.write_preference(key, Some(value));
+    }
+
+    pub fn remove_preference(&self, key: String) {
+        self.write_preference(key, None);
+    }
+
+    pub fn get_preference(&self, key: String) -> Option<PreferenceValue> {
+        self.preferences.read().get(&key).cloned()
+    }
+
+    pub fn get_preferences(&self) -> HashMap<String, PreferenceValue> {
+        self.preferences.read().clone()
+    }
+
+    /// `default` when the key is unset or holds another type; likewise for the other typed getters.
+    pub fn get_bool_preference(&self, key: String, default: bool) -> bool {
+        match self.preferences.read().get(&key) { Some(PreferenceValue::Bool { value }) => *value, _ => default }
+    }
+
+    pub fn set_bool_preference(&self, key: String, value: bool) {
+        self.write_preference(key, Some(PreferenceValue::Bool { value }));
+    }
+
+    pub fn get_int_preference(&self, key: String, default: i64) -> i64 {
+        match self.preferences.read().get(&key) { Some(PreferenceValue::Int { value }) => *value, _ => default }
+    }
+
+    pub fn set_int_preference(&self, key: String, value: i64) {
+        self.write_preference(key, Some(PreferenceValue::Int { value }));
+    }
+
+    /// Int values are widened, so a threshold stored as 5 reads back as 5.0.
+    pub fn get_float_preference(&self, key: String, default: f64) -> f64 {
+        match self.preferences.read().get(&key) {
+            Some(PreferenceValue::Float { value }) => *value,
+            Some(PreferenceValue::Int { value }) => *value as f64,
+            _ => default,
+        }
+    }
+
+    pub fn set_float_preference(&self, key: String, value: f64) {
+        self.write_preference(key, Some(PreferenceValue::Float { value }));
+    }
+
+    pub fn get_string_preference(&self, key: String, default: String) -> String {
+        match self.preferences.read().get(&key) { Some(PreferenceValue::Text { value }) => value.clone(), _ => default }
+    }
+
+    pub fn set_string_preference(&self, key: String, value: String) {
+        self.write_preference(key, Some(PreferenceValue::Text { value }));
+    }
+
+    /// Sets the BCP 47 locale (e.g. "fr-FR") used for numbers and units in insight, command and USSD replies. Replies
+    /// are translated for English, Indonesian ("id") and Spanish ("es"); other languages get English text.
+    pub fn set_locale(&self, tag: String) {
+        *self.locale.write() = Locale::parse(&tag);
+    }
+
+    pub fn get_locale(&self) -> String {
+        self.locale.read().tag.clone()
+    }
+
+    /// Switches market defaults: the operator currency and the baseline latency (the network's, scaled by the
+    /// midpoint of the region's range).
+    pub fn set_region(&self, region: RegionPreset) {
+        let profile = region.profile();
+        self.operator.write().currency = profile.currency.clone();
+        *self.region.write() = profile;
+        let mut lock = self.state.write();
+        if !lock.throttled && lock.grace.is_none() { lock.current_latency_ms = self.sample_latency(); }
+        let account = lock.clone();
+        drop(lock);
+        self.notify_and_persist(account, None);
+    }
+
+    /// Moves the line onto another network generation and emits `NetworkTypeChanged`. Latency follows the new
+    /// profile unless throttling or grace holds it down.
+    pub fn set_network_generation(&self, generation: NetworkGeneration) {
+        let from = std::mem::replace(&mut *self.network.write(), generation);
+        if from == generation { return; }
+        let mut lock = self.state.write();
+        if !lock.throttled && lock.grace.is_none() { lock.current_latency_ms = self.sample_latency(); }
+        let account = lock.clone();
+        drop(lock);
+        self.notify_and_persist(account, None);
+        self.dispatch_event(TelcoEvent::NetworkTypeChanged { from, to: generation });
+    }
+
+    pub fn get_network_profile(&self) -> NetworkProfile {
+        self.network.read().profile()
+    }
+
+    /// Holds the signal at `dbm` (switching to `SignalMode::Fixed`). Below -120 dBm the line has no coverage and
+    /// drops to `Searching`; it registers again where it was once the signal returns.
+    pub fn set_signal_strength(&self, dbm: i32) {
+        *self.signal_mode.write() = SignalMode::Fixed;
+        self.apply_signal(dbm);
+    }
+
+    pub fn set_signal_mode(&self, mode: SignalMode) {
+        *self.signal_mode.write() = mode;
+    }
+
+    pub fn get_signal(&self) -> SignalReading {
+        let lock = self.state.read();
+        SignalReading { dbm: lock.signal_dbm, bars: lock.signal_bars, generation: *self.network.read() }
+    }
+
+    pub fn set_signal_handler(&self, handler: Box<dyn SignalHandler>) {
+        let reading = self.get_signal();
+        let mut lock = self.signal_handler.write();
+        *lock = Some(handler);
+        if let Some(h) = &*lock { h.on_signal(reading); }
+    }
+
+    pub fn clear_signal_handler(&self) {
+        *self.signal_handler.write() = None;
+    }
+
+    pub fn list_apns(&self) -> Vec<ApnConfig> {
+        self.apns.read().clone()
+    }
+
+    /// Adds (or replaces, by name) a user-defined APN. It only takes effect once selected.
+    pub fn add_apn(&self, name: String, apn: String, kind: ApnKind) {
+        let mut apns = self.apns.write();
+        apns.retain(|a| a.name != name);
+        apns.push(ApnConfig { name, apn, kind, selected: false });
+    }
+
+    /// Selects the named APN for its kind, deselecting the previous one.
+    pub fn select_apn(&self, name: String) -> Result<(), TelcoError> {
+        let mut apns = self.apns.write();
+        let kind = apns.iter().find(|a| a.name == name).map(|a| a.kind)
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("Unknown APN '{}'", name)))?;
+        for a in apns.iter_mut().filter(|a| a.kind == kind) { a.selected = a.name == name; }
+        Ok(())
+    }
+
+    /// Moves the line to `state`, rejecting transitions the network state machine doesn't allow.
+    pub fn set_registration_state(&self, state: RegistrationState) -> Result<(), TelcoError> {
+        let mut lock = self.state.write();
+        let from = lock.registration;
+        if !from.can_transition_to(state) {
+            return Err(TelcoError::InvalidCommand(format!("Cannot move from {:?} to {:?}", from, state)));
+        }
+        lock.registration = state;
+        let account = lock.clone();
+        drop(lock);
+        self.notify_and_persist(account, None);
+        self.dispatch_event(TelcoEvent::RegistrationChanged { from, to: state });
+        Ok(())
+    }
+
+    /// Turns the radio off (registration `Off`, sensor paused, usage rejected) or back on,
+    /// in which case the line searches and re-registers on its home network.
+    pub fn set_flight_mode(&self, enabled: bool) {
+        let mut lock = self.state.write();
+        let from = lock.registration;
+        if (from == RegistrationState::Off) == enabled { return; }
+        let steps = if enabled { vec![RegistrationState::Off] } else { vec![RegistrationState::Searching, RegistrationState::Registered] };
+        lock.registration = *steps.last().unwrap();
+        let account = lock.clone();
+        drop(lock);
+        self.notify_and_persist(account, None);
+        self.dispatch_event(TelcoEvent::FlightModeChanged { enabled });
+        let mut prev = from;
+        for to in steps {
+            self.dispatch_event(TelcoEvent::RegistrationChanged { from: prev, to });
+            prev = to;
+        }
+    }
+
+    pub fn is_flight_mode(&self) -> bool {
+        self.state.read().registration == RegistrationState::Off
+    }
+
+    pub fn get_sim_identity(&self) -> SimIdentity {
+        self.sim_identity.read().clone()
+    }
+
+    pub fn swap_sim(&self, new_iccid: String) -> Result<SimRequest, TelcoError> {
+        if !(18..=20).contains(&new_iccid.len()) || !new_iccid.chars().all(|c| c.is_ascii_digit()) {
+            return Err(TelcoError::InvalidCommand("ICCID must be 18-20 digits".to_string()));
+        }
+        self.open_sim_request(SimRequestKind::SimSwap { new_iccid })
+    }
+
+    pub fn port_in(&self, msisdn: String, donor_operator: String) -> Result<SimRequest, TelcoError> {
+        self.open_sim_request(SimRequestKind::PortIn { msisdn, donor_operator })
+    }
+
+    pub fn port_out(&self, recipient_operator: String) -> Result<SimRequest, TelcoError> {
+        self.open_sim_request(SimRequestKind::PortOut { recipient_operator })
+    }
+
+    pub fn list_sim_requests(&self) -> Vec<SimRequest> {
+        self.sim_requests.lock().clone()
+    }
+
+    /// Moves a pending request into verification and issues its one-time code.
+    pub fn advance_sim_request(&self, request_id: String) -> Result<SimRequest, TelcoError> {
+        self.transition_sim_request(&request_id, |r| {
+            if r.state != SimRequestState::Pending { return Err(TelcoError::InvalidCommand(format!("Request is {:?}, expected Pending", r.state))); }
+            r.state = SimRequestState::Verification;
+            let code = format!("{:06}", secure_random_u64() % 1_000_000);
+            register_sensitive(&code);
+            r.verification_code = Some(code);
+            Ok(())
+        })
+    }
+
+    /// Completes a request in verification when `code` matches, then applies it to the line.
+    pub fn verify_sim_request(&self, request_id: String, code: String) -> Result<SimRequest, TelcoError> {
+        let request = self.transition_sim_request(&request_id, |r| {
+            if r.state != SimRequestState::Verification { return Err(TelcoError::InvalidCommand(format!("Request is {:?}, expected Verification", r.state))); }
+            if r.verification_code.as_deref() != Some(code.trim()) { return Err(TelcoError::InvalidCommand("Verification code mismatch".to_string())); }
+            r.state = SimRequestState::Complete;
+            if let Some(code) = r.verification_code.take() { unregister_sensitive(&code); }
+            Ok(())
+        })?;
+        let mut identity = self.sim_identity.write();
+        match &request.kind {
+            SimRequestKind::SimSwap { new_iccid } => identity.iccid = new_iccid.clone(),
+            SimRequestKind::PortIn { msisdn, .. } => identity.msisdn = msisdn.clone(),
+            SimRequestKind::PortOut { recipient_operator } => {
+                identity.operator = recipient_operator.clone();
+                drop(identity);
+                let mut lock = self.state.write();
+                lock.is_active = false;
+                let account = lock.clone();
+                drop(lock);
+                self.notify_and_persist(account, None);
+            }
+        }
+        Ok(request)
+    }
+
+    pub fn cancel_sim_request(&self, request_id: String) -> Result<SimRequest, TelcoError> {
+        self.transition_sim_request(&request_id, |r| {
+            if matches!(r.state, SimRequestState::Complete | SimRequestState::Cancelled) {
+                return Err(TelcoError::InvalidCommand(format!("Request is already {:?}", r.state)));
+            }
+            r.state = SimRequestState::Cancelled;
+            if let Some(code) = r.verification_code.take() { unregister_sensitive(&code); }
+            Ok(())
+        })
+    }
+
+    /// Activates a plan from the operator catalog by name (case-insensitive).
+    pub fn buy_plan(&self, plan_name: String) -> Result<(), TelcoError> {
+        self.simulate_response_delay("buy_plan");
+        let plan = self.operator.read().plans.iter().find(|p| p.name.eq_ignore_ascii_case(&plan_name)).cloned()
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("Unknown plan '{}'", plan_name)))?;
+        if self.state.read().biometric_locked { return Err(TelcoError::Locked); }
+        let bucket = plan.to_bucket(self.now_secs());
+        self.approve_purchase(&bucket, plan.price_cents)?;
+        let bought = bucket.id.clone();
+        self.add_paid_bucket(bucket, plan.price_cents)?;
+        // Renewing a plan that is still running retires the old bucket, rolling over what's left of it.
+        let now = self.now_secs();
+        self.retire_buckets(|b| b.name == plan.name && b.source == BucketSource::Purchase && b.expiry > now && b.id != bought);
+        self.unlock_achievement(Achievement::FirstTopUp);
+        Ok(())
+    }
+
+    pub fn set_ad_reward_policy(&self, policy: AdRewardPolicy) {
+        *self.ad_reward_policy.write() = policy;
+    }
+
+    /// Grants a promo bucket for a watched ad, subject to the daily cap, cooldown and per-cycle byte cap.
+    pub fn grant_ad_reward(&self, bytes: u64) -> Result<(), TelcoError> {
+        let policy = *self.ad_reward_policy.read();
+        let now = self.now_secs();
+        let mut rewards = self.ad_rewards.lock();
+        if let Some(&(last, _)) = rewards.iter().max_by_key(|(t, _)| *t) {
+            if now < last + policy.cooldown_secs {
+                return Err(TelcoError::RateLimited(format!("Next ad reward available in {}s", last + policy.cooldown_secs - now)));
+            }
+        }
+        let today = rewards.iter().filter(|(t, _)| *t + 86400 > now).count() as u32;
+        if today >= policy.max_per_day {
+            return Err(TelcoError::RateLimited("Daily ad reward limit reached".to_string()));
+        }
+        let cycle_bytes: u64 = rewards.iter().filter(|(t, _)| *t + BILLING_CYCLE_SECS > now).map(|(_, b)| b).sum();
+        if cycle_bytes + bytes > policy.max_bytes_per_cycle {
+            return Err(TelcoError::RateLimited("Ad reward allowance for this cycle exhausted".to_string()));
+        }
+        self.grant_bucket("Ad Reward".to_string(), bytes, QuotaType::General, BucketSource::Promo)?;
+        self.record_telemetry("reward.ad");
+        rewards.push((now, bytes));
+        drop(rewards);
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            self.persist(PersistenceMsg::AdReward { account_id, timestamp: now, bytes });
+        }
+        Ok(())
+    }
+
+    pub fn set_achievement_config(&self, config: AchievementConfig) {
+        *self.achievement_config.write() = config;
+    }
+
+    /// Sets the daily budget the coach tracks, or `None` to stop coaching. The budget also becomes the threshold
+    /// for the `WeekUnderBudget` achievement.
+    pub fn set_daily_budget(&self, budget_bytes: Option<u64>) -> Result<(), TelcoError> {
+        let now = self.now_secs();
+        let used_bytes = match budget_bytes { Some(_) => self.usage_on_day(now / 86400)?, None => 0 };
+        if let Some(budget) = budget_bytes { self.achievement_config.write().daily_budget_bytes = budget; }
+        *self.budget.lock() = BudgetTracker { budget_bytes, day: now / 86400, used_bytes, ..BudgetTracker::default() };
+        Ok(())
+    }
+
+    pub fn get_budget_status(&self) -> Result<Option<BudgetStatus>, TelcoError> {
+        let now = self.now_secs();
+        let tracker = self.budget.lock();
+        let Some(budget_bytes) = tracker.budget_bytes else { return Ok(None); };
+        let used_today_bytes = if tracker.day == now / 86400 { tracker.used_bytes } else { 0 };
+        let projected_bytes = BudgetTracker { used_bytes: used_today_bytes, ..BudgetTracker::default() }.projected(now);
+        drop(tracker);
+        Ok(Some(BudgetStatus {
+            budget_bytes,
+            used_today_bytes,
+            projected_bytes,
+            on_track: projected_bytes <= budget_bytes,
+            streak_days: self.budget_streak(budget_bytes)?,
+        }))
+    }
+
+    /// Replaces the caps. Usage already recorded this cycle counts against them; spending counts from when the
+    /// engine started.
+    pub fn set_usage_caps(&self, caps: UsageCaps) -> Result<(), TelcoError> {
+        let now = self.now_secs();
+        let period_start = now - now % BILLING_CYCLE_SECS;
+        let used = self.usage_by_category_since(period_start)?;
+        let mut tracker = self.caps.lock();
+        tracker.roll(now);
+        tracker.caps = caps;
+        tracker.reached.clear();
+        tracker.total_bytes = used.iter().filter(|c| c.category.is_data()).map(|c| c.bytes).sum();
+        tracker.category_bytes = used;
+        Ok(())
+    }
+
+    pub fn get_cap_status(&self) -> CapStatus {
+        let now = self.now_secs();
+        let mut tracker = self.caps.lock();
+        tracker.roll(now);
+        CapStatus {
+            caps: tracker.caps.clone(),
+            period_started_at: now - now % BILLING_CYCLE_SECS,
+            total_bytes: tracker.total_bytes,
+            category_bytes: tracker.category_bytes.clone(),
+            spent_cents: tracker.spent_cents,
+        }
+    }
+
+    pub fn get_achievements(&self) -> Vec<AchievementRecord> {
+        self.achievements.lock().clone()
+    }
+
+    /// Re-derives history-based achievements and returns the ones unlocked by this call.
+    pub fn evaluate_achievements(&self) -> Result<Vec<Achievement>, TelcoError> {
+        let config = *self.achievement_config.read();
+        let mut unlocked = Vec::new();
+        if self.week_under_budget(config.daily_budget_bytes)? && self.unlock_achievement(Achievement::WeekUnderBudget) {
+            unlocked.push(Achievement::WeekUnderBudget);
+        }
+        if self.happy_hour_bytes(&config)? >= config.happy_hour_target_bytes && self.unlock_achievement(Achievement::HappyHourSaver) {
+            unlocked.push(Achievement::HappyHourSaver);
+        }
+        Ok(unlocked)
+    }
+
+    pub fn register_experiment(&self, name: String, variants: Vec<String>) -> Result<(), TelcoError> {
+        if variants.is_empty() { return Err(TelcoError::InvalidCommand(format!("Experiment '{}' needs at least one variant", name))); }
+        self.experiments.write().insert(name, variants);
+        Ok(())
+    }
+
+    /// Returns the account's variant, assigning one deterministically from the account id on first use.
+    /// Assignments are persisted so later changes to the variant list don't reshuffle existing accounts.
+    pub fn get_experiment_variant(&self, name: String) -> Option<String> {
+        let mut assignments = self.experiment_assignments.lock();
+        if let Some(v) = assignments.get(&name) { return Some(v.clone()); }
+        let experiments = self.experiments.read();
+        let variants = experiments.get(&name)?;
+        let account_id = self.state.read().id.clone();
+        let index = (stable_hash(&format!("{}:{}", name, account_id)) % variants.len() as u64) as usize;
+        let variant = variants[index].clone();
+        assignments.insert(name.clone(), variant.clone());
+        #[cfg(feature = "sqlite")]
+        {
+            self.persist(PersistenceMsg::ExperimentAssignment { account_id, experiment: name, variant: variant.clone() });
+        }
+        Some(variant)
+    }
+
+    pub fn get_experiment_assignments(&self) -> Vec<ExperimentAssignment> {
+        let names: Vec<String> = self.experiments.read().keys().cloned().collect();
+        let mut assignments: Vec<ExperimentAssignment> = names.into_iter()
+            .filter_map(|experiment| self.get_experiment_variant(experiment.clone()).map(|variant| ExperimentAssignment { experiment, variant }))
+            .collect();
+        assignments.sort_by(|a, b| a.experiment.cmp(&b.experiment));
+        assignments
+    }
+
+    pub fn get_historical_usage(&self, limit: u32) -> Result<Vec<UsageRecord>, TelcoError> {
+        if let Some(backend) = &*self.backend.read() {
+            return backend.query_history(self.state.read().id.clone(), limit);
+        }
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let mut stmt = conn.prepare("SELECT timestamp, amount, category, source, confidence FROM usage_history WHERE account_id = ?1 ORDER BY timestamp DESC LIMIT ?2")
+                .map_err(db_err)?;
+            
+            let records = stmt.query_map(params![account_id, limit], usage_record_from_row)
+                .map_err(db_err)?
+            .filter_map(|r| r.ok())
+            .collect();
+            
+            Ok(records)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = limit;
+            Ok(vec![])
+        }
+    }
+
+    /// Runs `filter` in SQL against this account's history, newest first.
+    pub fn query_usage(&self, filter: UsageFilter) -> Result<Vec<UsageRecord>, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let (mut sql, mut args) = usage_filter_sql("SELECT timestamp, amount, category, source, confidence FROM usage_history", account_id, &filter);
+            sql += " ORDER BY timestamp DESC";
+            if let Some(limit) = filter.limit { sql += " LIMIT ?"; args.push(Box::new(limit)); }
+            let mut stmt = conn.prepare(&sql).map_err(db_err)?;
+            let records = stmt.query_map(rusqlite::params_from_iter(args.iter()), usage_record_from_row)
+                .map_err(db_err)?
+                .filter_map(|r| r.ok())
+                .collect();
+            Ok(records)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = filter;
+            Ok(vec![])
+        }
+    }
+
+    /// Keyset-paginated variant of `query_usage`; pass the previous page's `next_cursor` to continue.
+    /// `filter.limit` is ignored in favour of `page_size`.
+    pub fn query_usage_page(&self, filter: UsageFilter, cursor: Option<HistoryCursor>, page_size: u32) -> Result<UsagePage, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let (mut sql, mut args) = usage_filter_sql("SELECT timestamp, amount, category, source, confidence, rowid FROM usage_history", account_id, &filter);
+            if let Some(c) = cursor {
+                sql += " AND (timestamp < ? OR (timestamp = ? AND rowid < ?))";
+                args.push(Box::new(c.timestamp));
+                args.push(Box::new(c.timestamp));
+                args.push(Box::new(c.row_id));
+            }
+            sql += " ORDER BY timestamp DESC, rowid DESC LIMIT ?";
+            args.push(Box::new(page_size));
+            let mut stmt = conn.prepare(&sql).map_err(db_err)?;
+            let rows: Vec<(UsageRecord, i64)> = stmt.query_map(rusqlite::params_from_iter(args.iter()), |row| Ok((usage_record_from_row(row)?, row.get(5)?)))
+                .map_err(db_err)?
+                .filter_map(|r| r.ok())
+                .collect();
+            let next_cursor = if rows.len() as u32 == page_size {
+                rows.last().map(|(r, row_id)| HistoryCursor { timestamp: r.timestamp, row_id: *row_id })
+            } else {
+                None
+            };
+            Ok(UsagePage { records: rows.into_iter().map(|(r, _)| r).collect(), next_cursor })
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = (filter, cursor, page_size);
+            Ok(UsagePage { records: vec![], next_cursor: None })
+        }
+    }
+
+    /// Sums usage in `[from, to)` per period and category in SQL, oldest period first. Periods without usage are
+    /// left out.
+    pub fn get_usage_aggregates(&self, period: AggregationPeriod, from: u64, to: u64) -> Result<Vec<UsageAggregate>, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let (length, offset) = period.span();
+            let mut stmt = conn.prepare(
+                "SELECT (timestamp - ?4) / ?5 * ?5 + ?4 AS period_start, category, SUM(amount), COUNT(*) FROM usage_history
+                 WHERE account_id = ?1 AND timestamp >= ?2 AND timestamp < ?3 GROUP BY period_start, category ORDER BY period_start, category",
+            ).map_err(db_err)?;
+            let rows = stmt.query_map(params![account_id, from, to, offset, length], |row| {
+                Ok((row.get::<_, u64>(0)?, row.get::<_, String>(1)?, row.get::<_, u64>(2)?, row.get::<_, u64>(3)?))
+            })
+                .map_err(db_err)?
+                .filter_map(|r| r.ok())
+                .filter_map(|(period_start, name, amount, record_count)| {
+                    QuotaType::from_name(&name).map(|category| UsageAggregate { period_start, category, amount, record_count })
+                })
+                .collect();
+            Ok(rows)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = (period, from, to);
+            Ok(vec![])
+        }
+    }
+
+    /// Writes usage in `[from, to)` as CSV, oldest first, with a header and ISO 8601 UTC timestamps. Rows are
+    /// streamed to the file at `path` (returning `None`), or returned as text when no path is given.
+    pub fn export_usage_csv(&self, path: Option<String>, from: Option<u64>, to: Option<u64>) -> Result<Option<String>, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            use std::io::Write;
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let filter = UsageFilter { from, to, ..UsageFilter::default() };
+            let (mut sql, args) = usage_filter_sql("SELECT timestamp, amount, category, source, confidence FROM usage_history", account_id, &filter);
+            sql += " ORDER BY timestamp, rowid";
+            let mut stmt = conn.prepare(&sql).map_err(db_err)?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(args.iter()), usage_record_from_row).map_err(db_err)?;
+            let mut text = Vec::new();
+            let mut file;
+            let out: &mut dyn Write = match &path {
+                Some(path) => {
+                    file = std::io::BufWriter::new(std::fs::File::create(path).map_err(io_err)?);
+                    &mut file
+                }
+                None => &mut text,
+            };
+            writeln!(out, "timestamp,amount,category,source,confidence").map_err(io_err)?;
+            for record in rows.filter_map(|r| r.ok()) {
+                let at = chrono::DateTime::from_timestamp(record.timestamp as i64, 0)
+                    .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+                    .unwrap_or_default();
+                let confidence = record.confidence.map(|c| c.to_string()).unwrap_or_default();
+                writeln!(out, "{},{},{},{:?},{}", at, record.amount, record.category, record.source, confidence).map_err(io_err)?;
+            }
+            out.flush().map_err(io_err)?;
+            Ok(path.is_none().then(|| String::from_utf8_lossy(&text).into_owned()))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = (path, from, to);
+            Err(TelcoError::StorageUnavailable)
+        }
+    }
+
+    /// Delivers matching history oldest-first in batches of `batch_size` without materializing the full result.
+    /// Returns the number of records delivered.
+    pub fn stream_usage(&self, filter: UsageFilter, batch_size: u32, handler: Box<dyn UsageBatchHandler>) -> Result<u64, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let (mut sql, mut args) = usage_filter_sql("SELECT timestamp, amount, category, source, confidence FROM usage_history", account_id, &filter);
+            sql += " ORDER BY timestamp ASC, rowid ASC";
+            if let Some(limit) = filter.limit { sql += " LIMIT ?"; args.push(Box::new(limit)); }
+            let mut stmt = conn.prepare(&sql).map_err(db_err)?;
+            let rows = stmt.query_map(rusqlite::params_from_iter(args.iter()), usage_record_from_row)
+                .map_err(db_err)?;
+            let batch_size = batch_size.max(1) as usize;
+            let mut batch = Vec::with_capacity(batch_size);
+            let mut delivered = 0u64;
+            for record in rows.filter_map(|r| r.ok()) {
+                batch.push(record);
+                if batch.len() == batch_size {
+                    delivered += batch.len() as u64;
+                    if !handler.on_batch(std::mem::replace(&mut batch, Vec::with_capacity(batch_size))) { return Ok(delivered); }
+                }
+            }
+            if !batch.is_empty() {
+                delivered += batch.len() as u64;
+                handler.on_batch(batch);
+            }
+            Ok(delivered)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = (filter, batch_size, handler);
+            Ok(0)
+        }
+    }
+
+    /// Single entry point for REST/gRPC/JS layers. `payload` is JSON tagged by `"command"` (e.g.
+    /// `{"command": "buy_plan", "plan": "Weekly 5GB"}`); whether `source` may run it is checked here, and every
+    /// well-formed attempt, refused or not, lands in the command audit log. Returns a short human-readable result.
+    pub fn dispatch_external_command(&self, source: CommandSource, payload: String) -> Result<String, TelcoError> {
+        let command: ExternalCommand = serde_json::from_str(&payload)
+            .map_err(|e| TelcoError::InvalidCommand(format!("Invalid command payload: {}", e)))?;
+        let name = command.name();
+        if !command.allowed(source) {
+            engine_log!(LogLevel::Warn, "command", "{:?} may not run '{}'", source, name);
+            self.audit_command(source, name, false, None);
+            return Err(TelcoError::PermissionDenied(format!("{:?} may not run '{}'", source, name)));
+        }
+        let result = match command {
+            ExternalCommand::Chat { text } => Ok(self.handle_command(text)),
+            ExternalCommand::BuyPlan { plan } => self.buy_plan(plan.clone()).map(|_| format!("Bought {}.", plan)),
+            ExternalCommand::CancelBucket { bucket_id } => self.cancel_bucket(bucket_id).map(|_| "Bucket cancelled.".to_string()),
+            ExternalCommand::RedeemShareToken { token } => self.redeem_share_token(token).map(|b| format!("Redeemed {}.", format_bytes(b.remaining_bytes))),
+            ExternalCommand::GrantBucket { name, bytes, category, source } => {
+                self.grant_bucket(name.clone(), bytes, category, source).map(|_| format!("Granted {}.", name))
+            }
+            ExternalCommand::Provision { provisioning } => self.apply_remote_provisioning(provisioning.to_string()).map(|_| "Provisioning applied.".to_string()),
+            ExternalCommand::SetRegistration { state } => self.set_registration_state(state).map(|_| format!("Registration set to {:?}.", state)),
+            ExternalCommand::AdvanceSimRequest { request_id } => self.advance_sim_request(request_id).map(|r| format!("Request is now {:?}.", r.state)),
+            ExternalCommand::SimulateUsage { bytes, category } => self.simulate_usage(bytes, category).map(|_| format!("Recorded {}.", format_bytes(bytes))),
+        };
+        engine_log!(LogLevel::Info, "command", "{:?} ran '{}': {}", source, name, if result.is_ok() { "ok" } else { "failed" });
+        self.audit_command(source, name, true, result.as_ref().err().map(|e| e.to_string()));
+        result
+    }
+
+    /// Newest first.
+    pub fn get_command_audit(&self, limit: u32) -> Result<Vec<CommandAuditEntry>, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let mut stmt = conn.prepare("SELECT timestamp, source, command, allowed, error FROM command_audit WHERE account_id = ?1 ORDER BY rowid DESC LIMIT ?2")
+                .map_err(db_err)?;
+            let entries = stmt.query_map(params![account_id, limit], |row| Ok(CommandAuditEntry {
+                timestamp: row.get(0)?,
+                source: CommandSource::parse(&row.get::<_, String>(1)?),
+                command: row.get(2)?,
+                allowed: row.get(3)?,
+                error: row.get(4)?,
+            }))
+                .map_err(db_err)?
+                .filter_map(|r| r.ok())
+                .collect();
+            Ok(entries)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = limit;
+            Ok(vec![])
+        }
+    }
+
+    /// Applies an operator provisioning push (plans, bonus buckets, suspend/resume). Either every action applies or,
+    /// if any is invalid, none does.
+    pub fn apply_remote_provisioning(&self, json: String) -> Result<UserAccount, TelcoError> {
+        let payload: ProvisioningPayload = serde_json::from_str(&json)
+            .map_err(|e| TelcoError::InvalidCommand(format!("Invalid provisioning payload: {}", e)))?;
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Err(TelcoError::Locked); }
+        let mut account = lock.clone();
+        let mut events = Vec::new();
+        for action in &payload.actions {
+            let bucket = match action {
+                ProvisioningAction::Plan { name, bytes: Some(bytes), category, validity_days, active_window, unlimited } => PlanOffer {
+                    name: name.clone(),
+                    bytes: *bytes,
+                    category: category.unwrap_or(QuotaType::General),
+                    price_cents: 0,
+                    validity_days: validity_days.unwrap_or(30),
+                    active_window: *active_window,
+                    unlimited: *unlimited,
+                    roaming: false,
+                }.to_bucket(self.now_secs()),
+                ProvisioningAction::Plan { name, .. } => self.operator.read().plans.iter().find(|p| p.name.eq_ignore_ascii_case(name)).map(|p| p.to_bucket(self.now_secs()))
+                    .ok_or_else(|| TelcoError::InvalidCommand(format!("Unknown plan '{}'", name)))?,
+                ProvisioningAction::Bonus { name, bytes, category, validity_days } => QuotaBucket {
+                    id: new_id(),
+                    name: name.clone(),
+                    remaining_bytes: *bytes,
+                    initial_bytes: *bytes,
+                    category: *category,
+                    expiry: self.now_secs() + validity_days.map_or(BucketSource::Promo.lifetime_secs(), |d| d as u64 * 86400),
+                    source: BucketSource::Promo,
+                    active_window: None,
+                    fair_usage: None,
+                },
+                ProvisioningAction::Suspend { reason } => {
+                    account.is_active = false;
+                    events.push(TelcoEvent::AccountSuspended { reason: reason.clone() });
+                    continue;
+                }
+                ProvisioningAction::Resume => {
+                    account.is_active = true;
+                    events.push(TelcoEvent::AccountResumed);
+                    continue;
+                }
+            };
+            account.buckets.push(bucket.clone());
+            events.push(TelcoEvent::BucketAdded { bucket });
+        }
+        account.refresh_balances();
+        if events.iter().any(|e| matches!(e, TelcoEvent::BucketAdded { .. })) { events.extend(self.lift_restrictions(&mut account)); }
+        *lock = account.clone();
+        drop(lock);
+
+        engine_log!(LogLevel::Info, "provisioning", "applied {} actions from {:?}", payload.actions.len(), payload.id);
+        self.notify_and_persist(account.clone(), None);
+        for event in events { self.dispatch_event(event); }
+        self.dispatch_event(TelcoEvent::ProvisioningApplied { provisioning_id: payload.id, actions: payload.actions.len() as u32 });
+        Ok(self.snapshot(account))
+    }
+
+    /// Moves `bytes` of `category` data out of this account into a signed token another device can redeem within
+    /// `ttl_secs`. The bytes leave the sender immediately; an unredeemed token does not come back.
+    pub fn create_share_token(&self, bytes: u64, category: QuotaType, ttl_secs: u64) -> Result<ShareToken, TelcoError> {
+        self.issue_share_token(bytes, category, ttl_secs, None)
+    }
+
+    /// Like `create_share_token`, but only `recipient` can redeem the token.
+    pub fn gift_data(&self, recipient: String, bytes: u64, category: QuotaType, ttl_secs: u64) -> Result<ShareToken, TelcoError> {
+        let recipient = recipient.trim().to_string();
+        if recipient.is_empty() { return Err(TelcoError::InvalidCommand("Gift needs a recipient".to_string())); }
+        if recipient == self.state.read().id { return Err(TelcoError::InvalidCommand("Can't gift data to yourself".to_string())); }
+        self.issue_share_token(bytes, category, ttl_secs, Some(recipient))
+    }
+
+    fn issue_share_token(&self, bytes: u64, category: QuotaType, ttl_secs: u64, to: Option<String>) -> Result<ShareToken, TelcoError> {
+        if bytes == 0 { return Err(TelcoError::InvalidCommand("Nothing to share".to_string())); }
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Err(TelcoError::Locked); }
+        let payload = SharePayload { nonce: format!("{:016x}{:016x}", secure_random_u64(), secure_random_u64()), from: lock.id.clone(), bytes, category, expires_at: self.now_secs() + ttl_secs, to };
+        let json = serde_json::to_vec(&payload).map_err(|e| TelcoError::SerializationFailed(e.to_string()))?;
+        let token = format!("{}.{}.{}", SHARE_TOKEN_PREFIX, to_hex(&json), to_hex(&share_signature(&json)));
+        let (shortfall, undo) = lock.drain(bytes, category, &self.consumption_policy.read(), 100, self.now_secs())?;
+        if shortfall > 0 {
+            lock.undo(undo);
+            return Err(TelcoError::InsufficientBalance);
+        }
+        let account = lock.clone();
+        drop(lock);
+        self.notify_and_persist(account, None);
+        self.dispatch_event(TelcoEvent::GiftSent { bytes, category, expires_at: payload.expires_at });
+        Ok(ShareToken { token, bytes, category, expires_at: payload.expires_at })
+    }
+
+    /// Verifies a share token's signature and expiry and adds its data as a promo bucket. Each token redeems once.
+    pub fn redeem_share_token(&self, token: String) -> Result<QuotaBucket, TelcoError> {
+        self.simulate_response_delay("redeem_share_token");
+        let invalid = || TelcoError::InvalidCommand("Invalid share token".to_string());
+        let (json, signature) = match token.trim().split('.').collect::<Vec<_>>().as_slice() {
+            [prefix, json, signature] if prefix.eq_ignore_ascii_case(SHARE_TOKEN_PREFIX) => (from_hex(json).ok_or_else(invalid)?, from_hex(signature).ok_or_else(invalid)?),
+            _ => return Err(invalid()),
+        };
+        // Compare every byte so timing doesn't reveal how much of a forged signature matched.
+        let expected = share_signature(&json);
+        if signature.len() != expected.len() || signature.iter().zip(expected).fold(0, |acc, (a, b)| acc | (a ^ b)) != 0 { return Err(invalid()); }
+        let payload: SharePayload = serde_json::from_slice(&json).map_err(|_| invalid())?;
+        if payload.expires_at <= self.now_secs() { return Err(TelcoError::InvalidCommand("Share token has expired".to_string())); }
+        let account_id = self.state.read().id.clone();
+        if payload.from == account_id { return Err(TelcoError::InvalidCommand("Can't redeem your own share token".to_string())); }
+        if payload.to.as_ref().is_some_and(|to| *to != account_id) { return Err(TelcoError::InvalidCommand("Share token is for another account".to_string())); }
+
+        let already = || TelcoError::InvalidCommand("Share token was already redeemed".to_string());
+        let mut redeemed = self.redeemed_tokens.lock();
+        if redeemed.contains(&payload.nonce) { return Err(already()); }
+        // Another simulator on the same file may have taken it since this one loaded.
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence {
+            let (reply, result) = mpsc::sync_channel(1);
+            p.send_request(PersistenceMsg::ClaimToken { account_id, nonce: payload.nonce.clone(), redeemed_at: self.now_secs(), reply })?;
+            if !result.recv().map_err(|_| TelcoError::StorageUnavailable)?? {
+                redeemed.push(payload.nonce);
+                return Err(already());
+            }
+        }
+        let bucket = QuotaBucket {
+            id: new_id(),
+            name: "Gifted Data".to_string(),
+            remaining_bytes: payload.bytes,
+            initial_bytes: payload.bytes,
+            category: payload.category,
+            expiry: self.now_secs() + BucketSource::Promo.lifetime_secs(),
+            source: BucketSource::Promo,
+            active_window: None,
+            fair_usage: None,
+        };
+        self.add_bucket(bucket.clone()).inspect_err(|_| {
+            #[cfg(feature = "sqlite")]
+            self.persist(PersistenceMsg::ReleaseToken { nonce: payload.nonce.clone() });
+        })?;
+        redeemed.push(payload.nonce);
+        drop(redeemed);
+        self.dispatch_event(TelcoEvent::GiftReceived { bucket: bucket.clone() });
+        Ok(bucket)
+    }
+
+    /// Buys a pool this account owns and shares through `add_family_member`. An account belongs to at most one pool.
+    pub fn create_family_pool(&self, name: String, bytes: u64, category: QuotaType, validity_days: u32) -> Result<FamilyPoolInfo, TelcoError> {
+        let account = self.state.read().clone();
+        if account.biometric_locked { return Err(TelcoError::Locked); }
+        if !category.is_data() { return Err(TelcoError::InvalidCommand("Family pools hold data".to_string())); }
+        let mut registry = FAMILY_POOLS.lock();
+        let pools = self.family_pools(&mut registry);
+        if pools.iter().any(|p| p.lock().members.iter().any(|m| m.account_id == account.id)) {
+            return Err(TelcoError::InvalidCommand("Already in a family pool".to_string()));
+        }
+        let pool = FamilyPool {
+            id: new_id(),
+            owner_id: account.id.clone(),
+            name,
+            remaining_bytes: bytes,
+            category,
+            expiry: self.now_secs() + validity_days as u64 * 86400,
+            members: vec![FamilyMemberUsage { account_id: account.id.clone(), cap_bytes: None, used_bytes: 0 }],
+            version: 1,
+        };
+        self.persist_family_pool(&pool, false);
+        let info = pool.info(&account.id);
+        pools.push(Arc::new(Mutex::new(pool)));
+        Ok(info)
+    }
+
+    /// Owner only. `cap_bytes` limits what the member may draw in total.
+    pub fn add_family_member(&self, account_id: String, cap_bytes: Option<u64>) -> Result<FamilyPoolInfo, TelcoError> {
+        if self.find_family_pool(&account_id).is_some() {
+            return Err(TelcoError::InvalidCommand(format!("'{}' is already in a family pool", account_id)));
+        }
+        self.update_owned_family_pool(|pool| {
+            pool.members.push(FamilyMemberUsage { account_id, cap_bytes, used_bytes: 0 });
+            Ok(())
+        })
+    }
+
+    /// Owner only. Lowering a cap below what the member already used stops further draws.
+    pub fn set_family_member_cap(&self, account_id: String, cap_bytes: Option<u64>) -> Result<FamilyPoolInfo, TelcoError> {
+        self.update_owned_family_pool(|pool| {
+            pool.member_mut(&account_id)?.cap_bytes = cap_bytes;
+            Ok(())
+        })
+    }
+
+    /// Owner only; the owner can't leave their own pool, see `dissolve_family_pool`.
+    pub fn remove_family_member(&self, account_id: String) -> Result<FamilyPoolInfo, TelcoError> {
+        self.update_owned_family_pool(|pool| {
+            if account_id == pool.owner_id { return Err(TelcoError::InvalidCommand("The owner can't leave the pool".to_string())); }
+            pool.member_mut(&account_id)?;
+            pool.members.retain(|m| m.account_id != account_id);
             Ok(())
+        })
+    }
+
+    /// The pool this account owns or draws from, with the per-member usage breakdown the caller may see.
+    pub fn get_family_pool(&self) -> Option<FamilyPoolInfo> {
+        let id = self.state.read().id.clone();
+        self.find_family_pool(&id).map(|p| p.lock().info(&id))
+    }
+
+    /// Owner only. Unused pool data is forfeited.
+    pub fn dissolve_family_pool(&self) -> Result<(), TelcoError> {
+        let pool = self.owned_family_pool()?;
+        let mut registry = FAMILY_POOLS.lock();
+        self.family_pools(&mut registry).retain(|p| !Arc::ptr_eq(p, &pool));
+        drop(registry);
+        let mut pool = pool.lock();
+        pool.version += 1;
+        self.persist_family_pool(&pool, true);
+        Ok(())
+    }
+
+    /// Backfills `days` (1-365) of demo history for `persona`: its operator catalog, usage sessions through the day,
+    /// the starter plan bought every cycle with toppings whenever it runs dry, and current buckets drawn down by the
+    /// usage since their purchase. Plan buckets already on the account are replaced; the wallet isn't charged.
+    pub fn seed_demo_data(&self, persona: Persona, days: u32) -> Result<SeedReport, TelcoError> {
+        if self.state.read().biometric_locked { return Err(TelcoError::Locked); }
+        let days = days.clamp(1, 365) as u64;
+        let profile = persona.preset().profile();
+        let plan = profile.starter_plan.as_ref().and_then(|name| profile.plans.iter().find(|p| &p.name == name)).cloned()
+            .ok_or_else(|| TelcoError::InternalError("persona preset has no starter plan".to_string()))?;
+        self.rollover_policy.write().max_bytes = profile.max_rollover_bytes;
+        *self.operator.write() = profile;
+
+        let now = self.now_secs();
+        let start = now - days * 86400;
+        let cycle_secs = plan.validity_days.max(1) as u64 * 86400;
+        let mut rows = Vec::new();
+        let mut usage_bytes = 0;
+        let mut purchases = 0;
+        // Buckets bought in the current cycle, in the order they are drawn down.
+        let mut current: Vec<QuotaBucket> = Vec::new();
+        let mut cycle_start = None;
+        // Four traffic ticks an hour, with the persona mostly asleep from 01:00 to 07:00.
+        for tick in 0..days * 24 * 4 {
+            let at = start + tick * 900 + random_u64() % 900;
+            if at > now { break; }
+            if cycle_start.is_none_or(|c| at >= c + cycle_secs) {
+                let bought = at - (at - start) % cycle_secs;
+                cycle_start = Some(bought);
+                current = vec![QuotaBucket { expiry: bought + cycle_secs, ..plan.to_bucket(self.now_secs()) }];
+                purchases += 1;
+            }
+            if (60..420).contains(&local_minute(at)) && !random_u64().is_multiple_of(10) { continue; }
+            let Some((bytes, category)) = persona.next_session() else { continue; };
+            let mut left = bytes;
+            for bucket in current.iter_mut() {
+                let take = left.min(bucket.remaining_bytes);
+                bucket.remaining_bytes -= take;
+                left -= take;
+            }
+            if left > 0 {
+                let topping = parse_topping("General 5GB", self.now_secs())?;
+                current.push(QuotaBucket { remaining_bytes: topping.remaining_bytes.saturating_sub(left), expiry: at + BucketSource::Purchase.lifetime_secs(), ..topping });
+                purchases += 1;
+            }
+            usage_bytes += bytes;
+            rows.push(ImportedUsage { timestamp: at, amount: bytes, category: format!("{:?}", category) });
+        }
+
+        let usage_rows = rows.len() as u64;
+        self.import_usage_rows(rows)?;
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Err(TelcoError::Locked); }
+        lock.buckets.retain(|b| b.source != BucketSource::Purchase);
+        lock.buckets.extend(current.into_iter().filter(|b| b.expiry > now));
+        lock.refresh_balances();
+        let account = lock.clone();
+        drop(lock);
+        self.notify_and_persist(account, None);
+        engine_log!(LogLevel::Info, "seed", "seeded {} days of {:?}: {} sessions, {} purchases", days, persona, usage_rows, purchases);
+        Ok(SeedReport { usage_rows, usage_bytes, purchases })
+    }
+
+    /// Serializes the account, its buckets and its whole usage history to JSON, for moving a demo profile to another
+    /// device or saving a fixture. Load it with `import_account_json`.
+    pub fn export_account_json(&self) -> Result<String, TelcoError> {
+        let account = self.state.read().clone();
+        if account.biometric_locked { return Err(TelcoError::Locked); }
+        let usage = self.query_usage(UsageFilter::default())?.into_iter().rev()
+            .filter_map(|r| QuotaType::from_name(&r.category).map(|category| UsageEntry {
+                bytes: r.amount,
+                category,
+                timestamp: r.timestamp,
+                source: r.source,
+                confidence: r.confidence,
+            }))
+            .collect();
+        let export = AccountExport { version: ACCOUNT_EXPORT_VERSION, exported_at: self.now_secs(), account, usage };
+        serde_json::to_string(&export).map_err(|e| TelcoError::SerializationFailed(e.to_string()))
+    }
+
+    /// Replaces this account's buckets, balances and usage history with an `export_account_json` payload. The
+    /// account keeps its own id. Empty or future-dated usage rows are rejected.
+    pub fn import_account_json(&self, payload: String) -> Result<ImportReport, TelcoError> {
+        let export: AccountExport = serde_json::from_str(&payload).map_err(|e| TelcoError::InvalidCommand(format!("Malformed account JSON: {}", e)))?;
+        if export.version > ACCOUNT_EXPORT_VERSION {
+            return Err(TelcoError::InvalidCommand(format!("Unsupported account export version {}", export.version)));
+        }
+        let now = self.now_secs();
+        let total = export.usage.len() as u64;
+        let usage: Vec<UsageEntry> = export.usage.into_iter().filter(|u| u.bytes > 0 && u.timestamp <= now).collect();
+        let rejected = total - usage.len() as u64;
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Err(TelcoError::Locked); }
+        let mut account = UserAccount { id: lock.id.clone(), biometric_locked: false, ..export.account };
+        account.refresh_balances();
+        *lock = account.clone();
+        drop(lock);
+        let imported = usage.len() as u64;
+        #[cfg(feature = "sqlite")]
+        self.persist(PersistenceMsg::ReplaceUsage { account_id: account.id.clone(), usage });
+        self.invalidate_insights();
+        self.notify_and_persist(account, None);
+        engine_log!(LogLevel::Info, "import", "imported account with {} usage rows", imported);
+        if cfg!(feature = "sqlite") {
+            Ok(ImportReport { imported, duplicates: 0, rejected })
+        } else {
+            Ok(ImportReport { imported: 0, duplicates: 0, rejected: total })
+        }
+    }
+
+    /// Imports `timestamp,amount,category` rows (an optional header line is skipped).
+    pub fn import_usage_csv(&self, data: String) -> Result<ImportReport, TelcoError> {
+        let mut rows = Vec::new();
+        let mut rejected = 0;
+        for (i, line) in data.lines().map(str::trim).filter(|l| !l.is_empty()).enumerate() {
+            let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+            let parsed = match fields.as_slice() {
+                [ts, amount, category] => ts.parse().ok().zip(amount.parse().ok()).map(|(timestamp, amount)| ImportedUsage { timestamp, amount, category: category.to_string() }),
+                _ => None,
+            };
+            match parsed {
+                Some(row) => rows.push(row),
+                None if i == 0 => {}
+                None => rejected += 1,
+            }
+        }
+        let mut report = self.import_usage_rows(rows)?;
+        report.rejected += rejected;
+        Ok(report)
+    }
+
+    /// Imports a JSON array of `{ "timestamp", "amount", "category" }` objects.
+    pub fn import_usage_json(&self, data: String) -> Result<ImportReport, TelcoError> {
+        let rows: Vec<ImportedUsage> = serde_json::from_str(&data).map_err(|e| TelcoError::InvalidCommand(format!("Malformed usage JSON: {}", e)))?;
+        self.import_usage_rows(rows)
+    }
+
+    /// The scheduler applies it hourly; call `prune_history` to apply it now.
+    pub fn set_retention_policy(&self, policy: RetentionPolicy) {
+        *self.retention_policy.write() = policy;
+    }
+
+    pub fn get_retention_policy(&self) -> RetentionPolicy {
+        *self.retention_policy.read()
+    }
+
+    /// Removes this account's raw usage rows beyond the retention policy, adding their amounts into one rollup row
+    /// per day and category so daily totals and forecasts stay intact.
+    pub fn prune_history(&self) -> Result<PruneReport, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let policy = *self.retention_policy.read();
+            self.last_prune_at.store(self.now_secs(), Ordering::Relaxed);
+            if policy.max_rows.is_none() && policy.max_age_secs.is_none() { return Ok(PruneReport { rows_pruned: 0, rollup_rows: 0 }); }
+            let cutoff = policy.max_age_secs.map_or(0, |age| self.now_secs().saturating_sub(age));
+            let max_rows = policy.max_rows.map_or(-1, |rows| rows.min(i64::MAX as u64) as i64);
+            let account_id = self.state.read().id.clone();
+            let mut conn = self.open_db()?;
+            let tx = conn.transaction().map_err(db_err)?;
+            tx.execute(
+                &format!("CREATE TEMP TABLE pruned AS SELECT rowid AS row_id, day, category, amount, {} AS segment FROM usage_history
+                 WHERE account_id = ?1 AND source IS NOT 'Rollup' AND (timestamp < ?2 OR rowid NOT IN
+                    (SELECT rowid FROM usage_history WHERE account_id = ?1 AND source IS NOT 'Rollup' ORDER BY timestamp DESC, rowid DESC LIMIT ?3))", SNAPSHOT_SEGMENT),
+                params![account_id, cutoff, max_rows],
+            ).map_err(db_err)?;
+            let rows_pruned: u64 = tx.query_row("SELECT COUNT(*) FROM pruned", [], |row| row.get(0)).map_err(db_err)?;
+            // Earlier rollups for the same days are merged in, so each day keeps one row per category. As in
+            // `compact_usage_history`, a rollup keeps its lowest rowid and never spans a snapshot's history cursor.
+            tx.execute_batch("CREATE TEMP TABLE pruned_days AS SELECT DISTINCT day FROM pruned;").map_err(db_err)?;
+            tx.execute(&format!("INSERT INTO pruned SELECT rowid, day, category, amount, {} FROM usage_history WHERE account_id = ?1 AND source = 'Rollup' AND day IN (SELECT day FROM pruned_days)", SNAPSHOT_SEGMENT),
+                params![account_id]).map_err(db_err)?;
+            tx.execute_batch("DELETE FROM usage_history WHERE rowid IN (SELECT row_id FROM pruned);").map_err(db_err)?;
+            let rollup_rows = tx.execute(
+                "INSERT INTO usage_history (rowid, timestamp, amount, category, account_id, day, source)
+                 SELECT MIN(row_id), day * 86400, SUM(amount), category, ?1, day, 'Rollup' FROM pruned GROUP BY day, category, segment",
+                params![account_id],
+            ).map_err(db_err)? as u64;
+            tx.execute_batch("DROP TABLE pruned; DROP TABLE pruned_days;").map_err(db_err)?;
+            tx.commit().map_err(db_err)?;
+            if rows_pruned > 0 { engine_log!(LogLevel::Info, "retention", "pruned {} usage rows into {} daily rollups", rows_pruned, rollup_rows); }
+            Ok(PruneReport { rows_pruned, rollup_rows })
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            Ok(PruneReport { rows_pruned: 0, rollup_rows: 0 })
+        }
+    }
+
+    /// Merges this account's usage rows older than `horizon_secs` into one row per category, source and minute.
+    /// Other accounts in the same file are left alone; the counts in the report are this account's rows.
+    pub fn compact_usage_history(&self, horizon_secs: u64) -> Result<CompactionReport, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            let mut conn = self.open_db()?;
+            let cutoff = self.now_secs().saturating_sub(horizon_secs);
+            let count = |conn: &Connection| conn.query_row("SELECT COUNT(*) FROM usage_history WHERE account_id = ?1", params![account_id], |row| row.get::<_, u64>(0))
+                .map_err(db_err);
+            let rows_before = count(&conn)?;
+            let tx = conn.transaction().map_err(db_err)?;
+            // Each merged row keeps its group's lowest rowid, and no group spans a snapshot's history cursor, so
+            // `restore_snapshot` still removes exactly the usage recorded after the snapshot.
+            tx.execute(&format!("CREATE TEMP TABLE compacted AS SELECT MIN(row_id) AS row_id, (timestamp / 60) * 60 AS minute, SUM(amount) AS amount, category, source, AVG(confidence) AS confidence
+                 FROM (SELECT rowid AS row_id, timestamp, amount, category, source, confidence, {} AS segment FROM usage_history WHERE account_id = ?1 AND timestamp < ?2)
+                 GROUP BY category, source, timestamp / 60, segment", SNAPSHOT_SEGMENT), params![account_id, cutoff])
+                .map_err(db_err)?;
+            tx.execute("DELETE FROM usage_history WHERE account_id = ?1 AND timestamp < ?2", params![account_id, cutoff])
+                .map_err(db_err)?;
+            tx.execute("INSERT INTO usage_history (rowid, timestamp, amount, category, account_id, day, source, confidence) SELECT row_id, minute, amount, category, ?1, minute / 86400, source, confidence FROM compacted",
+                params![account_id])
+                .map_err(db_err)?;
+            tx.execute_batch("DROP TABLE compacted;").map_err(db_err)?;
+            tx.commit().map_err(db_err)?;
+            Ok(CompactionReport { rows_before, rows_after: count(&conn)? })
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = horizon_secs;
+            Ok(CompactionReport { rows_before: 0, rows_after: 0 })
+        }
+    }
+
+    /// Attributes traffic to a destination observed via DNS or SNI, categorized through the host classifier or domain rules.
+    /// Returns the category the bytes were charged to.
+    pub fn record_domain_traffic(&self, domain: String, bytes: u64) -> Result<QuotaType, TelcoError> {
+        let category = self.categorize(domain);
+        self.record_usage(bytes, category, UsageSource::Sensor, None)?;
+        Ok(category)
+    }
+
+    /// Returns the category traffic to `domain` would be charged to, without recording anything.
+    pub fn categorize(&self, domain: String) -> QuotaType {
+        if let Some(category) = self.traffic_classifier.read().as_ref().and_then(|c| c.classify(domain.clone())) {
+            return category;
+        }
+        categorize_with(&self.category_rules.read(), &domain)
+    }
+
+    pub fn set_traffic_classifier(&self, classifier: Box<dyn TrafficClassifier>) {
+        *self.traffic_classifier.write() = Some(classifier);
+    }
+
+    /// Reverts attribution to the built-in domain rules.
+    pub fn clear_traffic_classifier(&self) {
+        *self.traffic_classifier.write() = None;
+    }
+
+    /// Replaces the platform's interface counters for the sensor and `list_interfaces`.
+    pub fn set_traffic_source(&self, source: Box<dyn TrafficSource>) {
+        *self.traffic_source.write() = Some(source);
+    }
+
+    /// Goes back to the built-in counters for this platform.
+    pub fn clear_traffic_source(&self) {
+        *self.traffic_source.write() = None;
+    }
+
+    pub fn list_category_rules(&self) -> Vec<CategoryRule> {
+        let mut rules = self.category_rules.read().clone();
+        rules.sort_by(|a, b| b.priority.cmp(&a.priority).then_with(|| a.pattern.cmp(&b.pattern)));
+        rules
+    }
+
+    /// Adds a rule, replacing any existing rule for the same pattern.
+    pub fn upsert_category_rule(&self, rule: CategoryRule) -> Result<(), TelcoError> {
+        let pattern = rule.pattern.trim().trim_start_matches("*.").trim_end_matches('.').to_lowercase();
+        if pattern.is_empty() { return Err(TelcoError::InvalidCommand("Category rule pattern is empty".to_string())); }
+        let mut rules = self.category_rules.write();
+        rules.retain(|r| r.pattern != pattern);
+        rules.push(CategoryRule { pattern, ..rule });
+        self.persist_category_rules(rules.clone());
+        Ok(())
+    }
+
+    pub fn remove_category_rule(&self, pattern: String) -> Result<(), TelcoError> {
+        let pattern = pattern.trim().trim_start_matches("*.").trim_end_matches('.').to_lowercase();
+        let mut rules = self.category_rules.write();
+        let before = rules.len();
+        rules.retain(|r| r.pattern != pattern);
+        if rules.len() == before { return Err(TelcoError::InvalidCommand(format!("No category rule for '{}'", pattern))); }
+        self.persist_category_rules(rules.clone());
+        Ok(())
+    }
+
+    pub fn reset_category_rules(&self) {
+        let mut rules = self.category_rules.write();
+        *rules = default_category_rules();
+        self.persist_category_rules(Vec::new());
+    }
+
+    pub fn set_vpn_policy(&self, policy: VpnPolicy) {
+        *self.vpn_policy.write() = policy;
+    }
+
+    /// Takes effect from the sensor's next sample; newly selected interfaces start counting from there.
+    pub fn set_sensor_interfaces(&self, selection: InterfaceSelection) {
+        *self.sensor_interfaces.write() = InterfaceFilter::new(selection);
+    }
+
+    pub fn get_sensor_interfaces(&self) -> InterfaceSelection {
+        self.sensor_interfaces.read().selection.clone()
+    }
+
+    /// The interfaces the sensor can see, with their counters, so apps can let the user pick. Empty on wasm unless
+    /// a `TrafficSource` is set.
+    pub fn list_interfaces(&self) -> Vec<NetworkInterface> {
+        let counters = self.read_traffic();
+        let selection = self.sensor_interfaces.read();
+        counters.into_iter()
+            .map(|c| NetworkInterface { tunnel: is_tunnel_interface(&c.name), selected: selection.selects(&c.name), name: c.name, rx_bytes: c.rx_bytes, tx_bytes: c.tx_bytes })
+            .collect()
+    }
+
+    pub fn set_diagnostics_handler(&self, handler: Box<dyn DiagnosticsHandler>) {
+        *self.diagnostics_handler.write() = Some(handler);
+    }
+
+    /// Starts a background check that restarts dead persistence/sensor threads and reports stalls through the
+    /// diagnostics handler. It holds only a weak reference and exits once the simulator is dropped.
+    pub fn start_watchdog(self: Arc<Self>) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let sim = Arc::downgrade(&self);
+            drop(self);
+            thread::spawn(move || {
+                let mut stalled: Vec<WorkerKind> = Vec::new();
+                loop {
+                    thread::sleep(std::time::Duration::from_secs(2));
+                    let Some(sim) = sim.upgrade() else { return; };
+                    sim.check_workers(&mut stalled);
+                }
+            });
+        }
+    }
+
+    /// Probes the database and background workers for a host diagnostics screen.
+    pub fn health_check(&self) -> HealthReport {
+        #[cfg(not(target_arch = "wasm32"))]
+        let sensor = worker_status(self.sensor_worker.lock().as_ref(), self.sensor_heartbeat.load(Ordering::Relaxed));
+        #[cfg(target_arch = "wasm32")]
+        let sensor = WorkerStatus::NotStarted;
+
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence {
+            let conn = open_connection(&self.db_path, self.db_key.read().as_ref()).ok();
+            let db_reachable = conn.as_ref().is_some_and(|c| c.query_row("SELECT 1", [], |_| Ok(())).is_ok());
+            // Taking the write lock proves the file is writable and not held by another writer.
+            let db_writable = conn.as_ref().is_some_and(|c| c.execute_batch("BEGIN IMMEDIATE; ROLLBACK;").is_ok());
+            let persistence_worker = worker_status(p.worker.lock().as_ref(), p.stats.heartbeat_ms.load(Ordering::Relaxed));
+            let last_write_at = Some(p.stats.last_write_at.load(Ordering::Relaxed)).filter(|t| *t > 0);
+            return HealthReport {
+                healthy: db_reachable && db_writable && persistence_worker == WorkerStatus::Running && !matches!(sensor, WorkerStatus::Stalled | WorkerStatus::Dead),
+                db_reachable,
+                db_writable,
+                persistence_queue_depth: p.stats.queued.load(Ordering::Relaxed),
+                persistence_worker,
+                sensor,
+                last_write_at,
+            };
+        }
+        // In-memory simulators have no database to check.
+        HealthReport {
+            healthy: !matches!(sensor, WorkerStatus::Stalled | WorkerStatus::Dead),
+            db_reachable: false,
+            db_writable: false,
+            persistence_queue_depth: 0,
+            persistence_worker: WorkerStatus::NotStarted,
+            sensor,
+            last_write_at: None,
+        }
+    }
+
+    /// No-op if the sensor is already running.
+    pub fn start_network_sensor(self: Arc<Self>) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut worker = self.sensor_worker.lock();
+            if worker.as_ref().is_some_and(|h| !h.is_finished()) { return; }
+            engine_log!(LogLevel::Info, "sensor", "starting network sensor");
+            let sim = self.clone();
+            *worker = Some(thread::spawn(move || {
+                let mut last_bytes: HashMap<String, u64> = HashMap::new();
+                let mut detector = BurstDetector::default();
+                loop {
+                    sim.sensor_heartbeat.store(real_now_ms(), Ordering::Relaxed);
+                    let counters = sim.read_traffic();
+                    if !counters.is_empty() {
+                        let (mut physical, mut tunnel) = (0u64, 0u64);
+                        let selection = sim.sensor_interfaces.read().clone();
+                        for InterfaceCounters { name, rx_bytes: bytes, .. } in counters {
+                            if !selection.selects(&name) { last_bytes.remove(&name); continue; }
+                            let tunnelled = is_tunnel_interface(&name);
+                            if let Some(prev) = last_bytes.insert(name, bytes) {
+                                let diff = bytes.saturating_sub(prev);
+                                if tunnelled { tunnel += diff; } else { physical += diff; }
+                            }
+                        }
+                        let (category, confidence) = detector.observe(physical);
+                        // Traffic seen while the radio is off is skipped rather than attributed later.
+                        if !sim.is_flight_mode() {
+                            let now = sim.now_secs();
+                            let entries: Vec<UsageEntry> = sim.vpn_policy.read().attribute(physical, tunnel).into_iter()
+                                .filter(|(diff, _)| *diff > 0)
+                                .map(|(bytes, source)| UsageEntry { bytes, category, timestamp: now, source, confidence: Some(confidence) })
+                                .collect();
+                            if !entries.is_empty() { let _ = sim.record_usage_batch(entries); }
+                        }
+                    }
+                    sim_sleep(500);
+                }
+            }));
+        }
+    }
+}
+
+/// Async counterparts for servers (e.g. Axum handlers) that must not block their executor. SQLite (through
+/// rusqlite) has no non-blocking API, so there is no truly async database path to expose: each call runs the
+/// blocking method on tokio's bounded blocking thread pool (`spawn_blocking`) and resolves when it finishes, or with
+/// `InternalError` if it panics. Called from outside a tokio runtime (e.g. from a foreign binding), the calls run on
+/// a shared one.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+#[uniffi::export(async_runtime = "tokio")]
+impl TelcoSimulator {
+    pub async fn simulate_usage_async(self: Arc<Self>, bytes: u64, category: QuotaType) -> Result<(), TelcoError> {
+        run_blocking(move || self.simulate_usage(bytes, category)).await?
+    }
+
+    pub async fn handle_command_async(self: Arc<Self>, command: String) -> Result<String, TelcoError> {
+        run_blocking(move || self.handle_command(command)).await
+    }
+
+    pub async fn get_account_info_async(self: Arc<Self>) -> Result<UserAccount, TelcoError> {
+        run_blocking(move || self.get_account_info()).await?
+    }
+
+    pub async fn get_historical_usage_async(self: Arc<Self>, limit: u32) -> Result<Vec<UsageRecord>, TelcoError> {
+        run_blocking(move || self.get_historical_usage(limit)).await?
+    }
+
+    pub async fn query_usage_async(self: Arc<Self>, filter: UsageFilter) -> Result<Vec<UsageRecord>, TelcoError> {
+        run_blocking(move || self.query_usage(filter)).await?
+    }
+
+    pub async fn query_usage_page_async(self: Arc<Self>, filter: UsageFilter, cursor: Option<HistoryCursor>, page_size: u32) -> Result<UsagePage, TelcoError> {
+        run_blocking(move || self.query_usage_page(filter, cursor, page_size)).await?
+    }
+
+    pub async fn import_usage_csv_async(self: Arc<Self>, data: String) -> Result<ImportReport, TelcoError> {
+        run_blocking(move || self.import_usage_csv(data)).await?
+    }
+
+    pub async fn compact_usage_history_async(self: Arc<Self>, horizon_secs: u64) -> Result<CompactionReport, TelcoError> {
+        run_blocking(move || self.compact_usage_history(horizon_secs)).await?
+    }
+
+    pub async fn health_check_async(self: Arc<Self>) -> Result<HealthReport, TelcoError> {
+        run_blocking(move || self.health_check()).await
+    }
+}
+
+// Runs `f` on tokio's blocking pool, turning a panic into `InternalError` rather than unwinding into the caller.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+async fn run_blocking<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Result<T, TelcoError> {
+    tokio::task::spawn_blocking(f).await.map_err(|e| {
+        let Ok(panic) = e.try_into_panic() else { return TelcoError::InternalError("blocking task was cancelled".to_string()) };
+        let reason = panic.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        TelcoError::InternalError(format!("blocking task panicked: {}", reason))
+    })
+}
+
+impl TelcoSimulator {
+    // Renders the node at `path`, running it if it is an action. `notice` goes above a menu.
+    fn ussd_screen(&self, path: &[u32], notice: Option<Msg>) -> UssdResponse {
+        let node = self.ussd_node(path);
+        if let Some(UssdNode::Action(action)) = node {
+            return UssdResponse { message: self.run_ussd_action(action), session_active: false };
+        }
+        let locale = self.locale.read();
+        match node {
+            Some(UssdNode::Menu { title, options }) => {
+                let mut lines: Vec<String> = notice.map(|n| locale.text(n, &[])).into_iter().chain([title]).collect();
+                lines.extend(options.iter().enumerate().map(|(i, o)| format!("{}. {}", i + 1, o)));
+                if !path.is_empty() { lines.push(format!("0. {}", locale.text(Msg::Back, &[]))); }
+                UssdResponse { message: lines.join("\n"), session_active: true }
+            }
+            Some(UssdNode::Action(_)) | None => UssdResponse { message: locale.text(Msg::InvalidChoice, &[]), session_active: false },
+        }
+    }
+
+    // The menu tree, looked up without side effects; `None` means the path doesn't exist.
+    fn ussd_node(&self, path: &[u32]) -> Option<UssdNode> {
+        let text = |msg: Msg| self.locale.read().text(msg, &[]);
+        let menu = |title: Msg, options: Vec<String>| Some(UssdNode::Menu { title: text(title), options });
+        let action = |action: UssdAction| Some(UssdNode::Action(action));
+        match path {
+            [] => menu(Msg::Services, [Msg::BuyData, Msg::BalanceMenu, Msg::UsageHistory, Msg::WalletMenu].map(text).to_vec()),
+            [1] => menu(Msg::BuyData, vec![text(Msg::Plans), text(Msg::Toppings)]),
+            [1, 1] => {
+                let plans = self.operator.read().plans.iter().map(|p| format!("{} ({}.{:02})", p.name, p.price_cents / 100, p.price_cents % 100)).collect();
+                menu(Msg::Plans, plans)
+            }
+            [1, 1, n] => action(UssdAction::BuyPlan(self.operator.read().plans.get((*n as usize).checked_sub(1)?)?.name.clone())),
+            [1, 2] => menu(Msg::Toppings, USSD_TOPPINGS.map(str::to_string).to_vec()),
+            [1, 2, n] => action(UssdAction::BuyTopping(USSD_TOPPINGS.get((*n as usize).checked_sub(1)?)?)),
+            [2] => action(UssdAction::Balance),
+            [3] => action(UssdAction::History),
+            [4] => action(UssdAction::Wallet),
+            _ => None,
+        }
+    }
+
+    fn run_ussd_action(&self, action: UssdAction) -> String {
+        let result = match action {
+            UssdAction::BuyPlan(plan) => self.buy_plan(plan.clone()).map(|_| self.locale.read().text(Msg::Bought, &[&plan])),
+            UssdAction::BuyTopping(topping) => self.parse_and_buy_topping(topping.to_string()).map(|_| self.locale.read().text(Msg::Bought, &[&topping])),
+            UssdAction::Balance => Ok(self.describe_balance()),
+            UssdAction::History => self.describe_history(5),
+            UssdAction::Wallet => {
+                let cents = self.state.read().wallet_cents;
+                let currency = self.operator.read().currency.clone();
+                let locale = self.locale.read();
+                Ok(locale.text(Msg::Wallet, &[&locale.format_money(cents), &currency]))
+            }
+        };
+        result.unwrap_or_else(|e| self.locale.read().error(&e))
+    }
+
+    // Returns the entries that went through and the (index, error) of those that did not. Caps and budgets are
+    // charged at the current time; the entry timestamps only date the history rows and events.
+    fn record_usage_batch(&self, entries: Vec<UsageEntry>) -> Result<(Vec<UsageEntry>, Vec<RejectedEntry>), TelcoError> {
+        self.check_data_apn()?;
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Err(TelcoError::Locked); }
+        if lock.registration == RegistrationState::Off { return Err(TelcoError::FlightMode); }
+        if !lock.registration.allows_data() { return Err(TelcoError::NotRegistered(lock.registration)); }
+
+        let now = self.now_secs();
+        let (mut applied, mut rejected, mut breaches, mut grace_events) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for (index, entry) in entries.into_iter().enumerate() {
+            // Grace, throttling and the data budget don't apply to calls and texts.
+            if !entry.category.is_data() {
+                rejected.push((index, TelcoError::InvalidCommand(format!("{:?} usage goes through simulate_call or simulate_sms", entry.category))));
+                continue;
+            }
+            let breach = self.caps.lock().check_usage(entry.bytes, entry.category, now);
+            if let Some(breach) = breach {
+                breaches.push((index, breach));
+                continue;
+            }
+            match self.consume_with_grace(&mut lock, entry.bytes, entry.category) {
+                Ok(grace_event) => {
+                    self.caps.lock().record_usage(entry.bytes, entry.category, now);
+                    grace_events.extend(grace_event);
+                    applied.push(entry);
+                }
+                Err(e) => {
+                    engine_log!(LogLevel::Debug, "usage", "rejected {} bytes of {:?}: {}", entry.bytes, entry.category, e);
+                    rejected.push((index, e));
+                }
+            }
+        }
+        let published = (!applied.is_empty()).then(|| (self.write_intent(&lock, &applied), lock.clone()));
+        drop(lock);
+
+        for (index, breach) in breaches { rejected.push((index, self.cap_exceeded(breach))); }
+        rejected.sort_by_key(|(index, _)| *index);
+        let Some((seq, account)) = published else { return Ok((applied, rejected)); };
+
+        for u in &applied { self.run_after_usage_hook(u.bytes, u.category, &account); }
+        self.publish(account, applied.clone(), seq);
+        for u in &applied {
+            self.dispatch_event(TelcoEvent::UsageRecorded { bytes: u.bytes, category: u.category, timestamp: u.timestamp });
+            self.track_budget(u.bytes, now);
+            self.record_telemetry(match u.category {
+                QuotaType::General => "usage.general", QuotaType::Social => "usage.social", QuotaType::Video => "usage.video",
+                QuotaType::Voice => "usage.voice", QuotaType::Sms => "usage.sms",
+            });
+        }
+        for event in grace_events { self.dispatch_event(event); }
+        Ok((applied, rejected))
+    }
+
+    fn run_scenario_action(&self, action: ScenarioAction) -> Result<(), TelcoError> {
+        match action {
+            ScenarioAction::Consume { bytes, category } => self.simulate_usage(bytes, category),
+            ScenarioAction::BuyTopping { category, bytes } => self.execute_command(TelcoCommand::BuyTopping { category, bytes }).map(|_| ()),
+            ScenarioAction::BuyPlan { plan } => self.buy_plan(plan),
+            ScenarioAction::TopUp { cents } => self.top_up_wallet(cents).map(|_| ()),
+            ScenarioAction::ExpireBucket { bucket } => {
+                let now = self.now_secs();
+                let mut lock = self.state.write();
+                if lock.biometric_locked { return Err(TelcoError::Locked); }
+                let target = lock.buckets.iter_mut().find(|b| b.id == bucket || b.name.eq_ignore_ascii_case(&bucket))
+                    .ok_or_else(|| TelcoError::InvalidCommand(format!("No bucket '{}'", bucket)))?;
+                target.expiry = target.expiry.min(now);
+                drop(lock);
+                self.process_expirations();
+                Ok(())
+            }
+            ScenarioAction::Signal { dbm } => {
+                self.set_signal_strength(dbm);
+                Ok(())
+            }
+            ScenarioAction::Tick => {
+                self.tick();
+                Ok(())
+            }
+        }
+    }
+
+    // The network generation's baseline scaled by the region (Global's 50 ms midpoint = 1x), plus jitter.
+    fn sample_latency(&self) -> u32 {
+        let profile = self.network.read().profile();
+        let region = self.region.read();
+        let base = profile.latency_ms as u64 * (region.latency_min_ms + region.latency_max_ms) as u64 / 100;
+        let jitter = profile.jitter_ms as u64 * (region.latency_min_ms + region.latency_max_ms) as u64 / 100;
+        let offset = random_u64() % (2 * jitter + 1);
+        (base + offset).saturating_sub(jitter).max(1) as u32
+    }
+
+    // Moves latency along with the network; throttling, grace and a radio that's off keep theirs.
+    // Updated in place: a new sample alone isn't worth a journal entry, a database write and a full account push.
+    // Handlers see it with the next real change, and `get_account_info` always has the latest.
+    fn refresh_latency(&self) {
+        let mut lock = self.state.write();
+        if lock.throttled || lock.grace.is_some() || !lock.registration.allows_data() { return; }
+        lock.current_latency_ms = self.sample_latency();
+    }
+
+    fn read_traffic(&self) -> Vec<InterfaceCounters> {
+        match &*self.traffic_source.read() {
+            Some(source) => source.read_counters(),
+            None => platform_counters(),
+        }
+    }
+
+    // Percent charged against home buckets: the zone's multiplier while roaming in one, otherwise face value.
+    fn roaming_rate(&self, account: &UserAccount) -> u32 {
+        if account.registration != RegistrationState::Roaming { return 100; }
+        self.roaming_zone.read().as_ref().map_or(100, |z| z.multiplier_percent)
+    }
+
+    // Buys the named roaming pass, or the operator's first one. Returns the plan bought.
+    fn buy_roaming_pass(&self, name: Option<&str>) -> Result<String, TelcoError> {
+        let plan = self.operator.read().plans.iter()
+            .find(|p| p.roaming && name.is_none_or(|n| p.name.eq_ignore_ascii_case(n.trim())))
+            .map(|p| p.name.clone())
+            .ok_or_else(|| TelcoError::InvalidCommand(match name {
+                Some(n) => format!("Unknown roaming pass '{}'", n.trim()),
+                None => "No roaming pass on offer".to_string(),
+            }))?;
+        self.buy_plan(plan.clone())?;
+        Ok(plan)
+    }
+
+    fn walk_signal(&self) {
+        if *self.signal_mode.read() != SignalMode::RandomWalk { return; }
+        let current = self.state.read().signal_dbm;
+        let step = (random_u64() % (2 * SIGNAL_WALK_STEP_DB + 1)) as i32 - SIGNAL_WALK_STEP_DB as i32;
+        self.apply_signal((current + step).clamp(SIGNAL_WALK_RANGE.0, SIGNAL_WALK_RANGE.1));
+    }
+
+    fn apply_signal(&self, dbm: i32) {
+        let mut lock = self.state.write();
+        if lock.signal_dbm == dbm { return; }
+        lock.signal_dbm = dbm;
+        lock.signal_bars = signal_bars(dbm);
+        // Flight mode owns registration while the radio is off.
+        let mut lost_from = self.coverage_lost_from.lock();
+        let from = lock.registration;
+        let to = match (lock.signal_bars, *lost_from) {
+            (0, None) if from != RegistrationState::Off && from != RegistrationState::Searching => {
+                *lost_from = Some(from);
+                Some(RegistrationState::Searching)
+            }
+            (bars, Some(prev)) if bars > 0 => {
+                *lost_from = None;
+                (from == RegistrationState::Searching).then_some(prev)
+            }
+            _ => None,
+        };
+        drop(lost_from);
+        let reading = SignalReading { dbm, bars: lock.signal_bars, generation: *self.network.read() };
+        // The reading itself only goes to the signal handler; the account is pushed and saved when registration moves.
+        if let Some(to) = to {
+            lock.registration = to;
+            let account = lock.clone();
+            drop(lock);
+            self.notify_and_persist(account, None);
+            self.dispatch_event(TelcoEvent::RegistrationChanged { from, to });
+        } else {
+            drop(lock);
+        }
+        if let Some(h) = &*self.signal_handler.read() {
+            self.delay_callback();
+            h.on_signal(reading);
+        }
+    }
+
+    fn install_preset(&self, preset: OperatorPreset) -> Result<(), TelcoError> {
+        let profile = preset.profile();
+        let starter = profile.starter_plan.as_ref().and_then(|name| profile.plans.iter().find(|p| &p.name == name)).map(|p| p.to_bucket(self.now_secs()));
+        self.rollover_policy.write().max_bytes = profile.max_rollover_bytes;
+        *self.operator.write() = profile;
+        if let Some(bucket) = starter {
+            if self.state.read().buckets.is_empty() { self.add_bucket(bucket)?; }
+        }
+        Ok(())
+    }
+
+    fn open(id: String, db_path: String, key: Option<SecretString>) -> Result<Arc<Self>, TelcoError> {
+        register_sensitive(&id);
+        register_sensitive(&db_path);
+        let db_key = Arc::new(RwLock::new(key));
+        #[cfg(feature = "sqlite")]
+        {
+            let (account, persisted, persistence) = open_persistence(&id, &db_path, db_key.clone(), false)?;
+            Ok(Self::assemble(id, db_path, account, persisted, db_key, Some(persistence)))
+        }
+        // Browsers have no file system to put a database in; IndexedDB keeps the account across reloads instead.
+        #[cfg(all(target_arch = "wasm32", not(feature = "sqlite")))]
+        {
+            let _ = db_key;
+            Self::with_backend(id, Box::new(IndexedDbBackend { db_name: db_path }))
+        }
+        #[cfg(all(not(target_arch = "wasm32"), not(feature = "sqlite")))]
+        {
+            Ok(Self::assemble(id.clone(), db_path, UserAccount::fresh(&id), PersistedState::default(), db_key))
+        }
+    }
+
+    fn assemble(id: String, db_path: String, account: UserAccount, persisted: PersistedState, db_key: Arc<RwLock<Option<SecretString>>>, #[cfg(feature = "sqlite")] persistence: Option<Persistence>) -> Arc<Self> {
+        Arc::new(Self { 
+            change_log: Mutex::new(ChangeLog::new(account.clone())),
+            quota_watch: Mutex::new(QuotaWatch::new(&account)),
+            state: Arc::new(RwLock::new(account)), 
+            db_path,
+            db_key,
+            update_handler: RwLock::new(None),
+            delta_handler: RwLock::new(None),
+            low_balance_bytes: AtomicU64::new(DEFAULT_LOW_BALANCE_BYTES),
+            event_handler: RwLock::new(None),
+            lifecycle_hooks: RwLock::new(None),
+            quiet_hours: RwLock::new(None),
+            event_buffer: Mutex::new(persisted.events),
+            ad_reward_policy: RwLock::new(AdRewardPolicy::default()),
+            ad_rewards: Mutex::new(persisted.ad_rewards),
+            achievement_config: RwLock::new(AchievementConfig::default()),
+            budget: Mutex::new(BudgetTracker::default()),
+            caps: Mutex::new(CapTracker::default()),
+            notification_thresholds: RwLock::new(NotificationThresholds::default()),
+            achievements: Mutex::new(persisted.achievements),
+            experiments: RwLock::new(HashMap::from([
+                (RECOMMENDATION_EXPERIMENT.to_string(), vec!["control".to_string(), "aggressive".to_string()]),
+            ])),
+            experiment_assignments: Mutex::new(persisted.experiment_assignments),
+            telemetry: Mutex::new(Telemetry::default()),
+            insight_cache: Mutex::new(InsightCache::default()),
+            forecast_config: RwLock::new(ForecastConfig::default()),
+            insight_config: RwLock::new(persisted.insight_config.unwrap_or_default()),
+            response_delays: RwLock::new(HashMap::new()),
+            chaos: RwLock::new(ChaosConfig::default()),
+            operator: RwLock::new(OperatorPreset::Custom.profile()),
+            region: RwLock::new(RegionPreset::Global.profile()),
+            network: RwLock::new(NetworkGeneration::FourG),
+            roaming_zone: RwLock::new(None),
+            signal_mode: RwLock::new(SignalMode::RandomWalk),
+            signal_handler: RwLock::new(None),
+            coverage_lost_from: Mutex::new(None),
+            apns: RwLock::new(default_apns()),
+            sim_identity: RwLock::new(SimIdentity {
+                iccid: format!("8901{:015}", stable_hash(&id) % 1_000_000_000_000_000),
+                msisdn: format!("+1555{:07}", stable_hash(&id) % 10_000_000),
+                operator: "Ferrum Mobile".to_string(),
+            }),
+            sim_requests: Mutex::new(Vec::new()),
+            ussd_session: Mutex::new(None),
+            backend: RwLock::new(None),
+            clock: RwLock::new(None),
+            clock_offset_secs: AtomicU64::new(0),
+            vpn_policy: RwLock::new(VpnPolicy::CountOnce),
+            sensor_interfaces: RwLock::new(InterfaceFilter::new(InterfaceSelection::Auto)),
+            // An account that never edited its rules (or cleared them all) uses the bundled defaults.
+            category_rules: RwLock::new(if persisted.category_rules.is_empty() { default_category_rules() } else { persisted.category_rules }),
+            traffic_classifier: RwLock::new(None),
+            traffic_source: RwLock::new(None),
+            bucket_order: RwLock::new(BucketSortOrder::Insertion),
+            trash: Mutex::new(persisted.trashed_buckets),
+            pending_orders: Mutex::new(persisted.pending_orders),
+            snapshots: Mutex::new(persisted.snapshots),
+            redeemed_tokens: Mutex::new(persisted.redeemed_tokens),
+            subscriptions: Mutex::new(persisted.subscriptions),
+            preferences: RwLock::new(persisted.preferences),
+            grace_policy: RwLock::new(GracePolicy::default()),
+            throttle_policy: RwLock::new(ThrottlePolicy::default()),
+            rollover_policy: RwLock::new(RolloverPolicy::default()),
+            consumption_policy: RwLock::new(ConsumptionPolicy::default()),
+            trash_retention_secs: AtomicU64::new(DEFAULT_TRASH_RETENTION_SECS),
+            retention_policy: RwLock::new(RetentionPolicy::default()),
+            last_prune_at: AtomicU64::new(0),
+            locale: RwLock::new(Locale::parse("en-US")),
+            diagnostics_handler: RwLock::new(None),
+            sensor_heartbeat: AtomicU64::new(0),
+            #[cfg(not(target_arch = "wasm32"))]
+            sensor_worker: Mutex::new(None),
+            #[cfg(feature = "sqlite")]
+            persistence,
+        })
+    }
+
+    // Seconds since the epoch on this simulator's clock, plus whatever scenarios skipped ahead.
+    fn now_secs(&self) -> u64 {
+        let now = self.clock.read().as_ref().map_or_else(now_secs, |clock| clock.now_ms() / 1000);
+        now.saturating_add(self.clock_offset_secs.load(Ordering::Relaxed))
+    }
+
+    fn notify_and_persist(&self, account: UserAccount, usage: Option<UsageEntry>) {
+        let usage: Vec<UsageEntry> = usage.into_iter().collect();
+        let seq = self.write_intent(&account, &usage);
+        self.publish(account, usage, seq);
+    }
+
+    // Journals the resulting state so a crash before the background writer catches up can be replayed on startup.
+    fn write_intent(&self, _account: &UserAccount, _usage: &[UsageEntry]) -> u64 {
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence {
+            return p.journal.lock().append(_account, _usage);
+        }
+        0
+    }
+
+    fn publish(&self, account: UserAccount, _usage: Vec<UsageEntry>, _seq: u64) {
+        if !_usage.is_empty() { self.invalidate_insights(); }
+        let account = Arc::new(account);
+        let previous = self.change_log.lock().record(&account, self.now_secs());
+        self.watch_quota(&account);
+        if let Some(handler) = &*self.update_handler.read() {
+            self.delay_callback();
+            handler.on_account_updated(self.snapshot((*account).clone()));
+        }
+        if let Some(handler) = &*self.delta_handler.read() {
+            self.delay_callback();
+            let deltas = account_deltas(&previous, &account, &_usage, self.low_balance_bytes.load(Ordering::Relaxed), self.now_secs());
+            if !deltas.is_empty() { handler.on_deltas(deltas); }
+        }
+        if let Some(backend) = &*self.backend.read() {
+            for u in _usage {
+                if let Err(e) = backend.append_usage(account.id.clone(), u.into()) { engine_log!(LogLevel::Warn, "backend", "append_usage failed: {}", e); }
+            }
+            if let Err(e) = backend.save_account(Arc::unwrap_or_clone(account)) { engine_log!(LogLevel::Warn, "backend", "save_account failed: {}", e); }
+        } else {
+            #[cfg(feature = "sqlite")]
+            self.persist(PersistenceMsg::Account { account, usage: _usage, journal_seq: _seq });
+        }
+    }
+
+    fn watch_quota(&self, account: &UserAccount) -> u32 {
+        let thresholds = self.notification_thresholds.read().clone();
+        let events = self.quota_watch.lock().evaluate(account, &thresholds, self.now_secs());
+        let sent = events.len() as u32;
+        for event in events { self.dispatch_event(event); }
+        sent
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn check_workers(self: &Arc<Self>, stalled: &mut Vec<WorkerKind>) {
+        #[cfg(feature = "sqlite")]
+        if let Some(p) = &self.persistence {
+            let mut worker = p.worker.lock();
+            if worker.as_ref().is_some_and(|h| h.is_finished()) {
+                *worker = Some(self.spawn_writer(p));
+                drop(worker);
+                self.report_diagnostic(DiagnosticEvent::WorkerRestarted { worker: WorkerKind::Persistence });
+            } else {
+                let closed = worker.is_none();
+                drop(worker);
+                if !closed { self.check_heartbeat(WorkerKind::Persistence, p.stats.heartbeat_ms.load(Ordering::Relaxed), stalled); }
+            }
+        }
+        let dead = self.sensor_worker.lock().as_ref().is_some_and(|h| h.is_finished());
+        if dead {
+            self.clone().start_network_sensor();
+            self.report_diagnostic(DiagnosticEvent::WorkerRestarted { worker: WorkerKind::Sensor });
+        } else if self.sensor_worker.lock().is_some() {
+            self.check_heartbeat(WorkerKind::Sensor, self.sensor_heartbeat.load(Ordering::Relaxed), stalled);
+        }
+    }
+
+    // Reports a stall once when it starts and once when the worker catches up again.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn check_heartbeat(&self, worker: WorkerKind, heartbeat_ms: u64, stalled: &mut Vec<WorkerKind>) {
+        let silent_ms = real_now_ms().saturating_sub(heartbeat_ms);
+        let was_stalled = stalled.contains(&worker);
+        if silent_ms >= WORKER_STALL_MS && !was_stalled {
+            stalled.push(worker);
+            self.report_diagnostic(DiagnosticEvent::WorkerStalled { worker, silent_ms });
+        } else if silent_ms < WORKER_STALL_MS && was_stalled {
+            stalled.retain(|w| *w != worker);
+            self.report_diagnostic(DiagnosticEvent::WorkerRecovered { worker });
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn spawn_writer(&self, p: &Persistence) -> thread::JoinHandle<()> {
+        spawn_persistence_worker(self.db_path.clone(), self.db_key.clone(), p.config.clone(), p.rx.clone(), p.journal.clone(), p.stats.clone())
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn persist(&self, msg: PersistenceMsg) {
+        let Some(p) = &self.persistence else { return; };
+        if p.closed.load(Ordering::SeqCst) { return; }
+        // Counted before it's sent so the worker's decrement can never land first.
+        p.stats.queued.fetch_add(1, Ordering::Relaxed);
+        if p.tx.try_send(msg).is_err() {
+            p.stats.queued.fetch_sub(1, Ordering::Relaxed);
+            let dropped_writes = p.stats.dropped_writes.fetch_add(1, Ordering::Relaxed) + 1;
+            p.stats.report(PersistenceFailure::WriteDropped { dropped_writes });
+        }
+    }
+
+    /// Checks out a pooled read connection. Ephemeral simulators get a fresh in-memory database so queries run
+    /// against an empty history.
+    #[cfg(feature = "sqlite")]
+    fn open_db(&self) -> Result<PooledConnection<'_>, TelcoError> {
+        if injected_fault(self.chaos.read().read_failure_percent) {
+            return Err(TelcoError::DatabaseError { failure: StorageFailure::Busy, message: "injected read fault".to_string() });
+        }
+        let Some(p) = &self.persistence else {
+            let conn = Connection::open_in_memory().map_err(db_err)?;
+            init_schema(&conn, &self.state.read().id)?;
+            return Ok(PooledConnection { conn: Some(conn), pool: None });
+        };
+        let generation = p.read_pool.generation.load(Ordering::SeqCst);
+        let idle = p.read_pool.idle.lock().pop();
+        let conn = match idle {
+            Some(conn) => conn,
+            None => {
+                let conn = open_connection(&self.db_path, self.db_key.read().as_ref())?;
+                configure_connection(&conn, &p.config.read())?;
+                conn
+            }
+        };
+        Ok(PooledConnection { conn: Some(conn), pool: Some((&p.read_pool, generation)) })
+    }
+
+    fn report_diagnostic(&self, event: DiagnosticEvent) {
+        engine_log!(LogLevel::Warn, "watchdog", "{:?}", event);
+        if let Some(handler) = &*self.diagnostics_handler.read() { handler.on_diagnostic(event); }
+    }
+
+    fn snapshot(&self, mut account: UserAccount) -> UserAccount {
+        self.bucket_order.read().apply(&mut account.buckets);
+        account
+    }
+
+    fn in_quiet_hours(&self) -> bool {
+        let Some(quiet) = *self.quiet_hours.read() else { return false; };
+        quiet.contains(local_minute(self.now_secs()))
+    }
+
+    fn dispatch_event(&self, event: TelcoEvent) {
+        if !event.is_critical() && self.in_quiet_hours() {
+            let mut buffer = self.event_buffer.lock();
+            buffer.push(event);
+            let snapshot = buffer.clone();
+            drop(buffer);
+            self.persist_event_queue(snapshot);
+            return;
+        }
+        self.flush_event_digest();
+        if let Some(h) = &*self.event_handler.read() {
+            self.delay_callback();
+            h.on_event(event);
+        }
+    }
+
+    // Validates and bulk-inserts in one transaction, skipping rows already present (same timestamp, amount, category).
+    fn import_usage_rows(&self, rows: Vec<ImportedUsage>) -> Result<ImportReport, TelcoError> {
+        let now = self.now_secs();
+        let total = rows.len() as u64;
+        let valid: Vec<(u64, u64, QuotaType)> = rows.into_iter()
+            .filter(|r| r.amount > 0 && r.timestamp <= now)
+            .filter_map(|r| QuotaType::from_name(&r.category).map(|c| (r.timestamp, r.amount, c)))
+            .collect();
+        let rejected = total - valid.len() as u64;
+        if let Some(backend) = &*self.backend.read() {
+            // The backend has no lookup to spot duplicates with, so every valid row is appended.
+            let account_id = self.state.read().id.clone();
+            for (timestamp, amount, category) in &valid {
+                let record = UsageRecord { timestamp: *timestamp, amount: *amount, category: format!("{:?}", category), source: UsageSource::Import, confidence: None };
+                backend.append_usage(account_id.clone(), record)?;
+            }
+            if !valid.is_empty() { self.invalidate_insights(); }
+            return Ok(ImportReport { imported: valid.len() as u64, duplicates: 0, rejected });
+        }
+        #[cfg(feature = "sqlite")]
+        {
+            let mut conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let tx = conn.transaction().map_err(db_err)?;
+            let mut imported = 0;
+            {
+                let mut stmt = tx.prepare(
+                    "INSERT INTO usage_history (timestamp, amount, category, account_id, day, source)
+                     SELECT ?1, ?2, ?3, ?4, ?5, 'Import'
+                     WHERE NOT EXISTS (SELECT 1 FROM usage_history WHERE account_id = ?4 AND timestamp = ?1 AND amount = ?2 AND category = ?3)"
+                ).map_err(db_err)?;
+                for (timestamp, amount, category) in &valid {
+                    imported += stmt.execute(params![timestamp, amount, format!("{:?}", category), account_id, timestamp / 86400])
+                        .map_err(db_err)? as u64;
+                }
+            }
+            tx.commit().map_err(db_err)?;
+            if imported > 0 { self.invalidate_insights(); }
+            Ok(ImportReport { imported, duplicates: valid.len() as u64 - imported, rejected })
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            Ok(ImportReport { imported: 0, duplicates: 0, rejected: rejected + valid.len() as u64 })
+        }
+    }
+
+    fn open_sim_request(&self, kind: SimRequestKind) -> Result<SimRequest, TelcoError> {
+        let mut requests = self.sim_requests.lock();
+        if requests.iter().any(|r| matches!(r.state, SimRequestState::Pending | SimRequestState::Verification)) {
+            return Err(TelcoError::InvalidCommand("Another SIM request is already in progress".to_string()));
+        }
+        let request = SimRequest { id: new_id(), kind, state: SimRequestState::Pending, created_at: self.now_secs(), verification_code: None };
+        requests.push(request.clone());
+        drop(requests);
+        self.dispatch_event(TelcoEvent::SimRequestUpdated { request: request.clone() });
+        Ok(request)
+    }
+
+    fn transition_sim_request(&self, request_id: &str, step: impl FnOnce(&mut SimRequest) -> Result<(), TelcoError>) -> Result<SimRequest, TelcoError> {
+        let mut requests = self.sim_requests.lock();
+        let request = requests.iter_mut().find(|r| r.id == request_id)
+            .ok_or_else(|| TelcoError::InvalidCommand(format!("Unknown SIM request '{}'", request_id)))?;
+        step(request)?;
+        let request = request.clone();
+        drop(requests);
+        self.dispatch_event(TelcoEvent::SimRequestUpdated { request: request.clone() });
+        Ok(request)
+    }
+
+    fn check_data_apn(&self) -> Result<(), TelcoError> {
+        let apns = self.apns.read();
+        match apns.iter().find(|a| a.kind == ApnKind::Default && a.selected) {
+            Some(a) if a.apn.eq_ignore_ascii_case(ApnKind::Default.expected_apn()) => Ok(()),
+            Some(a) => Err(TelcoError::NoDataConnection(format!("APN '{}' rejected by network", a.apn))),
+            None => Err(TelcoError::NoDataConnection("No data APN selected".to_string())),
+        }
+    }
+
+    // Real time, not simulated: the delay is for the person watching the UI, whatever the clock scale.
+    fn simulate_response_delay(&self, _api: &str) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let Some(delay) = self.response_delays.read().get(_api).copied() else { return; };
+            let jitter = if delay.jitter_ms > 0 { random_u64() % (delay.jitter_ms as u64 + 1) } else { 0 };
+            thread::sleep(std::time::Duration::from_millis(delay.base_ms as u64 + jitter));
+        }
+    }
+
+    fn delay_callback(&self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let ms = self.chaos.read().callback_delay_ms;
+            if ms > 0 { thread::sleep(std::time::Duration::from_millis(ms as u64)); }
+        }
+    }
+
+    fn add_bucket(&self, bucket: QuotaBucket) -> Result<(), TelcoError> {
+        self.add_paid_bucket(bucket, 0)
+    }
+
+    // Adds a plan or topping resolved by `resolve_purchase`, once the lifecycle hooks allow it.
+    fn buy_bucket(&self, bucket: QuotaBucket) -> Result<(), TelcoError> {
+        let price_cents = self.operator.read().price_of(&bucket);
+        self.approve_purchase(&bucket, price_cents)?;
+        self.add_paid_bucket(bucket, price_cents)
+    }
+
+    fn approve_purchase(&self, bucket: &QuotaBucket, price_cents: u64) -> Result<(), TelcoError> {
+        let Some(hooks) = &*self.lifecycle_hooks.read() else { return Ok(()); };
+        match hooks.before_purchase(PurchaseRequest { bucket: bucket.clone(), price_cents }) {
+            PurchaseDecision::Allow => Ok(()),
+            PurchaseDecision::Deny { reason } => {
+                engine_log!(LogLevel::Info, "purchase", "host declined {}: {}", bucket.name, reason);
+                Err(TelcoError::PurchaseDeclined(reason))
+            }
+        }
+    }
+
+    fn run_after_usage_hook(&self, units: u64, category: QuotaType, account: &UserAccount) {
+        if let Some(hooks) = &*self.lifecycle_hooks.read() { hooks.after_usage(units, category, account.clone()); }
+    }
+
+    // Charges the wallet and adds the bucket under one lock, so two purchases can't both spend the same money.
+    fn add_paid_bucket(&self, bucket: QuotaBucket, price_cents: u64) -> Result<(), TelcoError> {
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Err(TelcoError::Locked); }
+        if lock.wallet_cents < price_cents {
+            return Err(TelcoError::InsufficientFunds { required_cents: price_cents, available_cents: lock.wallet_cents });
+        }
+        let mut caps = self.caps.lock();
+        if let Some(breach) = caps.check_spend(price_cents, self.now_secs()) {
+            drop(caps);
+            drop(lock);
+            return Err(self.cap_exceeded(breach));
+        }
+        caps.spent_cents += price_cents;
+        drop(caps);
+        lock.wallet_cents -= price_cents;
+        lock.buckets.push(bucket.clone());
+        lock.refresh_balances();
+        let lifted = self.lift_restrictions(&mut lock);
+        let account = lock.clone();
+        drop(lock);
+        self.notify_and_persist(account, None);
+        self.dispatch_event(TelcoEvent::BucketAdded { bucket });
+        for event in lifted { self.dispatch_event(event); }
+        Ok(())
+    }
+
+    // Any new quota ends grace and throttling and restores the network's normal latency.
+    fn lift_restrictions(&self, account: &mut UserAccount) -> Vec<TelcoEvent> {
+        let mut events = Vec::new();
+        if account.grace.take().is_some() { events.push(TelcoEvent::GraceEnded); }
+        if std::mem::take(&mut account.throttled) { events.push(TelcoEvent::ThrottleEnded); }
+        if !events.is_empty() {
+            account.current_latency_ms = self.sample_latency();
+        }
+        events
+    }
+
+    // Charges the buckets first; whatever they can't cover is carried by the grace policy, then the throttle, or rejected.
+    // Deducts from `account` in place. A refused usage leaves it as it was.
+    fn consume_with_grace(&self, account: &mut UserAccount, bytes: u64, category: QuotaType) -> Result<Option<TelcoEvent>, TelcoError> {
+        let (shortfall, undo) = account.drain(bytes, category, &self.consumption_policy.read(), self.roaming_rate(account), self.now_secs())?;
+        let was_throttled = undo.throttled;
+        let result = match self.find_family_pool(&account.id).filter(|_| shortfall > 0) {
+            None => self.cover_shortfall(account, was_throttled, shortfall, category),
+            Some(pool) => {
+                // The family pool comes before grace. The draw is only kept if the usage as a whole goes through.
+                let mut pool = pool.lock();
+                let pooled = pool.available_to(&account.id, category, self.now_secs()).min(shortfall);
+                let result = self.cover_shortfall(account, was_throttled, shortfall - pooled, category);
+                if pooled > 0 && result.is_ok() {
+                    pool.draw(&account.id, pooled);
+                    self.persist_family_pool(&pool, false);
+                }
+                result
+            }
+        };
+        if result.is_err() { account.undo(undo); }
+        result
+    }
+
+    fn cover_shortfall(&self, next: &mut UserAccount, was_throttled: bool, shortfall: u64, category: QuotaType) -> Result<Option<TelcoEvent>, TelcoError> {
+        if shortfall == 0 {
+            // Unlimited buckets throttle past their FUP threshold and recover with the next cycle.
+            let event = match (was_throttled, next.throttled) {
+                (false, true) => {
+                    let throttle = self.throttle_policy.read();
+                    next.current_latency_ms = throttle.latency_ms;
+                    Some(TelcoEvent::ThrottleStarted { speed_kbps: throttle.speed_kbps })
+                }
+                (true, false) => {
+                    next.current_latency_ms = self.sample_latency();
+                    Some(TelcoEvent::ThrottleEnded)
+                }
+                _ => None,
+            };
+            return Ok(event);
+        }
+        let policy = self.grace_policy.read();
+        let mut grace = next.grace.clone().unwrap_or(GraceState { used_bytes: 0, allowance_bytes: policy.allowance_bytes, started_at: self.now_secs() });
+        if !policy.zero_rated.contains(&category) {
+            if grace.used_bytes + shortfall > grace.allowance_bytes {
+                let throttle = self.throttle_policy.read();
+                if !throttle.enabled { return Err(TelcoError::InsufficientBalance); }
+                let event = (!next.throttled).then_some(TelcoEvent::ThrottleStarted { speed_kbps: throttle.speed_kbps });
+                next.throttled = true;
+                next.current_latency_ms = throttle.latency_ms;
+                return Ok(event);
+            }
+            grace.used_bytes += shortfall;
+        }
+        let event = if next.grace.is_none() {
+            Some(TelcoEvent::GraceStarted { allowance_bytes: grace.allowance_bytes })
+        } else if grace.used_bytes == grace.allowance_bytes && shortfall > 0 && !policy.zero_rated.contains(&category) {
+            Some(TelcoEvent::GraceExhausted)
         } else {
-            Err(TelcoError::InvalidCommand("Try 'YouTube 2GB'".to_string()))
+            None
+        };
+        next.current_latency_ms = policy.throttled_latency_ms;
+        next.grace = Some(grace);
+        Ok(event)
+    }
+
+    fn family_pools<'a>(&self, registry: &'a mut Vec<(String, Vec<SharedFamilyPool>)>) -> &'a mut Vec<SharedFamilyPool> {
+        let idx = match registry.iter().position(|(path, _)| *path == self.db_path) {
+            Some(idx) => idx,
+            None => {
+                #[cfg(feature = "sqlite")]
+                let loaded = match &self.persistence {
+                    Some(_) => open_connection(&self.db_path, self.db_key.read().as_ref()).map(|c| load_family_pools(&c)).unwrap_or_default(),
+                    None => Vec::new(),
+                };
+                #[cfg(not(feature = "sqlite"))]
+                let loaded = Vec::new();
+                registry.push((self.db_path.clone(), loaded.into_iter().map(|p| Arc::new(Mutex::new(p))).collect()));
+                registry.len() - 1
+            }
+        };
+        &mut registry[idx].1
+    }
+
+    fn find_family_pool(&self, account_id: &str) -> Option<SharedFamilyPool> {
+        let mut registry = FAMILY_POOLS.lock();
+        self.family_pools(&mut registry).iter().find(|p| p.lock().members.iter().any(|m| m.account_id == account_id)).cloned()
+    }
+
+    fn owned_family_pool(&self) -> Result<SharedFamilyPool, TelcoError> {
+        let account = self.state.read().clone();
+        if account.biometric_locked { return Err(TelcoError::Locked); }
+        let pool = self.find_family_pool(&account.id).ok_or_else(|| TelcoError::InvalidCommand("Not in a family pool".to_string()))?;
+        if pool.lock().owner_id != account.id { return Err(TelcoError::PermissionDenied("Only the pool owner can manage it".to_string())); }
+        Ok(pool)
+    }
+
+    fn update_owned_family_pool(&self, update: impl FnOnce(&mut FamilyPool) -> Result<(), TelcoError>) -> Result<FamilyPoolInfo, TelcoError> {
+        let pool = self.owned_family_pool()?;
+        let mut pool = pool.lock();
+        update(&mut pool)?;
+        pool.version += 1;
+        self.persist_family_pool(&pool, false);
+        Ok(pool.info(&pool.owner_id))
+    }
+
+    fn persist_family_pool(&self, _pool: &FamilyPool, _dissolved: bool) {
+        #[cfg(feature = "sqlite")]
+        self.persist(_pool.persistence_msg(_dissolved));
+    }
+
+    fn leave_family_pools(&self, account_id: &str) {
+        let mut registry = FAMILY_POOLS.lock();
+        let changed = leave_family_pools(self.family_pools(&mut registry), account_id);
+        drop(registry);
+        for (pool, dissolved) in changed { self.persist_family_pool(&pool, dissolved); }
+    }
+
+    fn purge_trash(&self) {
+        let before = self.now_secs().saturating_sub(self.trash_retention_secs.load(Ordering::Relaxed));
+        self.trash.lock().retain(|t| t.deleted_at >= before);
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            self.persist(PersistenceMsg::PurgeTrash { account_id, before });
+        }
+    }
+
+    fn unlock_achievement(&self, achievement: Achievement) -> bool {
+        let mut achievements = self.achievements.lock();
+        if achievements.iter().any(|a| a.achievement == achievement) { return false; }
+        let record = AchievementRecord { achievement, unlocked_at: self.now_secs() };
+        achievements.push(record.clone());
+        drop(achievements);
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            self.persist(PersistenceMsg::Achievement { account_id, record });
+        }
+        #[cfg(not(feature = "sqlite"))]
+        let _ = record;
+        self.dispatch_event(TelcoEvent::AchievementUnlocked { achievement });
+        self.record_telemetry("achievement.unlocked");
+        true
+    }
+
+    fn record_telemetry(&self, feature: &'static str) {
+        let mut telemetry = self.telemetry.lock();
+        if telemetry.enabled { *telemetry.counters.entry(feature).or_insert(0) += 1; }
+    }
+
+    fn track_budget(&self, bytes: u64, now: u64) {
+        let mut tracker = self.budget.lock();
+        let Some(budget_bytes) = tracker.budget_bytes else { return; };
+        let mut events = Vec::new();
+        let day = now / 86400;
+        let day_completed = day != tracker.day;
+        if day_completed {
+            events.push(TelcoEvent::BudgetDayCompleted { day: tracker.day, used_bytes: tracker.used_bytes, budget_bytes, under_budget: tracker.used_bytes <= budget_bytes });
+            *tracker = BudgetTracker { budget_bytes: Some(budget_bytes), day, ..BudgetTracker::default() };
+        }
+        tracker.used_bytes += bytes;
+        if !tracker.exceeded && tracker.used_bytes > budget_bytes {
+            tracker.exceeded = true;
+            events.push(TelcoEvent::BudgetExceeded { used_bytes: tracker.used_bytes, budget_bytes });
+        } else if !tracker.warned && !tracker.exceeded && tracker.projected(now) > budget_bytes {
+            tracker.warned = true;
+            events.push(TelcoEvent::BudgetPaceWarning { used_bytes: tracker.used_bytes, projected_bytes: tracker.projected(now), budget_bytes });
+        }
+        drop(tracker);
+        for event in events { self.dispatch_event(event); }
+        if day_completed { let _ = self.evaluate_achievements(); }
+    }
+
+    // Whether history is old enough for `InsightConfig::min_history_days`.
+    fn has_forecast_history(&self) -> bool {
+        let min_history_days = self.insight_config.read().min_history_days as u64;
+        if min_history_days == 0 { return true; }
+        #[cfg(feature = "sqlite")]
+        {
+            let Ok(conn) = self.open_db() else { return false; };
+            let account_id = self.state.read().id.clone();
+            let oldest: Option<u64> = conn.query_row("SELECT MIN(timestamp) FROM usage_history WHERE account_id = ?1 AND category NOT IN ('Voice', 'Sms')",
+                params![account_id], |row| row.get(0)).unwrap_or(None);
+            oldest.is_some_and(|oldest| self.now_secs().saturating_sub(oldest) >= min_history_days * 86400)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            false
+        }
+    }
+
+    // Swaps in everything `assemble` took from storage, after a restore.
+    #[cfg(feature = "sqlite")]
+    fn reload_persisted(&self, persisted: PersistedState) {
+        *self.event_buffer.lock() = persisted.events;
+        *self.ad_rewards.lock() = persisted.ad_rewards;
+        *self.achievements.lock() = persisted.achievements;
+        *self.experiment_assignments.lock() = persisted.experiment_assignments;
+        *self.insight_config.write() = persisted.insight_config.unwrap_or_default();
+        *self.category_rules.write() = if persisted.category_rules.is_empty() { default_category_rules() } else { persisted.category_rules };
+        *self.trash.lock() = persisted.trashed_buckets;
+        *self.pending_orders.lock() = persisted.pending_orders;
+        *self.snapshots.lock() = persisted.snapshots;
+        *self.redeemed_tokens.lock() = persisted.redeemed_tokens;
+        *self.subscriptions.lock() = persisted.subscriptions;
+        *self.preferences.write() = persisted.preferences;
+    }
+
+    fn prune_history_if_due(&self) {
+        if self.now_secs() < self.last_prune_at.load(Ordering::Relaxed) + PRUNE_INTERVAL_SECS { return; }
+        if let Err(e) = self.prune_history() { engine_log!(LogLevel::Warn, "retention", "pruning failed: {}", e); }
+    }
+
+    fn usage_by_category_since(&self, _since: u64) -> Result<Vec<CategoryBytes>, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let mut stmt = conn.prepare("SELECT category, SUM(amount) FROM usage_history WHERE account_id = ?1 AND timestamp >= ?2 GROUP BY category").map_err(db_err)?;
+            let rows = stmt.query_map(params![account_id, _since], |row| Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?)))
+                .map_err(db_err)?
+                .filter_map(|r| r.ok())
+                .filter_map(|(name, bytes)| QuotaType::from_name(&name).map(|category| CategoryBytes { category, bytes }))
+                .collect();
+            Ok(rows)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            Ok(Vec::new())
+        }
+    }
+
+    // Builds the error for a cap breach, announcing it the first time this cycle. Call without the state lock held.
+    fn cap_exceeded(&self, (cap, limit, used): (CapKind, u64, u64)) -> TelcoError {
+        if self.caps.lock().first_reach(cap) {
+            engine_log!(LogLevel::Info, "caps", "{:?} cap reached ({} of {})", cap, used, limit);
+            self.dispatch_event(TelcoEvent::CapReached { cap, limit, used });
+        }
+        TelcoError::CapExceeded { cap, limit, used }
+    }
+
+    fn usage_on_day(&self, _day: u64) -> Result<u64, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            conn.query_row("SELECT COALESCE(SUM(amount), 0) FROM usage_history WHERE account_id = ?1 AND day = ?2 AND category NOT IN ('Voice', 'Sms')", params![account_id, _day], |row| row.get(0))
+                .map_err(db_err)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            Ok(0)
+        }
+    }
+
+    // Days since the last day over budget (or since history began), not counting today.
+    fn budget_streak(&self, _budget_bytes: u64) -> Result<u32, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let today = self.now_secs() / 86400;
+            let first_day: Option<u64> = conn.query_row("SELECT MIN(day) FROM usage_history WHERE account_id = ?1", params![account_id], |row| row.get(0))
+                .map_err(db_err)?;
+            let Some(first_day) = first_day.filter(|d| *d < today) else { return Ok(0); };
+            let last_over: Option<u64> = conn.query_row(
+                "SELECT MAX(day) FROM (SELECT day, SUM(amount) AS total FROM usage_history WHERE account_id = ?1 AND day < ?2 AND category NOT IN ('Voice', 'Sms') GROUP BY day HAVING total > ?3)",
+                params![account_id, today, _budget_bytes], |row| row.get(0)
+            ).map_err(db_err)?;
+            Ok((today - last_over.map_or(first_day, |d| d + 1)) as u32)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            Ok(0)
         }
     }
-    pub fn get_historical_usage(&self, limit: u32) -> Result<Vec<UsageRecord>, TelcoError> {
+
+    // Seven consecutive complete days, each with usage at or below the budget.
+    fn week_under_budget(&self, _daily_budget: u64) -> Result<bool, TelcoError> {
         #[cfg(feature = "sqlite")]
         {
-            let conn = Connection::open(&self.db_path).map_err(|e| TelcoError::DatabaseError(e.to_string()))?;
-            let mut stmt = conn.prepare("SELECT timestamp, amount, category FROM usage_history ORDER BY timestamp DESC LIMIT ?1")
-                .map_err(|e| TelcoError::DatabaseError(e.to_string()))?;
-            
-            let records = stmt.query_map(params![limit], |row| {
-                Ok(UsageRecord {
-                    timestamp: row.get(0)?,
-                    amount: row.get(1)?,
-                    category: row.get(2)?,
-                })
-            }).map_err(|e| TelcoError::DatabaseError(e.to_string()))?
+            let conn = self.open_db()?;
+            let account_id = self.state.read().id.clone();
+            let today = self.now_secs() / 86400;
+            let first_day: Option<u64> = conn.query_row("SELECT MIN(day) FROM usage_history WHERE account_id = ?1", params![account_id], |row| row.get(0))
+                .map_err(db_err)?;
+            if first_day.is_none_or(|d| d + 7 > today) { return Ok(false); }
+            let mut stmt = conn.prepare("SELECT COUNT(*) FROM (SELECT day, SUM(amount) AS total FROM usage_history WHERE account_id = ?1 AND day >= ?2 AND day < ?3 AND category NOT IN ('Voice', 'Sms') GROUP BY day HAVING total > ?4)")
+                .map_err(db_err)?;
+            let over_budget: u64 = stmt.query_row(params![account_id, today - 7, today, _daily_budget], |row| row.get(0))
+                .map_err(db_err)?;
+            Ok(over_budget == 0)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            Ok(false)
+        }
+    }
+
+    fn happy_hour_bytes(&self, _config: &AchievementConfig) -> Result<u64, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = self.open_db()?;
+            let op = if _config.happy_hour_start_minute <= _config.happy_hour_end_minute { "AND" } else { "OR" };
+            let sql = format!(
+                "SELECT COALESCE(SUM(amount), 0) FROM (SELECT amount, CAST(strftime('%H', timestamp, 'unixepoch', 'localtime') AS INTEGER) * 60 + CAST(strftime('%M', timestamp, 'unixepoch', 'localtime') AS INTEGER) AS minute FROM usage_history WHERE account_id = ?3 AND category NOT IN ('Voice', 'Sms')) WHERE minute >= ?1 {} minute < ?2",
+                op
+            );
+            let account_id = self.state.read().id.clone();
+            conn.query_row(&sql, params![_config.happy_hour_start_minute, _config.happy_hour_end_minute, account_id], |row| row.get(0))
+                .map_err(db_err)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        {
+            Ok(0)
+        }
+    }
+
+    fn retire_buckets(&self, retiring: impl Fn(&QuotaBucket) -> bool) -> Vec<QuotaBucket> {
+        let policy = *self.rollover_policy.read();
+        let mut lock = self.state.write();
+        if lock.biometric_locked { return Vec::new(); }
+        let now = self.now_secs();
+        let indices: Vec<usize> = (0..lock.buckets.len())
+            .filter(|&i| lock.buckets[i].remaining_bytes > 0 && retiring(&lock.buckets[i]))
+            .collect();
+        if indices.is_empty() { return Vec::new(); }
+        let (rolled_over, forfeited_bytes) = lock.roll_over(&indices, &policy, now);
+        let account = lock.clone();
+        drop(lock);
+        self.notify_and_persist(account, None);
+        engine_log!(LogLevel::Info, "rollover", "retired {} buckets, carried {} and forfeited {} bytes",
+            indices.len(), rolled_over.iter().map(|b| b.remaining_bytes).sum::<u64>(), forfeited_bytes);
+        if let Some(hooks) = &*self.lifecycle_hooks.read() { hooks.on_cycle_rollover(rolled_over.clone(), forfeited_bytes); }
+        self.dispatch_event(TelcoEvent::QuotaRolledOver { rolled_over: rolled_over.clone(), forfeited_bytes });
+        rolled_over
+    }
+
+    fn write_preference(&self, key: String, value: Option<PreferenceValue>) {
+        let mut preferences = self.preferences.write();
+        let previous = match &value {
+            Some(v) => preferences.insert(key.clone(), v.clone()),
+            None => preferences.remove(&key),
+        };
+        if previous == value { return; }
+        // Queued under the lock so concurrent writes to one key reach the database in order.
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            self.persist(PersistenceMsg::Preference { account_id, key: key.clone(), value: value.clone() });
+        }
+        drop(preferences);
+        self.dispatch_event(TelcoEvent::PreferenceChanged { key, value });
+    }
+
+    fn persist_subscriptions(&self, _subscriptions: Vec<Subscription>) {
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            self.persist(PersistenceMsg::Subscriptions { account_id, subscriptions: _subscriptions });
+        }
+    }
+
+    fn persist_pending_orders(&self, _orders: Vec<ScheduledOrder>) {
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            self.persist(PersistenceMsg::PendingOrders { account_id, orders: _orders });
+        }
+    }
+
+    // Catalog plans take precedence over topping syntax, so an operator can offer e.g. "Night 10GB".
+    fn resolve_purchase(&self, purchase: &str) -> Result<QuotaBucket, TelcoError> {
+        let plan = self.operator.read().plans.iter().find(|p| p.name.eq_ignore_ascii_case(purchase.trim())).map(|p| p.to_bucket(self.now_secs()));
+        match plan {
+            Some(bucket) => Ok(bucket),
+            None => parse_topping(purchase, self.now_secs()).map_err(|_| TelcoError::InvalidCommand(format!("Unknown plan or topping '{}'", purchase.trim()))),
+        }
+    }
+
+    fn persist_category_rules(&self, _rules: Vec<CategoryRule>) {
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            self.persist(PersistenceMsg::CategoryRules { account_id, rules: _rules });
+        }
+    }
+
+    fn audit_command(&self, _source: CommandSource, _command: &str, _allowed: bool, _error: Option<String>) {
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            let entry = CommandAuditEntry { timestamp: self.now_secs(), source: _source, command: _command.to_string(), allowed: _allowed, error: _error };
+            self.persist(PersistenceMsg::CommandAudit { account_id, entry });
+        }
+    }
+
+    fn persist_event_queue(&self, _events: Vec<TelcoEvent>) {
+        #[cfg(feature = "sqlite")]
+        {
+            let account_id = self.state.read().id.clone();
+            self.persist(PersistenceMsg::EventQueue { account_id, events: _events });
+        }
+    }
+}
+
+/// Lists what changed from `before` to `after`, matching buckets by id. Balances are reported per bucket; the
+/// account-wide totals are left out since they follow from those.
+#[uniffi::export]
+pub fn diff_accounts(before: UserAccount, after: UserAccount) -> Vec<AccountChange> {
+    account_changes(&before, &after)
+}
+
+fn account_changes(before: &UserAccount, after: &UserAccount) -> Vec<AccountChange> {
+    let mut changes = Vec::new();
+    if before.is_active != after.is_active { changes.push(AccountChange::ActiveChanged { active: after.is_active }); }
+    if before.biometric_locked != after.biometric_locked { changes.push(AccountChange::LockChanged { locked: after.biometric_locked }); }
+    if before.registration != after.registration { changes.push(AccountChange::RegistrationChanged { registration: after.registration }); }
+    if before.throttled != after.throttled { changes.push(AccountChange::ThrottleChanged { throttled: after.throttled }); }
+    if before.grace != after.grace { changes.push(AccountChange::GraceChanged { grace: after.grace.clone() }); }
+    if before.wallet_cents != after.wallet_cents { changes.push(AccountChange::WalletChanged { wallet_cents: after.wallet_cents }); }
+    for old in &before.buckets {
+        let Some(new) = after.buckets.iter().find(|b| b.id == old.id) else {
+            changes.push(AccountChange::BucketRemoved { bucket_id: old.id.clone(), name: old.name.clone() });
+            continue;
+        };
+        // Unlimited buckets keep counting past their threshold, where the headroom is already 0.
+        let (old_used, new_used) = match (old.fair_usage, new.fair_usage) {
+            (Some(o), Some(n)) if o.period_started_at == n.period_started_at => (o.used_bytes, n.used_bytes),
+            _ => (0, 0),
+        };
+        let bucket_id = new.id.clone();
+        let remaining_bytes = new.remaining_bytes;
+        if new_used > old_used {
+            changes.push(AccountChange::BytesConsumed { bucket_id, bytes: new_used - old_used, remaining_bytes });
+        } else if new.remaining_bytes < old.remaining_bytes {
+            changes.push(AccountChange::BytesConsumed { bucket_id, bytes: old.remaining_bytes - new.remaining_bytes, remaining_bytes });
+        } else if new.remaining_bytes > old.remaining_bytes {
+            changes.push(AccountChange::BytesAdded { bucket_id, bytes: new.remaining_bytes - old.remaining_bytes, remaining_bytes });
+        }
+        let cycle = |b: &QuotaBucket| b.fair_usage.map(|f| (f.threshold_bytes, f.period_started_at));
+        if old.name != new.name || old.category != new.category || old.expiry != new.expiry || old.source != new.source
+            || old.active_window != new.active_window || cycle(old) != cycle(new) {
+            changes.push(AccountChange::BucketUpdated { bucket: new.clone() });
+        }
+    }
+    for new in after.buckets.iter().filter(|b| !before.buckets.iter().any(|o| o.id == b.id)) {
+        changes.push(AccountChange::BucketAdded { bucket: new.clone() });
+    }
+    changes
+}
+
+/// Ranks every account stored in `db_path`: heaviest users by bytes used since `since`, top savers by remaining balance.
+#[uniffi::export]
+pub fn fleet_leaderboard(db_path: String, metric: LeaderboardMetric, since: u64, limit: u32) -> Result<Vec<LeaderboardEntry>, TelcoError> {
+    #[cfg(feature = "sqlite")]
+    {
+        let conn = open_connection(&db_path, None)?;
+        let sql = match metric {
+            LeaderboardMetric::HeaviestUsers => "SELECT account_id, SUM(amount) AS total FROM usage_history WHERE account_id IS NOT NULL AND timestamp >= ?1 AND category NOT IN ('Voice', 'Sms') GROUP BY account_id ORDER BY total DESC LIMIT ?2",
+            LeaderboardMetric::TopSavers => "SELECT a.id, COALESCE(SUM(b.remaining_bytes), 0) AS total FROM accounts a LEFT JOIN buckets b ON b.account_id = a.id AND b.expiry > ?1 AND b.deleted_at IS NULL AND b.category NOT IN ('Voice', 'Sms') GROUP BY a.id ORDER BY total DESC LIMIT ?2",
+        };
+        let since = if metric == LeaderboardMetric::TopSavers { now_secs() } else { since };
+        let mut stmt = conn.prepare(sql).map_err(db_err)?;
+        let entries = stmt.query_map(params![since, limit], |row| Ok(LeaderboardEntry { account_id: row.get(0)?, value: row.get(1)? }))
+            .map_err(db_err)?
             .filter_map(|r| r.ok())
             .collect();
-            
-            Ok(records)
+        Ok(entries)
+    }
+    #[cfg(not(feature = "sqlite"))]
+    {
+        let _ = (db_path, metric, since, limit);
+        Ok(vec![])
+    }
+}
+
+/// Runs a fleet of generated subscribers against one database, as a scenario lab for operator dashboards.
+/// An empty `db_path` keeps every member in memory.
+#[derive(uniffi::Object)]
+pub struct FleetOrchestrator {
+    db_path: String,
+    members: RwLock<Vec<FleetSim>>,
+    ticks: AtomicU64,
+    // Bumped by start/stop so a superseded traffic thread notices and exits.
+    generation: Arc<AtomicU64>,
+}
+
+#[uniffi::export]
+impl FleetOrchestrator {
+    #[uniffi::constructor]
+    pub fn new(db_path: String) -> Arc<Self> {
+        Arc::new(Self { db_path, members: RwLock::new(Vec::new()), ticks: AtomicU64::new(0), generation: Arc::new(AtomicU64::new(0)) })
+    }
+
+    /// Adds `count` members, all of `persona` or cycling through every persona when `None`. Members are set up
+    /// with their persona's operator preset; roamers start out roaming.
+    pub fn spawn_members(&self, count: u32, persona: Option<Persona>) -> Result<Vec<FleetMember>, TelcoError> {
+        let mut members = self.members.write();
+        let mut spawned = Vec::new();
+        for i in 0..count as usize {
+            let persona = persona.unwrap_or(Persona::ALL[i % Persona::ALL.len()]);
+            let account_id = format!("fleet-{}-{}", persona.slug(), members.len() + 1);
+            let sim = if self.db_path.is_empty() { TelcoSimulator::ephemeral(account_id.clone()) } else { TelcoSimulator::new(account_id.clone(), self.db_path.clone())? };
+            sim.install_preset(persona.preset())?;
+            if persona == Persona::Roamer { sim.set_registration_state(RegistrationState::Roaming)?; }
+            let member = FleetMember { account_id, persona };
+            spawned.push(member.clone());
+            members.push(FleetSim { member, sim, used_bytes: AtomicU64::new(0), rejected: AtomicU64::new(0) });
+        }
+        engine_log!(LogLevel::Info, "fleet", "spawned {} members", spawned.len());
+        Ok(spawned)
+    }
+
+    pub fn list_members(&self) -> Vec<FleetMember> {
+        self.members.read().iter().map(|m| m.member.clone()).collect()
+    }
+
+    /// The member's simulator, for driving one subscriber by hand or attaching handlers.
+    pub fn simulator(&self, account_id: String) -> Option<Arc<TelcoSimulator>> {
+        self.members.read().iter().find(|m| m.member.account_id == account_id).map(|m| m.sim.clone())
+    }
+
+    /// Generates one tick of traffic for every member. Returns the number of sessions recorded.
+    pub fn run_traffic_tick(&self) -> u32 {
+        self.ticks.fetch_add(1, Ordering::Relaxed);
+        let mut recorded = 0;
+        for m in self.members.read().iter() {
+            let Some((bytes, category)) = m.member.persona.next_session() else { continue; };
+            match m.sim.simulate_usage(bytes, category) {
+                Ok(()) => {
+                    m.used_bytes.fetch_add(bytes, Ordering::Relaxed);
+                    recorded += 1;
+                }
+                Err(_) => { m.rejected.fetch_add(1, Ordering::Relaxed); }
+            }
+        }
+        recorded
+    }
+
+    /// Runs a traffic tick every `interval_ms` of simulated time until `stop_traffic`. Restarting replaces the running
+    /// generator.
+    pub fn start_traffic(self: Arc<Self>, interval_ms: u64) {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+            let current = self.generation.clone();
+            let fleet = Arc::downgrade(&self);
+            drop(self);
+            thread::spawn(move || loop {
+                sim_sleep(interval_ms);
+                if current.load(Ordering::SeqCst) != generation { return; }
+                let Some(fleet) = fleet.upgrade() else { return; };
+                fleet.run_traffic_tick();
+            });
+        }
+    }
+
+    pub fn stop_traffic(&self) {
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn fleet_stats(&self) -> FleetStats {
+        let members = self.members.read();
+        let mut stats = FleetStats {
+            members: members.len() as u32,
+            ticks: self.ticks.load(Ordering::Relaxed),
+            used_bytes: 0,
+            remaining_bytes: 0,
+            exhausted_members: 0,
+            throttled_members: 0,
+            rejected_sessions: 0,
+            by_persona: Vec::new(),
+        };
+        for m in members.iter() {
+            let (remaining, throttled) = { let account = m.sim.state.read(); (account.data_balance_bytes, account.throttled) };
+            let (used, rejected) = (m.used_bytes.load(Ordering::Relaxed), m.rejected.load(Ordering::Relaxed));
+            stats.used_bytes += used;
+            stats.remaining_bytes += remaining;
+            stats.rejected_sessions += rejected;
+            if remaining == 0 { stats.exhausted_members += 1; }
+            if throttled { stats.throttled_members += 1; }
+            let idx = match stats.by_persona.iter().position(|p| p.persona == m.member.persona) {
+                Some(idx) => idx,
+                None => {
+                    stats.by_persona.push(PersonaStats { persona: m.member.persona, members: 0, used_bytes: 0, remaining_bytes: 0, rejected_sessions: 0 });
+                    stats.by_persona.len() - 1
+                }
+            };
+            let entry = &mut stats.by_persona[idx];
+            entry.members += 1;
+            entry.used_bytes += used;
+            entry.remaining_bytes += remaining;
+            entry.rejected_sessions += rejected;
+        }
+        stats
+    }
+}
+
+/// Hosts several accounts in one database. Each account gets one shared `TelcoSimulator`, opened on first use.
+#[derive(uniffi::Object)]
+pub struct TelcoAccountManager {
+    db_path: String,
+    handles: Mutex<HashMap<String, Arc<TelcoSimulator>>>,
+    active: RwLock<Option<String>>,
+}
+
+#[uniffi::export]
+impl TelcoAccountManager {
+    #[uniffi::constructor]
+    pub fn new(db_path: String) -> Arc<Self> {
+        Arc::new(Self { db_path, handles: Mutex::new(HashMap::new()), active: RwLock::new(None) })
+    }
+
+    /// Stored accounts plus any opened this session whose first write is still queued, sorted by id.
+    pub fn list_accounts(&self) -> Result<Vec<String>, TelcoError> {
+        let mut ids: Vec<String> = self.handles.lock().keys().cloned().collect();
+        #[cfg(feature = "sqlite")]
+        for id in load_account_ids(&open_connection(&self.db_path, None)?) {
+            if !ids.contains(&id) { ids.push(id); }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn create_account(&self, id: String) -> Result<Arc<TelcoSimulator>, TelcoError> {
+        if id.trim().is_empty() { return Err(TelcoError::InvalidCommand("Account id must not be empty".to_string())); }
+        let mut handles = self.handles.lock();
+        if handles.contains_key(&id) || self.is_stored(&id)? {
+            return Err(TelcoError::InvalidCommand(format!("Account '{}' already exists", id)));
+        }
+        let sim = TelcoSimulator::new(id.clone(), self.db_path.clone())?;
+        // Writes the accounts row right away so the account is listed before anything happens on it.
+        let account = sim.state.read().clone();
+        sim.notify_and_persist(account, None);
+        handles.insert(id, sim.clone());
+        Ok(sim)
+    }
+
+    /// The simulator for an existing account; every call for the same id returns the same instance.
+    pub fn account(&self, id: String) -> Result<Arc<TelcoSimulator>, TelcoError> {
+        let mut handles = self.handles.lock();
+        if let Some(sim) = handles.get(&id) { return Ok(sim.clone()); }
+        if !self.is_stored(&id)? { return Err(TelcoError::InvalidCommand(format!("Unknown account '{}'", id))); }
+        let sim = TelcoSimulator::new(id.clone(), self.db_path.clone())?;
+        handles.insert(id, sim.clone());
+        Ok(sim)
+    }
+
+    pub fn switch_account(&self, id: String) -> Result<Arc<TelcoSimulator>, TelcoError> {
+        let sim = self.account(id.clone())?;
+        *self.active.write() = Some(id);
+        Ok(sim)
+    }
+
+    pub fn active_account(&self) -> Option<Arc<TelcoSimulator>> {
+        let active = self.active.read();
+        self.handles.lock().get(active.as_ref()?).cloned()
+    }
+
+    /// Removes the account and everything stored for it. Handles to it that the host still holds must not be used
+    /// afterwards, or their writes recreate the account.
+    pub fn delete_account(&self, id: String) -> Result<(), TelcoError> {
+        let mut handles = self.handles.lock();
+        let open = handles.remove(&id);
+        if open.is_none() && !self.is_stored(&id)? { return Err(TelcoError::InvalidCommand(format!("Unknown account '{}'", id))); }
+        let mut active = self.active.write();
+        if active.as_ref() == Some(&id) { *active = None; }
+        // A pool never outlives its owner, nor keeps a member that no longer exists.
+        if let Some(sim) = &open { sim.leave_family_pools(&id); }
+        #[cfg(feature = "sqlite")]
+        {
+            match open {
+                // Queued behind the account's pending writes so none of them lands after the delete.
+                Some(sim) if sim.persistence.is_some() => sim.persist(PersistenceMsg::DeleteAccount { account_id: id.clone() }),
+                _ => {
+                    let mut conn = open_connection(&self.db_path, None)?;
+                    let mut registry = FAMILY_POOLS.lock();
+                    let idx = match registry.iter().position(|(path, _)| *path == self.db_path) {
+                        Some(idx) => idx,
+                        None => {
+                            registry.push((self.db_path.clone(), load_family_pools(&conn).into_iter().map(|p| Arc::new(Mutex::new(p))).collect()));
+                            registry.len() - 1
+                        }
+                    };
+                    let changed = leave_family_pools(&mut registry[idx].1, &id);
+                    drop(registry);
+                    for (pool, dissolved) in changed { apply_persistence_msg(&mut conn, pool.persistence_msg(dissolved))?; }
+                    delete_account_rows(&mut conn, &id)?;
+                }
+            }
+            let _ = std::fs::remove_file(journal_path(&self.db_path, &id));
+        }
+        engine_log!(LogLevel::Info, "accounts", "deleted account {}", id);
+        Ok(())
+    }
+}
+
+impl TelcoAccountManager {
+    fn is_stored(&self, _id: &str) -> Result<bool, TelcoError> {
+        #[cfg(feature = "sqlite")]
+        {
+            let conn = open_connection(&self.db_path, None)?;
+            Ok(load_account_ids(&conn).iter().any(|id| id == _id))
+        }
+        #[cfg(not(feature = "sqlite"))]
+        Ok(false)
+    }
+}
+
+/// Speeds up (or slows down) the process clock, and so every simulator without its own clock, e.g. 1440.0 plays a
+/// 30-day cycle in 30 minutes. Expiries, billing cycles, the forecast window and background ticks all follow the
+/// scaled clock; changing the factor keeps the current simulated time, it only affects how fast it advances from
+/// now on. Wraps the installed `ClockSource` in a `ScaledClock`.
+#[uniffi::export]
+pub fn set_time_scale(factor: f64) -> Result<(), TelcoError> {
+    if !factor.is_finite() || factor <= 0.0 { return Err(TelcoError::InvalidCommand(format!("Time scale must be positive, got {}", factor))); }
+    let mut clock = CLOCK.write();
+    match clock.as_mut() {
+        Some(clock) => clock.rescale(factor),
+        None => *clock = Some(ScaledClock::new(Box::new(SystemClock), factor)),
+    }
+    Ok(())
+}
+
+#[uniffi::export]
+pub fn get_time_scale() -> f64 {
+    CLOCK.read().as_ref().map_or(1.0, |clock| clock.scale)
+}
+
+/// Whether "MB"/"GB" mean powers of 1000 (`Si`) or 1024 (`Iec`, shown as "MiB"/"GiB"). Applies to command
+/// parsing, plan sizes, formatting and insights alike.
+#[derive(Clone, Copy, Debug, uniffi::Enum, PartialEq)]
+pub enum UnitPolicy { Si, Iec }
+
+impl UnitPolicy {
+    fn base(&self) -> u64 {
+        match self { UnitPolicy::Si => 1000, UnitPolicy::Iec => 1024 }
+    }
+
+    fn labels(&self) -> &'static [&'static str] {
+        match self {
+            UnitPolicy::Si => &["B", "KB", "MB", "GB", "TB"],
+            UnitPolicy::Iec => &["B", "KiB", "MiB", "GiB", "TiB"],
+        }
+    }
+}
+
+static UNIT_POLICY: AtomicU8 = AtomicU8::new(UnitPolicy::Iec as u8);
+
+/// Process-wide; defaults to `Iec`. Set it before creating simulators so preset plan sizes follow it.
+#[uniffi::export]
+pub fn set_unit_policy(policy: UnitPolicy) {
+    UNIT_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+#[uniffi::export]
+pub fn get_unit_policy() -> UnitPolicy {
+    if UNIT_POLICY.load(Ordering::Relaxed) == UnitPolicy::Si as u8 { UnitPolicy::Si } else { UnitPolicy::Iec }
+}
+
+fn megabytes(n: u64) -> u64 {
+    n * get_unit_policy().base().pow(2)
+}
+
+fn gigabytes(n: u64) -> u64 {
+    n * get_unit_policy().base().pow(3)
+}
+
+// `amount` MB or GB from chat input, which can ask for more than fits in a u64.
+fn scaled_size(amount: u64, unit: &str) -> Result<u64, TelcoError> {
+    let power = if unit.eq_ignore_ascii_case("gb") { 3 } else { 2 };
+    amount.checked_mul(get_unit_policy().base().pow(power))
+        .ok_or_else(|| TelcoError::InvalidCommand(format!("{} {} is too large", amount, unit.to_uppercase())))
+}
+
+#[uniffi::export]
+pub fn gigabytes_to_bytes(gb: f64) -> u64 {
+    (gb * get_unit_policy().base().pow(3) as f64).round() as u64
+}
+
+#[uniffi::export]
+pub fn bytes_to_gigabytes(bytes: u64) -> f64 {
+    bytes as f64 / get_unit_policy().base().pow(3) as f64
+}
+
+const MAX_SCENARIO_OFFSET_SECS: u64 = 100 * 365 * 86400;
+
+/// Parses a scenario script, one step per line as `at t+<offset> <action>`, where the offset is a number with an
+/// optional s/m/h/d unit and the action is one of:
+///
+/// ```text
+/// consume 200MB Video      buy Social 1GB      buy plan Weekly 5GB
+/// top up 500               expire bucket X     signal -110dBm
+/// tick
+/// ```
+///
+/// Blank lines and lines starting with `#` are skipped. Steps are sorted by offset; equal offsets keep script order.
+#[uniffi::export]
+pub fn load_scenario(script: String) -> Result<Scenario, TelcoError> {
+    let step = Regex::new(r"(?i)^at\s+t\+(\d+)\s*([smhd]?)\s+(.+)$").unwrap();
+    let mut steps = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let line_no = i as u32 + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') { continue; }
+        let invalid = |what: String| TelcoError::InvalidCommand(format!("Line {}: {}", line_no, what));
+        let caps = step.captures(line).ok_or_else(|| invalid(format!("expected 'at t+<offset> <action>', got '{}'", line)))?;
+        let amount: u64 = caps[1].parse().map_err(|_| invalid(format!("bad offset '{}'", &caps[1])))?;
+        let unit = match caps[2].to_lowercase().as_str() { "m" => 60, "h" => 3600, "d" => 86400, _ => 1 };
+        let at_secs = amount.checked_mul(unit).filter(|&secs| secs <= MAX_SCENARIO_OFFSET_SECS)
+            .ok_or_else(|| invalid(format!("offset '{}{}' is more than 100 years", &caps[1], &caps[2])))?;
+        let action = parse_scenario_action(&caps[3]).map_err(|e| match e {
+            TelcoError::InvalidCommand(what) => invalid(what),
+            e => e,
+        })?;
+        steps.push(ScenarioStep { at_secs, line: line_no, action });
+    }
+    steps.sort_by_key(|s| s.at_secs);
+    Ok(Scenario { steps })
+}
+
+fn parse_scenario_action(text: &str) -> Result<ScenarioAction, TelcoError> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let category = |name: &str| QuotaType::from_name(name).ok_or_else(|| TelcoError::InvalidCommand(format!("Unknown category '{}'", name)));
+    match words.as_slice() {
+        [verb, size @ .., name] if verb.eq_ignore_ascii_case("consume") && !size.is_empty() => {
+            Ok(ScenarioAction::Consume { bytes: parse_bytes(size.join(""))?, category: category(name)? })
+        }
+        [verb, plan, name @ ..] if verb.eq_ignore_ascii_case("buy") && plan.eq_ignore_ascii_case("plan") && !name.is_empty() => {
+            Ok(ScenarioAction::BuyPlan { plan: name.join(" ") })
+        }
+        [verb, name, size @ ..] if verb.eq_ignore_ascii_case("buy") && !size.is_empty() => {
+            Ok(ScenarioAction::BuyTopping { category: category(name)?, bytes: parse_bytes(size.join(""))? })
+        }
+        [top, up, cents] if top.eq_ignore_ascii_case("top") && up.eq_ignore_ascii_case("up") => {
+            Ok(ScenarioAction::TopUp { cents: cents.parse().map_err(|_| TelcoError::InvalidCommand(format!("Bad amount '{}'", cents)))? })
+        }
+        [verb, bucket, name @ ..] if verb.eq_ignore_ascii_case("expire") && bucket.eq_ignore_ascii_case("bucket") && !name.is_empty() => {
+            Ok(ScenarioAction::ExpireBucket { bucket: name.join(" ") })
+        }
+        [verb, dbm] if verb.eq_ignore_ascii_case("signal") => {
+            let value = dbm.strip_suffix("dBm").or_else(|| dbm.strip_suffix("dbm")).unwrap_or(dbm);
+            Ok(ScenarioAction::Signal { dbm: value.parse().map_err(|_| TelcoError::InvalidCommand(format!("Bad signal '{}'", dbm)))? })
+        }
+        [verb] if verb.eq_ignore_ascii_case("tick") => Ok(ScenarioAction::Tick),
+        _ => Err(TelcoError::InvalidCommand(format!("Unknown action '{}'", text))),
+    }
+}
+
+/// Parses sizes like "2GB", "1.5 GiB" or "500 mb". An explicit "i" always means 1024; otherwise the unit policy decides.
+#[uniffi::export]
+pub fn parse_bytes(text: String) -> Result<u64, TelcoError> {
+    let invalid = || TelcoError::InvalidCommand(format!("Unrecognized size '{}'", text));
+    let re = Regex::new(r"(?i)^\s*(\d+(?:\.\d+)?)\s*(?:([kmgt])(i)?)?b?\s*$").unwrap();
+    let caps = re.captures(&text).ok_or_else(invalid)?;
+    let value: f64 = caps[1].parse().map_err(|_| invalid())?;
+    let power = caps.get(2).and_then(|m| "kmgt".find(&m.as_str().to_lowercase())).map(|i| i as i32 + 1).unwrap_or(0);
+    let base = if caps.get(3).is_some() { 1024.0 } else { get_unit_policy().base() as f64 };
+    Ok((value * base.powi(power)).round() as u64)
+}
+
+/// Sizes per the unit policy, e.g. "1.5 GiB" or "512 KB". Whole values drop the decimal.
+#[uniffi::export]
+pub fn format_bytes(bytes: u64) -> String {
+    let policy = get_unit_policy();
+    format_scaled(bytes as f64, policy.base() as f64, policy.labels())
+}
+
+/// Bits per second in decimal units, as operators advertise speeds, e.g. "12.5 Mbps".
+#[uniffi::export]
+pub fn format_speed(bytes_per_sec: u64) -> String {
+    format_scaled(bytes_per_sec as f64 * 8.0, 1000.0, &["bps", "Kbps", "Mbps", "Gbps"])
+}
+
+/// Relative wording for a bucket expiry, e.g. "expires in 3 days" or "expired".
+#[uniffi::export]
+pub fn format_duration_until(expiry: u64) -> String {
+    let Some(secs) = expiry.checked_sub(now_secs()).filter(|s| *s > 0) else { return "expired".to_string(); };
+    let (amount, unit) = match secs {
+        s if s >= 86400 => (s / 86400, "day"),
+        s if s >= 3600 => (s / 3600, "hour"),
+        s if s >= 60 => (s / 60, "minute"),
+        _ => return "expires in less than a minute".to_string(),
+    };
+    format!("expires in {} {}{}", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+fn format_scaled(mut value: f64, base: f64, units: &[&str]) -> String {
+    let mut unit = 0;
+    while value >= base && unit + 1 < units.len() {
+        value /= base;
+        unit += 1;
+    }
+    let rounded = (value * 10.0).round() / 10.0;
+    if unit == 0 || rounded.fract() == 0.0 {
+        format!("{} {}", rounded as u64, units[unit])
+    } else {
+        format!("{:.1} {}", rounded, units[unit])
+    }
+}
+
+impl UserAccount {
+    // Totals are per unit: bytes across the data categories, seconds for voice, messages for SMS.
+    fn refresh_balances(&mut self) {
+        let total = |matches: &dyn Fn(QuotaType) -> bool| self.buckets.iter().filter(|b| matches(b.category)).map(|b| b.remaining_bytes).sum();
+        self.data_balance_bytes = total(&|c| c.is_data());
+        self.voice_balance_secs = total(&|c| c == QuotaType::Voice);
+        self.sms_balance = total(&|c| c == QuotaType::Sms);
+    }
+
+    // Empties the buckets at `indices` (ending them now) and carries up to `policy.max_bytes` of their balance into
+    // one rollover bucket per category. Returns the new buckets and the bytes that didn't fit.
+    fn roll_over(&mut self, indices: &[usize], policy: &RolloverPolicy, now: u64) -> (Vec<QuotaBucket>, u64) {
+        let mut budget = policy.max_bytes;
+        let mut forfeited = 0;
+        let mut carried: Vec<(QuotaType, u64)> = Vec::new();
+        for &i in indices {
+            let bucket = &mut self.buckets[i];
+            // Carried bytes don't roll over a second time, or they would never run out. Minutes, texts, FUP headroom and
+            // roaming passes lapse.
+            let carry = if matches!(bucket.source, BucketSource::Rollover | BucketSource::Roaming) || !bucket.category.is_data() || bucket.fair_usage.is_some() { 0 } else { bucket.remaining_bytes.min(budget) };
+            budget -= carry;
+            forfeited += bucket.remaining_bytes - carry;
+            bucket.remaining_bytes = 0;
+            bucket.expiry = bucket.expiry.min(now);
+            if carry == 0 { continue; }
+            match carried.iter_mut().find(|(c, _)| *c == bucket.category) {
+                Some((_, bytes)) => *bytes += carry,
+                None => carried.push((bucket.category, carry)),
+            }
+        }
+        let rolled: Vec<QuotaBucket> = carried.into_iter().map(|(category, bytes)| QuotaBucket {
+            id: new_id(),
+            name: format!("Rollover {:?}", category),
+            remaining_bytes: bytes,
+            initial_bytes: bytes,
+            category,
+            expiry: now + policy.validity_days as u64 * 86400,
+            source: BucketSource::Rollover,
+            active_window: None,
+            fair_usage: None,
+        }).collect();
+        self.buckets.extend(rolled.iter().cloned());
+        self.refresh_balances();
+        (rolled, forfeited)
+    }
+
+    fn fresh(id: &str) -> Self {
+        UserAccount { 
+            id: id.to_string(), 
+            is_active: true, 
+            biometric_locked: false, 
+            buckets: vec![], 
+            last_traffic_bytes: 0,
+            data_balance_bytes: 0,
+            voice_balance_secs: 0,
+            sms_balance: 0,
+            current_latency_ms: 46,
+            registration: RegistrationState::Registered,
+            grace: None,
+            throttled: false,
+            wallet_cents: 0,
+            signal_dbm: DEFAULT_SIGNAL_DBM,
+            signal_bars: signal_bars(DEFAULT_SIGNAL_DBM),
+        }
+    }
+
+    pub fn consume_data(&self, amount: u64, category: QuotaType) -> Result<Self, TelcoError> {
+        let mut next = self.clone();
+        let (shortfall, _) = next.drain(amount, category, &ConsumptionPolicy::default(), 100, now_secs())?;
+        if shortfall > 0 { return Err(TelcoError::InsufficientBalance); }
+        Ok(next)
+    }
+
+    // Deducts as much of `amount` as the applicable buckets cover, in place, and returns what they couldn't along
+    // with what `undo` needs to put the account back.
+    fn drain(&mut self, amount: u64, category: QuotaType, policy: &ConsumptionPolicy, rate_percent: u32, now: u64) -> Result<(u64, DrainUndo), TelcoError> {
+        if !self.is_active { return Err(TelcoError::AccountInactive); }
+        let mut undo = DrainUndo { buckets: Vec::new(), throttled: self.throttled, grace: self.grace.clone(), current_latency_ms: self.current_latency_ms };
+        let mut remaining = amount;
+        let mut over_fup = None;
+        // Roaming passes are drawn at face value; whatever they leave is charged at `rate_percent`.
+        let mut rated = rate_percent == 100;
+        let rate = |units: u64| u64::try_from((units as u128 * rate_percent as u128).div_ceil(100)).unwrap_or(u64::MAX);
+        for i in policy.order(&self.buckets, category, now, self.registration == RegistrationState::Roaming) {
+            if !rated && self.buckets[i].source != BucketSource::Roaming {
+                remaining = rate(remaining);
+                rated = true;
+            }
+            undo.buckets.push((i, self.buckets[i].clone()));
+            let bucket = &mut self.buckets[i];
+            if let Some(fup) = bucket.fair_usage.as_mut() {
+                fup.roll_period(now);
+                if fup.exceeded() { over_fup.get_or_insert(i); continue; }
+                fup.used_bytes += remaining;
+                bucket.remaining_bytes = fup.threshold_bytes.saturating_sub(fup.used_bytes);
+                self.throttled = fup.exceeded();
+                remaining = 0;
+                break;
+            }
+            let deduction = std::cmp::min(bucket.remaining_bytes, remaining);
+            bucket.remaining_bytes -= deduction;
+            remaining -= deduction;
+            if remaining == 0 { break; }
+        }
+        if !rated { remaining = rate(remaining); }
+        // An unlimited bucket past its threshold still carries the rest, throttled.
+        if let Some(i) = over_fup.filter(|_| remaining > 0) {
+            let bucket = &mut self.buckets[i];
+            if let Some(fup) = bucket.fair_usage.as_mut() { fup.used_bytes += remaining; }
+            bucket.remaining_bytes = 0;
+            self.throttled = true;
+            remaining = 0;
+        }
+        self.refresh_balances();
+        Ok((remaining, undo))
+    }
+
+    fn undo(&mut self, undo: DrainUndo) {
+        for (i, bucket) in undo.buckets.into_iter().rev() { self.buckets[i] = bucket; }
+        self.throttled = undo.throttled;
+        self.grace = undo.grace;
+        self.current_latency_ms = undo.current_latency_ms;
+        self.refresh_balances();
+    }
+}
+
+// What a `drain` overwrote: the buckets it touched and the throttle/grace fields the caller may go on to change.
+struct DrainUndo {
+    buckets: Vec<(usize, QuotaBucket)>,
+    throttled: bool,
+    grace: Option<GraceState>,
+    current_latency_ms: u32,
+}
+
+// Uppercase so tokens fit a QR code's compact alphanumeric mode.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() { return None; }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+// FIPS 180-4 SHA-256, only for signing share tokens.
+fn sha256(data: &[u8]) -> [u8; 32] {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 { message.push(0); }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for i in 0..16 { w[i] = u32::from_be_bytes([chunk[4 * i], chunk[4 * i + 1], chunk[4 * i + 2], chunk[4 * i + 3]]); }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let t1 = hh.wrapping_add(e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25))
+                .wrapping_add((e & f) ^ (!e & g)).wrapping_add(K[i]).wrapping_add(w[i]);
+            let t2 = (a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22)).wrapping_add((a & b) ^ (a & c) ^ (b & c));
+            hh = g; g = f; f = e; e = d.wrapping_add(t1); d = c; c = b; b = a; a = t1.wrapping_add(t2);
         }
-        #[cfg(not(feature = "sqlite"))]
-        {
-            let _ = limit;
-            Ok(vec![])
+        for (x, y) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) { *x = x.wrapping_add(y); }
+    }
+    let mut out = [0u8; 32];
+    for (i, word) in h.iter().enumerate() { out[4 * i..4 * i + 4].copy_from_slice(&word.to_be_bytes()); }
+    out
+}
+
+// RFC 2104 HMAC over SHA-256.
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 { block[..32].copy_from_slice(&sha256(key)); } else { block[..key.len()].copy_from_slice(key); }
+    let inner: Vec<u8> = block.iter().map(|b| b ^ 0x36).chain(message.iter().copied()).collect();
+    let outer: Vec<u8> = block.iter().map(|b| b ^ 0x5c).chain(sha256(&inner)).collect();
+    sha256(&outer)
+}
+
+// FNV-1a: stable across builds and platforms, unlike std's DefaultHasher.
+fn stable_hash(input: &str) -> u64 {
+    input.bytes().fold(0xcbf29ce484222325u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+// SplitMix64 state while a seed is set; `None` draws from the OS.
+static SEEDED_RNG: Mutex<Option<u64>> = Mutex::new(None);
+
+/// Makes the simulation's random choices in the process (response-delay jitter, generated demo traffic, ids) follow
+/// `seed`, so integration tests and recorded demos replay identically; pair it with a `TestClock` for timestamps
+/// too. `None` goes back to OS randomness. SIM verification codes and share-token nonces always come from the OS.
+#[uniffi::export]
+pub fn set_random_seed(seed: Option<u64>) {
+    *SEEDED_RNG.lock() = seed;
+}
+
+fn fill_random(buf: &mut [u8]) {
+    let mut rng = SEEDED_RNG.lock();
+    let Some(state) = rng.as_mut() else {
+        let _ = getrandom::getrandom(buf);
+        return;
+    };
+    for chunk in buf.chunks_mut(8) {
+        *state = state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        chunk.copy_from_slice(&(z ^ (z >> 31)).to_le_bytes()[..chunk.len()]);
+    }
+}
+
+fn random_u64() -> u64 {
+    let mut buf = [0u8; 8];
+    fill_random(&mut buf);
+    u64::from_le_bytes(buf)
+}
+
+// For values that guard something (verification codes, token nonces); never follows `set_random_seed`.
+fn secure_random_u64() -> u64 {
+    let mut buf = [0u8; 8];
+    let _ = getrandom::getrandom(&mut buf);
+    u64::from_le_bytes(buf)
+}
+
+// Random RFC 4122 version-4 identifier.
+fn new_id() -> String {
+    let mut b = [0u8; 16];
+    fill_random(&mut b);
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let hex: String = b.iter().map(|x| format!("{:02x}", x)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+fn parse_topping(command: &str, now: u64) -> Result<QuotaBucket, TelcoError> {
+    let re = Regex::new(r"(?i)(YouTube|Social|General)\s+(\d+)\s*(GB|MB)").unwrap();
+    let caps = re.captures(command).ok_or_else(|| TelcoError::InvalidCommand("Try 'YouTube 2GB'".to_string()))?;
+    let cat_str = caps.get(1).unwrap().as_str().to_lowercase();
+    let amount: u64 = caps[2].parse().map_err(|_| TelcoError::InvalidCommand(format!("{} is too large", &caps[2])))?;
+    let unit = caps.get(3).unwrap().as_str().to_uppercase();
+    let bytes = scaled_size(amount, &unit)?;
+    let category = match cat_str.as_str() { "youtube" => QuotaType::Video, "social" => QuotaType::Social, _ => QuotaType::General };
+    topping_bucket(bytes, category, now).map(|bucket| QuotaBucket { name: format!("{} {} Topping", amount, unit), ..bucket })
+}
+
+fn topping_bucket(bytes: u64, category: QuotaType, now: u64) -> Result<QuotaBucket, TelcoError> {
+    if !category.is_data() || bytes == 0 {
+        return Err(TelcoError::InvalidCommand(format!("Toppings are General, Social or Video data, not {} of {:?}", bytes, category)));
+    }
+    let name = if bytes.is_multiple_of(gigabytes(1)) { format!("{} GB Topping", bytes / gigabytes(1)) } else { format!("{} MB Topping", bytes.div_ceil(megabytes(1))) };
+    Ok(QuotaBucket {
+        id: new_id(),
+        name,
+        remaining_bytes: bytes,
+        initial_bytes: bytes,
+        category,
+        expiry: now + BucketSource::Purchase.lifetime_secs(),
+        source: BucketSource::Purchase,
+        active_window: None,
+        fair_usage: None,
+    })
+}
+
+// Next time the local (simulated) clock reads hour:minute; today if that is still ahead, otherwise tomorrow.
+fn next_local_time(hour: u32, minute: u32, now: u64) -> Option<u64> {
+    let now = chrono::DateTime::from_timestamp(now as i64, 0)?.with_timezone(&chrono::Local);
+    let today = now.date_naive().and_hms_opt(hour, minute, 0)?.and_local_timezone(chrono::Local).earliest()?;
+    let at = if today > now { today } else { today + chrono::Duration::days(1) };
+    Some(at.timestamp() as u64)
+}
+
+// The process clock; `None` until a clock or time scale is set, meaning the system clock at normal speed.
+static CLOCK: RwLock<Option<ScaledClock>> = RwLock::new(None);
+
+/// Where simulated time comes from: expiries, cycles, forecasts and timestamps all read it. `set_time_scale`
+/// still applies on top.
+#[uniffi::export(callback_interface)]
+pub trait ClockSource: Send + Sync {
+    /// Milliseconds since the Unix epoch.
+    fn now_ms(&self) -> u64;
+}
+
+/// The default clock: wall-clock time, read through `Date.now()` on wasm where `SystemTime` isn't available.
+pub struct SystemClock;
+
+impl ClockSource for SystemClock {
+    fn now_ms(&self) -> u64 {
+        real_now_ms()
+    }
+}
+
+/// A clock that only moves when told to, for tests. Clones share the same time.
+#[derive(Clone, Debug)]
+pub struct TestClock {
+    now_ms: Arc<AtomicU64>,
+}
+
+impl TestClock {
+    pub fn new(start_ms: u64) -> Self {
+        Self { now_ms: Arc::new(AtomicU64::new(start_ms)) }
+    }
+
+    pub fn advance(&self, ms: u64) {
+        self.now_ms.fetch_add(ms, Ordering::SeqCst);
+    }
+
+    pub fn set(&self, ms: u64) {
+        self.now_ms.store(ms, Ordering::SeqCst);
+    }
+}
+
+impl ClockSource for TestClock {
+    fn now_ms(&self) -> u64 {
+        self.now_ms.load(Ordering::SeqCst)
+    }
+}
+
+/// Runs `base` at `scale` times its speed, starting from `base`'s reading when built.
+pub struct ScaledClock {
+    base: Box<dyn ClockSource>,
+    anchor_base_ms: u64,
+    anchor_ms: u64,
+    scale: f64,
+}
+
+impl ScaledClock {
+    pub fn new(base: Box<dyn ClockSource>, scale: f64) -> Self {
+        let now = base.now_ms();
+        Self { base, anchor_base_ms: now, anchor_ms: now, scale }
+    }
+
+    // Re-anchors at the current scaled time, so changing speed doesn't jump.
+    fn rescale(&mut self, scale: f64) {
+        self.anchor_ms = self.now_ms();
+        self.anchor_base_ms = self.base.now_ms();
+        self.scale = scale;
+    }
+}
+
+impl ClockSource for ScaledClock {
+    fn now_ms(&self) -> u64 {
+        self.anchor_ms.saturating_add((self.base.now_ms().saturating_sub(self.anchor_base_ms) as f64 * self.scale) as u64)
+    }
+}
+
+/// Replaces the process clock, which every simulator without its own (`TelcoSimulator::with_clock`) reads. Simulated
+/// time restarts from the new clock's reading; the time scale is kept.
+#[uniffi::export]
+pub fn set_clock_source(clock: Box<dyn ClockSource>) {
+    let scaled = ScaledClock::new(clock, get_time_scale());
+    *CLOCK.write() = Some(scaled);
+}
+
+/// Goes back to `SystemClock`, keeping the time scale.
+#[uniffi::export]
+pub fn use_system_clock() {
+    set_clock_source(Box::new(SystemClock));
+}
+
+// Wall-clock time, also used for worker heartbeats, which must not follow a test clock.
+fn real_now_ms() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    { js_sys::Date::now() as u64 }
+    #[cfg(not(target_arch = "wasm32"))]
+    { SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_millis() as u64) }
+}
+
+/// Minutes since local midnight at `secs`.
+fn local_minute(secs: u64) -> u32 {
+    chrono::DateTime::from_timestamp(secs as i64, 0)
+        .map(|t| t.with_timezone(&chrono::Local))
+        .map_or(0, |t| t.hour() * 60 + t.minute())
+}
+
+fn now_secs() -> u64 {
+    CLOCK.read().as_ref().map_or_else(real_now_ms, |clock| clock.now_ms()) / 1000
+}
+
+/// Sleeps for `ms` of simulated time, never less than 10ms of wall time so fast clocks don't spin.
+#[cfg(not(target_arch = "wasm32"))]
+fn sim_sleep(ms: u64) {
+    let scale = get_time_scale();
+    thread::sleep(std::time::Duration::from_millis(((ms as f64 / scale) as u64).max(10)));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn worker_status(handle: Option<&thread::JoinHandle<()>>, heartbeat_ms: u64) -> WorkerStatus {
+    match handle {
+        None => WorkerStatus::NotStarted,
+        Some(h) if h.is_finished() => WorkerStatus::Dead,
+        Some(_) if real_now_ms().saturating_sub(heartbeat_ms) >= WORKER_STALL_MS => WorkerStatus::Stalled,
+        Some(_) => WorkerStatus::Running,
+    }
+}
+
+// How many of account ?1's snapshot history cursors lie below a `usage_history` row. Rows that differ were recorded
+// on opposite sides of a snapshot, so history rewrites must not merge them.
+#[cfg(feature = "sqlite")]
+const SNAPSHOT_SEGMENT: &str = "(SELECT COUNT(*) FROM snapshots WHERE snapshots.account_id = ?1 AND snapshots.history_cursor < usage_history.rowid)";
+
+/// Version stamped into the database file (`PRAGMA user_version`). Backups only restore into the same version.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[cfg(feature = "sqlite")]
+fn init_schema(conn: &Connection, id: &str) -> Result<(), TelcoError> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS accounts (id TEXT PRIMARY KEY, is_active BOOLEAN, locked BOOLEAN, last_traffic INTEGER);
+         CREATE TABLE IF NOT EXISTS buckets (id INTEGER PRIMARY KEY, account_id TEXT, name TEXT, remaining_bytes INTEGER, category TEXT, expiry INTEGER);
+         CREATE TABLE IF NOT EXISTS usage_history (timestamp INTEGER, amount INTEGER, category TEXT);
+         CREATE TABLE IF NOT EXISTS event_queue (seq INTEGER PRIMARY KEY, account_id TEXT, payload TEXT);
+         CREATE TABLE IF NOT EXISTS ad_rewards (account_id TEXT, timestamp INTEGER, bytes INTEGER);
+         CREATE TABLE IF NOT EXISTS achievements (account_id TEXT, kind TEXT, unlocked_at INTEGER, PRIMARY KEY (account_id, kind));
+         CREATE TABLE IF NOT EXISTS experiments (account_id TEXT, name TEXT, variant TEXT, PRIMARY KEY (account_id, name));
+         CREATE TABLE IF NOT EXISTS category_rules (account_id TEXT, pattern TEXT, category TEXT, priority INTEGER, PRIMARY KEY (account_id, pattern));
+         CREATE TABLE IF NOT EXISTS pending_orders (account_id TEXT, order_id TEXT, purchase TEXT, activate_at INTEGER, created_at INTEGER, PRIMARY KEY (account_id, order_id));
+         CREATE TABLE IF NOT EXISTS snapshots (account_id TEXT, name TEXT, created_at INTEGER, payload TEXT, history_cursor INTEGER, PRIMARY KEY (account_id, name));
+         CREATE TABLE IF NOT EXISTS redeemed_tokens (nonce TEXT PRIMARY KEY, account_id TEXT, redeemed_at INTEGER);
+         CREATE TABLE IF NOT EXISTS command_audit (account_id TEXT, timestamp INTEGER, source TEXT, command TEXT, allowed BOOLEAN, error TEXT);
+         CREATE TABLE IF NOT EXISTS family_pools (pool_id TEXT PRIMARY KEY, payload TEXT, version INTEGER);
+         CREATE TABLE IF NOT EXISTS preferences (account_id TEXT, key TEXT, value TEXT, PRIMARY KEY (account_id, key));
+         CREATE TABLE IF NOT EXISTS subscriptions (account_id TEXT, subscription_id TEXT, purchase TEXT, bucket_id TEXT, next_renewal_at INTEGER, renewals INTEGER, created_at INTEGER, PRIMARY KEY (account_id, subscription_id));
+         CREATE TABLE IF NOT EXISTS insight_config (account_id TEXT PRIMARY KEY, window_days INTEGER, top_up_threshold_days INTEGER, min_history_days INTEGER);
+         CREATE TABLE IF NOT EXISTS expired_buckets (account_id TEXT, bucket_id TEXT, archived_at INTEGER, payload TEXT);"
+    ).map_err(db_err)?;
+    ensure_column(conn, "buckets", "source", "TEXT DEFAULT 'Purchase'")?;
+    ensure_column(conn, "buckets", "bucket_id", "TEXT")?;
+    ensure_column(conn, "buckets", "deleted_at", "INTEGER")?;
+    ensure_column(conn, "buckets", "window_start", "INTEGER")?;
+    ensure_column(conn, "buckets", "window_end", "INTEGER")?;
+    ensure_column(conn, "buckets", "fup_threshold", "INTEGER")?;
+    ensure_column(conn, "buckets", "fup_used", "INTEGER")?;
+    ensure_column(conn, "buckets", "fup_period_start", "INTEGER")?;
+    ensure_column(conn, "buckets", "initial_bytes", "INTEGER")?;
+    conn.execute_batch("CREATE UNIQUE INDEX IF NOT EXISTS idx_buckets_account_bucket ON buckets (account_id, bucket_id);").map_err(db_err)?;
+    // Files from before nonces were unique across accounts keep the first redemption of each.
+    conn.execute_batch(
+        "DELETE FROM redeemed_tokens WHERE rowid NOT IN (SELECT MIN(rowid) FROM redeemed_tokens GROUP BY nonce);
+         CREATE UNIQUE INDEX IF NOT EXISTS idx_redeemed_tokens_nonce ON redeemed_tokens (nonce);"
+    ).map_err(db_err)?;
+    ensure_column(conn, "usage_history", "account_id", "TEXT")?;
+    ensure_column(conn, "usage_history", "day", "INTEGER")?;
+    ensure_column(conn, "usage_history", "source", "TEXT DEFAULT 'Manual'")?;
+    ensure_column(conn, "usage_history", "confidence", "REAL")?;
+    // Rows written before history was account-scoped belong to the account that owned the file.
+    conn.execute("UPDATE usage_history SET account_id = ?1 WHERE account_id IS NULL", params![id])
+        .map_err(db_err)?;
+    conn.execute_batch(
+        "UPDATE usage_history SET day = timestamp / 86400 WHERE day IS NULL;
+         CREATE INDEX IF NOT EXISTS idx_usage_account_time ON usage_history (account_id, timestamp, amount, category);
+         CREATE INDEX IF NOT EXISTS idx_usage_account_day ON usage_history (account_id, day, category, amount);"
+    ).map_err(db_err)?;
+    ensure_column(conn, "accounts", "journal_seq", "INTEGER DEFAULT 0")?;
+    ensure_column(conn, "accounts", "registration", "TEXT DEFAULT 'Registered'")?;
+    ensure_column(conn, "accounts", "grace_used", "INTEGER")?;
+    ensure_column(conn, "accounts", "grace_allowance", "INTEGER")?;
+    ensure_column(conn, "accounts", "grace_started", "INTEGER")?;
+    ensure_column(conn, "accounts", "throttled", "BOOLEAN DEFAULT 0")?;
+    ensure_column(conn, "accounts", "wallet_cents", "INTEGER DEFAULT 0")?;
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION).map_err(db_err)?;
+    Ok(())
+}
+
+/// Opens `db_path`, applying `key` first. A file written with a different key (or none) reads as `WrongDatabaseKey`.
+#[cfg(feature = "sqlite")]
+fn open_connection(db_path: &str, key: Option<&SecretString>) -> Result<Connection, TelcoError> {
+    let conn = Connection::open(db_path).map_err(db_err)?;
+    if let Some(_key) = key {
+        #[cfg(feature = "sqlcipher")]
+        conn.pragma_update(None, "key", _key.expose_secret()).map_err(db_err)?;
+        #[cfg(not(feature = "sqlcipher"))]
+        return Err(TelcoError::InvalidCommand("database encryption requires the `sqlcipher` feature".to_string()));
+    }
+    // SQLCipher only notices a bad key once it decrypts the first page.
+    match conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(())) {
+        Ok(()) => Ok(conn),
+        Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::NotADatabase => Err(TelcoError::WrongDatabaseKey),
+        Err(e) => Err(db_err(e)),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn configure_connection(conn: &Connection, config: &DbConfig) -> Result<(), TelcoError> {
+    conn.busy_timeout(std::time::Duration::from_millis(config.busy_timeout_ms as u64)).map_err(db_err)?;
+    let synchronous = match config.synchronous { SynchronousMode::Off => "OFF", SynchronousMode::Normal => "NORMAL", SynchronousMode::Full => "FULL" };
+    conn.pragma_update(None, "synchronous", synchronous).map_err(db_err)?;
+    // journal_mode answers with the mode it ended up in, so it has to be read rather than just set.
+    let mode = if config.wal { "WAL" } else { "DELETE" };
+    conn.query_row(&format!("PRAGMA journal_mode = {}", mode), [], |_| Ok(())).map_err(db_err)
+}
+
+/// Switches the worker's connection to `key`. A plaintext file is exported into an encrypted copy that replaces it.
+#[cfg(feature = "sqlcipher")]
+fn rekey_database(conn: &mut Connection, db_path: &str, current: Option<&SecretString>, key: &SecretString) -> Result<(), TelcoError> {
+    if current.is_some() {
+        return conn.pragma_update(None, "rekey", key.expose_secret()).map_err(db_err);
+    }
+    // A leftover -wal file would be replayed onto the encrypted copy once it replaces the original.
+    conn.query_row("PRAGMA journal_mode = DELETE", [], |_| Ok(())).map_err(db_err)?;
+    let encrypted = format!("{}.encrypting", db_path);
+    let _ = std::fs::remove_file(&encrypted);
+    conn.execute("ATTACH DATABASE ?1 AS encrypted KEY ?2", params![encrypted, key.expose_secret()]).map_err(db_err)?;
+    let exported = conn.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(())).map_err(db_err);
+    conn.execute_batch("DETACH DATABASE encrypted").map_err(db_err)?;
+    if let Err(e) = exported {
+        let _ = std::fs::remove_file(&encrypted);
+        return Err(e);
+    }
+    std::fs::rename(&encrypted, db_path).map_err(io_err)?;
+    *conn = open_connection(db_path, Some(key))?;
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn open_persistence(id: &str, db_path: &str, db_key: Arc<RwLock<Option<SecretString>>>, in_memory: bool) -> Result<(UserAccount, PersistedState, Persistence), TelcoError> {
+    let config = Arc::new(RwLock::new(DbConfig::default()));
+    let conn = open_connection(db_path, db_key.read().as_ref())?;
+    configure_connection(&conn, &config.read())?;
+    init_schema(&conn, id)?;
+    let persisted = load_persisted_state(&conn, id);
+    let account = load_account_internal(&conn, id).unwrap_or_else(|_| UserAccount::fresh(id));
+    let applied_seq: u64 = conn.query_row("SELECT journal_seq FROM accounts WHERE id = ?1", params![id], |row| row.get::<_, Option<u64>>(0))
+        .ok().flatten().unwrap_or(0);
+    let (journal, recovered) = if in_memory {
+        (IntentJournal { file: None, next_seq: applied_seq + 1 }, Vec::new())
+    } else {
+        IntentJournal::open(&journal_path(db_path, id), applied_seq)
+    };
+    if !recovered.is_empty() { engine_log!(LogLevel::Info, "persistence", "replaying {} journaled writes for {}", recovered.len(), id); }
+    let account = recovered.last().map(|e| e.account.clone()).unwrap_or(account);
+
+    let journal = Arc::new(Mutex::new(journal));
+    let (tx, rx) = mpsc::sync_channel::<PersistenceMsg>(1000);
+    let rx = Arc::new(Mutex::new(rx));
+    let stats = Arc::new(WriterStats::default());
+    stats.heartbeat_ms.store(real_now_ms(), Ordering::Relaxed);
+    let worker = spawn_persistence_worker(db_path.to_string(), db_key, config.clone(), rx.clone(), journal.clone(), stats.clone());
+    for entry in recovered {
+        stats.queued.fetch_add(1, Ordering::Relaxed);
+        if tx.try_send(PersistenceMsg::Account { account: Arc::new(entry.account), usage: entry.usage, journal_seq: entry.seq }).is_err() {
+            stats.queued.fetch_sub(1, Ordering::Relaxed);
         }
     }
+    let anchor = in_memory.then(|| Mutex::new(conn));
+    Ok((account, persisted, Persistence { tx, rx, worker: Mutex::new(Some(worker)), closed: AtomicBool::new(false), stats, journal, config, read_pool: ReadPool::default(), _anchor: anchor }))
+}
 
-    pub fn start_network_sensor(self: Arc<Self>) {
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            thread::spawn(move || {
-                let mut last_bytes = 0;
-                loop {
-                    if let Ok(content) = std::fs::read_to_string("/proc/net/dev") {
-                        for line in content.lines() {
-                            // Monitor common interfaces
-                            if line.contains("wlp3s0:") || line.contains("tun0:") || line.contains("eth0:") {
-                                let parts: Vec<&str> = line.split_whitespace().collect();
-                                if parts.len() > 1 {
-                                    let bytes: u64 = parts[1].parse().unwrap_or(0);
-                                    if last_bytes > 0 && bytes > last_bytes {
-                                        let diff = bytes - last_bytes;
-                                        // Map real traffic to Social quota for visibility in demo
-                                        let _ = self.simulate_usage(diff, QuotaType::Social);
-                                    }
-                                    last_bytes = bytes;
-                                }
-                            }
+// Heartbeats at least once a second while idle so the watchdog can tell an empty queue from a stuck write.
+#[cfg(feature = "sqlite")]
+fn spawn_persistence_worker(db_path: String, db_key: Arc<RwLock<Option<SecretString>>>, config: Arc<RwLock<DbConfig>>, rx: Arc<Mutex<mpsc::Receiver<PersistenceMsg>>>, journal: Arc<Mutex<IntentJournal>>, stats: Arc<WriterStats>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut conn = match open_connection(&db_path, db_key.read().as_ref()) {
+            Ok(conn) => conn,
+            Err(e) => { engine_log!(LogLevel::Error, "persistence", "cannot open {}: {}", db_path, e); return; }
+        };
+        // Other simulators (e.g. a fleet) may be writing to the same file.
+        if let Err(e) = configure_connection(&conn, &config.read()) { engine_log!(LogLevel::Warn, "persistence", "cannot configure {}: {}", db_path, e); }
+        // Buckets as this connection last wrote them, so an account save only touches the ones that changed.
+        let mut written_buckets: HashMap<String, Vec<QuotaBucket>> = HashMap::new();
+        loop {
+            stats.heartbeat_ms.store(real_now_ms(), Ordering::Relaxed);
+            let first = match rx.lock().recv_timeout(std::time::Duration::from_secs(1)) {
+                Ok(msg) => msg,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            };
+            let mut batch = vec![first];
+            {
+                let rx = rx.lock();
+                while batch.len() < WRITE_BATCH_MAX {
+                    let Ok(msg) = rx.try_recv() else { break; };
+                    batch.push(msg);
+                }
+            }
+            let mut writes = Vec::new();
+            for msg in batch {
+                if !msg.is_request() {
+                    writes.push(msg);
+                    continue;
+                }
+                // Requests see every write queued before them.
+                write_batch(&mut conn, std::mem::take(&mut writes), &stats, &journal, &mut written_buckets);
+                stats.queued.fetch_sub(1, Ordering::Relaxed);
+                match msg {
+                    PersistenceMsg::Rekey { key, reply } => {
+                        // Holding the key lock keeps other connections from opening the file mid-switch.
+                        let mut current = db_key.write();
+                        #[cfg(feature = "sqlcipher")]
+                        let result = rekey_database(&mut conn, &db_path, current.as_ref(), &key);
+                        #[cfg(not(feature = "sqlcipher"))]
+                        let result = Err(TelcoError::InvalidCommand("database encryption requires the `sqlcipher` feature".to_string()));
+                        if result.is_ok() {
+                            *current = Some(key);
+                            let _ = configure_connection(&conn, &config.read());
                         }
+                        drop(current);
+                        let _ = reply.send(result);
+                    }
+                    PersistenceMsg::Backup { path, reply } => {
+                        let _ = reply.send(backup_database(&conn, &path, db_key.read().as_ref()));
+                    }
+                    PersistenceMsg::Restore { path, account_id, reply } => {
+                        written_buckets.clear();
+                        let journal_seq = journal.lock().next_seq.saturating_sub(1);
+                        let result = restore_database(&mut conn, &path, db_key.read().as_ref()).and_then(|_| {
+                            conn.execute("UPDATE accounts SET journal_seq = ?2 WHERE id = ?1", params![account_id, journal_seq]).map(|_| ()).map_err(db_err)
+                        });
+                        let _ = reply.send(result);
                     }
-                    thread::sleep(std::time::Duration::from_millis(500));
+                    PersistenceMsg::ClaimToken { account_id, nonce, redeemed_at, reply } => {
+                        // The nonce key makes this atomic against other simulators writing the same file.
+                        let result = conn.execute("INSERT OR IGNORE INTO redeemed_tokens (account_id, nonce, redeemed_at) VALUES (?1, ?2, ?3)",
+                            params![account_id, nonce, redeemed_at]).map(|inserted| inserted > 0).map_err(db_err);
+                        let _ = reply.send(result);
+                    }
+                    PersistenceMsg::Shutdown { reply } => {
+                        // Folds the WAL back into the database file so nothing is left only in `-wal`.
+                        let _ = reply.send(conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(())).map_err(db_err));
+                        return;
+                    }
+                    _ => {}
                 }
-            });
+            }
+            write_batch(&mut conn, writes, &stats, &journal, &mut written_buckets);
+        }
+    })
+}
+
+// Applies queued writes in one transaction, folding each run of updates to the same account into a single save.
+// Should the transaction not start (another writer holds the lock past the busy timeout), each write commits alone.
+#[cfg(feature = "sqlite")]
+fn write_batch(conn: &mut Connection, writes: Vec<PersistenceMsg>, stats: &WriterStats, journal: &Mutex<IntentJournal>, written_buckets: &mut HashMap<String, Vec<QuotaBucket>>) {
+    if writes.is_empty() { return; }
+    let count = writes.len() as u64;
+    let in_transaction = conn.execute_batch("BEGIN IMMEDIATE").is_ok();
+    let mut applied_seq = None;
+    let mut writes = writes.into_iter().peekable();
+    while let Some(msg) = writes.next() {
+        if injected_fault(stats.write_fault_percent.load(Ordering::Relaxed)) {
+            stats.record_failure(TelcoError::DatabaseError { failure: StorageFailure::Busy, message: "injected write fault".to_string() });
+            continue;
+        }
+        let result = match msg {
+            PersistenceMsg::Account { mut account, mut usage, mut journal_seq } => {
+                while let Some(PersistenceMsg::Account { account: next, .. }) = writes.peek() {
+                    if next.id != account.id { break; }
+                    let Some(PersistenceMsg::Account { account: next, usage: next_usage, journal_seq: next_seq }) = writes.next() else { break; };
+                    account = next;
+                    usage.extend(next_usage);
+                    journal_seq = next_seq;
+                }
+                let result = write_account(conn, &account, &usage, journal_seq, written_buckets);
+                // The journal keeps a failed account write, so it is retried on the next open.
+                applied_seq = result.is_ok().then_some(journal_seq);
+                result
+            }
+            msg => {
+                if let PersistenceMsg::DeleteAccount { account_id } = &msg { written_buckets.remove(account_id); }
+                apply_persistence_msg(conn, msg)
+            }
+        };
+        if let Err(e) = result { stats.record_failure(e); }
+    }
+    if in_transaction {
+        if let Err(e) = conn.execute_batch("COMMIT") {
+            let _ = conn.execute_batch("ROLLBACK");
+            written_buckets.clear();
+            applied_seq = None;
+            stats.record_failure(db_err(e));
         }
     }
+    stats.queued.fetch_sub(count, Ordering::Relaxed);
+    stats.last_write_at.store(now_secs(), Ordering::Relaxed);
+    if let Some(seq) = applied_seq { journal.lock().truncate_if_applied(seq); }
 }
 
-impl TelcoSimulator {
-    fn notify_and_persist(&self, account: UserAccount, _usage: Option<(u64, QuotaType, u64)>) {
-        if let Some(handler) = &*self.update_handler.read() { handler.on_account_updated(account.clone()); }
-        #[cfg(feature = "sqlite")]
-        {
-            let _ = self.persistence_tx.try_send(PersistenceMsg { account, usage: _usage });
+#[cfg(feature = "sqlite")]
+fn write_account(conn: &mut Connection, account: &UserAccount, usage: &[UsageEntry], journal_seq: u64, written_buckets: &mut HashMap<String, Vec<QuotaBucket>>) -> Result<(), TelcoError> {
+    let sp = conn.savepoint().map_err(db_err)?;
+    for u in usage { insert_usage_row(&sp, &account.id, &(*u).into()).map_err(db_err)?; }
+    save_account_rows(&sp, account, journal_seq, written_buckets.get(&account.id).map(Vec::as_slice))?;
+    sp.commit().map_err(db_err)?;
+    written_buckets.insert(account.id.clone(), account.buckets.clone());
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn backup_database(conn: &Connection, path: &str, key: Option<&SecretString>) -> Result<(), TelcoError> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(io_err(e)),
+        _ => {}
+    }
+    let mut dest = open_connection(path, key)?;
+    let backup = rusqlite::backup::Backup::new(conn, &mut dest).map_err(db_err)?;
+    backup.run_to_completion(256, std::time::Duration::ZERO, None).map_err(db_err)
+}
+
+#[cfg(feature = "sqlite")]
+fn restore_database(conn: &mut Connection, path: &str, key: Option<&SecretString>) -> Result<(), TelcoError> {
+    let source = open_connection(path, key)?;
+    let backup = rusqlite::backup::Backup::new(&source, conn).map_err(db_err)?;
+    backup.run_to_completion(256, std::time::Duration::ZERO, None).map_err(db_err)
+}
+
+#[cfg(feature = "sqlite")]
+fn backup_manifest_path(path: &str) -> String {
+    format!("{}.manifest.json", path)
+}
+
+#[cfg(feature = "sqlite")]
+fn apply_persistence_msg(conn: &mut Connection, msg: PersistenceMsg) -> Result<(), TelcoError> {
+    match msg {
+        PersistenceMsg::Account { account, usage, journal_seq } => {
+            write_account(conn, &account, &usage, journal_seq, &mut HashMap::new())?;
+        }
+        PersistenceMsg::Rekey { .. } | PersistenceMsg::Backup { .. } | PersistenceMsg::Restore { .. } | PersistenceMsg::ClaimToken { .. }
+        | PersistenceMsg::Shutdown { .. } => {}
+        PersistenceMsg::PendingOrders { account_id, orders } => {
+            let tx = conn.savepoint().map_err(db_err)?;
+            tx.execute("DELETE FROM pending_orders WHERE account_id = ?1", params![account_id]).map_err(db_err)?;
+            for o in orders {
+                tx.execute("INSERT INTO pending_orders (account_id, order_id, purchase, activate_at, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+                    params![account_id, o.id, o.purchase, o.activate_at, o.created_at]).map_err(db_err)?;
+            }
+            tx.commit().map_err(db_err)?;
+        }
+        PersistenceMsg::Preference { account_id, key, value } => {
+            match value.and_then(|v| serde_json::to_string(&v).ok()) {
+                Some(json) => { conn.execute("INSERT OR REPLACE INTO preferences (account_id, key, value) VALUES (?1, ?2, ?3)", params![account_id, key, json]).map_err(db_err)?; }
+                None => { conn.execute("DELETE FROM preferences WHERE account_id = ?1 AND key = ?2", params![account_id, key]).map_err(db_err)?; }
+            }
+        }
+        PersistenceMsg::Configure { config } => {
+            configure_connection(conn, &config)?;
+        }
+        PersistenceMsg::InsightConfig { account_id, config } => {
+            conn.execute("INSERT OR REPLACE INTO insight_config (account_id, window_days, top_up_threshold_days, min_history_days) VALUES (?1, ?2, ?3, ?4)",
+                params![account_id, config.window_days, config.top_up_threshold_days, config.min_history_days]).map_err(db_err)?;
+        }
+        PersistenceMsg::Subscriptions { account_id, subscriptions } => {
+            let tx = conn.savepoint().map_err(db_err)?;
+            tx.execute("DELETE FROM subscriptions WHERE account_id = ?1", params![account_id]).map_err(db_err)?;
+            for s in subscriptions {
+                tx.execute("INSERT INTO subscriptions (account_id, subscription_id, purchase, bucket_id, next_renewal_at, renewals, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![account_id, s.id, s.purchase, s.bucket_id, s.next_renewal_at, s.renewals, s.created_at]).map_err(db_err)?;
+            }
+            tx.commit().map_err(db_err)?;
+        }
+        PersistenceMsg::CreateSnapshot { account_id, name, created_at, payload } => {
+            conn.execute(
+                "INSERT OR REPLACE INTO snapshots (account_id, name, created_at, payload, history_cursor)
+                 VALUES (?1, ?2, ?3, ?4, (SELECT COALESCE(MAX(rowid), 0) FROM usage_history))",
+                params![account_id, name, created_at, payload]).map_err(db_err)?;
+        }
+        PersistenceMsg::DeleteSnapshot { account_id, name } => {
+            conn.execute("DELETE FROM snapshots WHERE account_id = ?1 AND name = ?2", params![account_id, name]).map_err(db_err)?;
+        }
+        PersistenceMsg::RestoreSnapshot { account_id, name, created_at } => {
+            let tx = conn.savepoint().map_err(db_err)?;
+            tx.execute("DELETE FROM usage_history WHERE account_id = ?1 AND rowid > (SELECT history_cursor FROM snapshots WHERE account_id = ?1 AND name = ?2)",
+                params![account_id, name]).map_err(db_err)?;
+            tx.execute("DELETE FROM ad_rewards WHERE account_id = ?1 AND timestamp > ?2", params![account_id, created_at]).map_err(db_err)?;
+            tx.execute("DELETE FROM achievements WHERE account_id = ?1 AND unlocked_at > ?2", params![account_id, created_at]).map_err(db_err)?;
+            tx.commit().map_err(db_err)?;
+        }
+        PersistenceMsg::ReleaseToken { nonce } => {
+            conn.execute("DELETE FROM redeemed_tokens WHERE nonce = ?1", params![nonce]).map_err(db_err)?;
+        }
+        PersistenceMsg::FamilyPool { pool_id, payload, version } => {
+            conn.execute(
+                "INSERT INTO family_pools (pool_id, payload, version) VALUES (?1, ?2, ?3)
+                 ON CONFLICT (pool_id) DO UPDATE SET payload = excluded.payload, version = excluded.version WHERE excluded.version > family_pools.version",
+                params![pool_id, payload, version]).map_err(db_err)?;
+        }
+        PersistenceMsg::CommandAudit { account_id, entry } => {
+            conn.execute("INSERT INTO command_audit (account_id, timestamp, source, command, allowed, error) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![account_id, entry.timestamp, format!("{:?}", entry.source), entry.command, entry.allowed, entry.error]).map_err(db_err)?;
+        }
+        PersistenceMsg::DeleteAccount { account_id } => {
+            delete_account_rows(conn, &account_id)?;
+        }
+        PersistenceMsg::ReplaceUsage { account_id, usage } => {
+            let tx = conn.savepoint().map_err(db_err)?;
+            tx.execute("DELETE FROM usage_history WHERE account_id = ?1", params![account_id]).map_err(db_err)?;
+            for u in usage {
+                tx.execute("INSERT INTO usage_history (timestamp, amount, category, account_id, day, source, confidence) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![u.timestamp, u.bytes, format!("{:?}", u.category), account_id, u.timestamp / 86400, format!("{:?}", u.source), u.confidence]).map_err(db_err)?;
+            }
+            tx.commit().map_err(db_err)?;
+        }
+        PersistenceMsg::PurgeTrash { account_id, before } => {
+            conn.execute("DELETE FROM buckets WHERE account_id = ?1 AND deleted_at < ?2", params![account_id, before]).map_err(db_err)?;
+        }
+        PersistenceMsg::TrashBucket { account_id, bucket, deleted_at } => save_bucket_row(conn, &account_id, &bucket, Some(deleted_at))?,
+        PersistenceMsg::ArchiveBuckets { account_id, buckets } => {
+            let tx = conn.savepoint().map_err(db_err)?;
+            for expired in buckets {
+                let payload = serde_json::to_string(&expired.bucket).map_err(|e| TelcoError::SerializationFailed(e.to_string()))?;
+                tx.execute("INSERT INTO expired_buckets (account_id, bucket_id, archived_at, payload) VALUES (?1, ?2, ?3, ?4)",
+                    params![account_id, expired.bucket.id, expired.archived_at, payload]).map_err(db_err)?;
+                // Expired buckets aren't restorable, so they must not linger in the trash.
+                tx.execute("DELETE FROM buckets WHERE account_id = ?1 AND bucket_id = ?2", params![account_id, expired.bucket.id]).map_err(db_err)?;
+            }
+            tx.commit().map_err(db_err)?;
+        }
+        PersistenceMsg::EventQueue { account_id, events } => {
+            let tx = conn.savepoint().map_err(db_err)?;
+            tx.execute("DELETE FROM event_queue WHERE account_id = ?1", params![account_id]).map_err(db_err)?;
+            for e in events {
+                if let Ok(payload) = serde_json::to_string(&e) {
+                    tx.execute("INSERT INTO event_queue (account_id, payload) VALUES (?1, ?2)", params![account_id, payload]).map_err(db_err)?;
+                }
+            }
+            tx.commit().map_err(db_err)?;
+        }
+        PersistenceMsg::AdReward { account_id, timestamp, bytes } => {
+            conn.execute("INSERT INTO ad_rewards (account_id, timestamp, bytes) VALUES (?1, ?2, ?3)",
+                params![account_id, timestamp, bytes]).map_err(db_err)?;
+        }
+        PersistenceMsg::Achievement { account_id, record } => {
+            conn.execute("INSERT OR IGNORE INTO achievements (account_id, kind, unlocked_at) VALUES (?1, ?2, ?3)",
+                params![account_id, format!("{:?}", record.achievement), record.unlocked_at]).map_err(db_err)?;
+        }
+        PersistenceMsg::ExperimentAssignment { account_id, experiment, variant } => {
+            conn.execute("INSERT OR REPLACE INTO experiments (account_id, name, variant) VALUES (?1, ?2, ?3)",
+                params![account_id, experiment, variant]).map_err(db_err)?;
+        }
+        PersistenceMsg::CategoryRules { account_id, rules } => {
+            let tx = conn.savepoint().map_err(db_err)?;
+            tx.execute("DELETE FROM category_rules WHERE account_id = ?1", params![account_id]).map_err(db_err)?;
+            for r in rules {
+                tx.execute("INSERT INTO category_rules (account_id, pattern, category, priority) VALUES (?1, ?2, ?3, ?4)",
+                    params![account_id, r.pattern, format!("{:?}", r.category), r.priority]).map_err(db_err)?;
+            }
+            tx.commit().map_err(db_err)?;
         }
     }
+    Ok(())
 }
 
-impl UserAccount {
-    pub fn consume_data(&self, amount: u64, category: QuotaType) -> Result<Self, TelcoError> {
-        if !self.is_active { return Err(TelcoError::AccountInactive); }
-        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
-        let mut new_buckets = self.buckets.clone();
-        let mut remaining = amount;
-        let priorities = if category == QuotaType::General { vec![QuotaType::General] } else { vec![category, QuotaType::General] };
-        for p in priorities {
-            for bucket in new_buckets.iter_mut().filter(|b| b.category == p && b.expiry > now) {
-                let deduction = std::cmp::min(bucket.remaining_bytes, remaining);
-                bucket.remaining_bytes -= deduction;
-                remaining -= deduction;
-                if remaining == 0 { break; }
+#[cfg(feature = "sqlite")]
+fn usage_filter_sql(select: &str, account_id: String, filter: &UsageFilter) -> (String, Vec<Box<dyn rusqlite::ToSql>>) {
+    let mut sql = format!("{} WHERE account_id = ?", select);
+    let mut args: Vec<Box<dyn rusqlite::ToSql>> = vec![Box::new(account_id)];
+    if let Some(from) = filter.from { sql += " AND timestamp >= ?"; args.push(Box::new(from)); }
+    if let Some(to) = filter.to { sql += " AND timestamp < ?"; args.push(Box::new(to)); }
+    if let Some(category) = filter.category { sql += " AND category = ?"; args.push(Box::new(format!("{:?}", category))); }
+    if let Some(min_amount) = filter.min_amount { sql += " AND amount >= ?"; args.push(Box::new(min_amount)); }
+    if let Some(source) = filter.source { sql += " AND source = ?"; args.push(Box::new(format!("{:?}", source))); }
+    (sql, args)
+}
+
+#[cfg(feature = "sqlite")]
+fn insert_usage_row(conn: &Connection, account_id: &str, r: &UsageRecord) -> rusqlite::Result<usize> {
+    conn.prepare_cached("INSERT INTO usage_history (timestamp, amount, category, account_id, day, source, confidence) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)")?
+        .execute(params![r.timestamp, r.amount, r.category, account_id, r.timestamp / 86400, format!("{:?}", r.source), r.confidence])
+}
+
+/// Writes the account row and its buckets. `previous` is what this connection last wrote for the account; given it,
+/// only buckets that changed are written. Run it inside a transaction.
+#[cfg(feature = "sqlite")]
+fn save_account_rows(conn: &Connection, account: &UserAccount, journal_seq: u64, previous: Option<&[QuotaBucket]>) -> Result<(), TelcoError> {
+    let grace = account.grace.as_ref();
+    conn.prepare_cached("INSERT OR REPLACE INTO accounts (id, is_active, locked, last_traffic, journal_seq, grace_used, grace_allowance, grace_started, throttled, wallet_cents, registration) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)").map_err(db_err)?
+        .execute(params![account.id, account.is_active, account.biometric_locked, account.last_traffic_bytes, journal_seq,
+            grace.map(|g| g.used_bytes), grace.map(|g| g.allowance_bytes), grace.map(|g| g.started_at), account.throttled, account.wallet_cents,
+            format!("{:?}", account.registration)]).map_err(db_err)?;
+    let previous: Option<HashMap<&str, &QuotaBucket>> = previous.map(|buckets| buckets.iter().map(|b| (b.id.as_str(), b)).collect());
+    match &previous {
+        None => {
+            // Rows from before bucket ids existed are rewritten below under the ids they were loaded with.
+            conn.execute("DELETE FROM buckets WHERE account_id = ?1 AND bucket_id IS NULL", params![account.id]).map_err(db_err)?;
+            // Anything no longer on the account goes; live buckets are written back below. Trashed rows stay.
+            conn.execute("DELETE FROM buckets WHERE account_id = ?1 AND deleted_at IS NULL", params![account.id]).map_err(db_err)?;
+        }
+        Some(previous) => {
+            let mut delete = conn.prepare_cached("DELETE FROM buckets WHERE account_id = ?1 AND bucket_id = ?2 AND deleted_at IS NULL").map_err(db_err)?;
+            for id in previous.keys().filter(|id| !account.buckets.iter().any(|b| b.id == **id)) {
+                delete.execute(params![account.id, id]).map_err(db_err)?;
             }
-            if remaining == 0 { break; }
         }
-        if remaining > 0 { return Err(TelcoError::InsufficientBalance); }
-        let total: u64 = new_buckets.iter().map(|b| b.remaining_bytes).sum();
-        Ok(Self { 
-            buckets: new_buckets, 
-            data_balance_bytes: total,
-            ..self.clone() 
-        })
     }
+    for b in account.buckets.iter().filter(|b| previous.as_ref().is_none_or(|p| p.get(b.id.as_str()) != Some(b))) {
+        save_bucket_row(conn, &account.id, b, None)?;
+    }
+    Ok(())
+}
+
+// Live buckets have no `deleted_at`; cancelled ones keep their row with it set until the trash is purged.
+#[cfg(feature = "sqlite")]
+fn save_bucket_row(conn: &Connection, account_id: &str, b: &QuotaBucket, deleted_at: Option<u64>) -> Result<(), TelcoError> {
+    conn.prepare_cached(
+        "INSERT INTO buckets (account_id, name, remaining_bytes, category, expiry, source, bucket_id, window_start, window_end, fup_threshold, fup_used, fup_period_start, deleted_at, initial_bytes)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)
+         ON CONFLICT (account_id, bucket_id) DO UPDATE SET name = excluded.name, remaining_bytes = excluded.remaining_bytes,
+            category = excluded.category, expiry = excluded.expiry, source = excluded.source,
+            window_start = excluded.window_start, window_end = excluded.window_end, fup_threshold = excluded.fup_threshold,
+            fup_used = excluded.fup_used, fup_period_start = excluded.fup_period_start, deleted_at = excluded.deleted_at,
+            initial_bytes = excluded.initial_bytes"
+    ).map_err(db_err)?
+        .execute(params![account_id, b.name, b.remaining_bytes, format!("{:?}", b.category), b.expiry, format!("{:?}", b.source), b.id,
+            b.active_window.map(|w| w.start_minute), b.active_window.map(|w| w.end_minute),
+            b.fair_usage.map(|f| f.threshold_bytes), b.fair_usage.map(|f| f.used_bytes), b.fair_usage.map(|f| f.period_started_at), deleted_at, b.initial_bytes])
+        .map_err(db_err)?;
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn usage_record_from_row(row: &rusqlite::Row) -> rusqlite::Result<UsageRecord> {
+    Ok(UsageRecord {
+        timestamp: row.get(0)?,
+        amount: row.get(1)?,
+        category: row.get(2)?,
+        source: UsageSource::parse(&row.get::<_, Option<String>>(3)?.unwrap_or_default()),
+        confidence: row.get(4)?,
+    })
+}
+
+#[cfg(feature = "sqlite")]
+fn ensure_column(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<(), TelcoError> {
+    let exists = conn.prepare(&format!("SELECT {} FROM {} LIMIT 0", column, table)).is_ok();
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, decl))
+            .map_err(db_err)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn load_persisted_state(conn: &Connection, id: &str) -> PersistedState {
+    PersistedState {
+        events: load_event_queue(conn, id),
+        ad_rewards: load_ad_rewards(conn, id),
+        achievements: load_achievements(conn, id),
+        experiment_assignments: load_experiment_assignments(conn, id),
+        category_rules: load_category_rules(conn, id),
+        trashed_buckets: load_trashed_buckets(conn, id),
+        pending_orders: load_pending_orders(conn, id),
+        snapshots: load_snapshots(conn, id),
+        redeemed_tokens: load_redeemed_tokens(conn),
+        subscriptions: load_subscriptions(conn, id),
+        preferences: load_preferences(conn, id),
+        insight_config: load_insight_config(conn, id),
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn load_insight_config(conn: &Connection, id: &str) -> Option<InsightConfig> {
+    conn.query_row("SELECT window_days, top_up_threshold_days, min_history_days FROM insight_config WHERE account_id = ?1", params![id], |row| Ok(InsightConfig {
+        window_days: row.get(0)?,
+        top_up_threshold_days: row.get(1)?,
+        min_history_days: row.get(2)?,
+    })).ok()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_preferences(conn: &Connection, id: &str) -> HashMap<String, PreferenceValue> {
+    let Ok(mut stmt) = conn.prepare("SELECT key, value FROM preferences WHERE account_id = ?1") else { return HashMap::new(); };
+    stmt.query_map(params![id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)))
+        .map(|rows| rows.filter_map(|r| r.ok()).filter_map(|(key, value)| serde_json::from_str(&value).ok().map(|v| (key, v))).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_subscriptions(conn: &Connection, id: &str) -> Vec<Subscription> {
+    let Ok(mut stmt) = conn.prepare("SELECT subscription_id, purchase, bucket_id, next_renewal_at, renewals, created_at FROM subscriptions WHERE account_id = ?1 ORDER BY next_renewal_at") else { return Vec::new(); };
+    stmt.query_map(params![id], |row| Ok(Subscription {
+        id: row.get(0)?,
+        purchase: row.get(1)?,
+        bucket_id: row.get(2)?,
+        next_renewal_at: row.get(3)?,
+        renewals: row.get(4)?,
+        created_at: row.get(5)?,
+    }))
+        .map(|rows| rows.filter_map(|r| r.ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+// Every account's: a token redeems once per file, not once per account.
+fn load_redeemed_tokens(conn: &Connection) -> Vec<String> {
+    let Ok(mut stmt) = conn.prepare("SELECT nonce FROM redeemed_tokens") else { return Vec::new(); };
+    stmt.query_map([], |row| row.get(0))
+        .map(|rows| rows.filter_map(|r| r.ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_snapshots(conn: &Connection, id: &str) -> Vec<StoredSnapshot> {
+    let Ok(mut stmt) = conn.prepare("SELECT name, created_at, payload FROM snapshots WHERE account_id = ?1 ORDER BY created_at") else { return Vec::new(); };
+    stmt.query_map(params![id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?, row.get::<_, String>(2)?)))
+        .map(|rows| rows.filter_map(|r| r.ok())
+            .filter_map(|(name, created_at, payload)| serde_json::from_str::<SnapshotState>(&payload).ok().map(|state| StoredSnapshot {
+                info: AccountSnapshot { name, created_at, bucket_count: state.account.buckets.len() as u32, balance_bytes: state.account.data_balance_bytes },
+                state,
+            }))
+            .collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_pending_orders(conn: &Connection, id: &str) -> Vec<ScheduledOrder> {
+    let Ok(mut stmt) = conn.prepare("SELECT order_id, purchase, activate_at, created_at FROM pending_orders WHERE account_id = ?1 ORDER BY activate_at") else { return Vec::new(); };
+    stmt.query_map(params![id], |row| Ok(ScheduledOrder { id: row.get(0)?, purchase: row.get(1)?, activate_at: row.get(2)?, created_at: row.get(3)? }))
+        .map(|rows| rows.filter_map(|r| r.ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_trashed_buckets(conn: &Connection, id: &str) -> Vec<TrashedBucket> {
+    let Ok(mut stmt) = conn.prepare("SELECT name, remaining_bytes, category, expiry, source, bucket_id, deleted_at, window_start, window_end, fup_threshold, fup_used, fup_period_start, initial_bytes FROM buckets WHERE account_id = ?1 AND deleted_at IS NOT NULL AND bucket_id IS NOT NULL ORDER BY deleted_at") else { return Vec::new(); };
+    stmt.query_map(params![id], |row| {
+        let category = QuotaType::from_name(&row.get::<_, String>(2)?).unwrap_or(QuotaType::General);
+        let source = BucketSource::parse(&row.get::<_, Option<String>>(4)?.unwrap_or_default());
+        let active_window = ValidityWindow::from_columns(row.get(7)?, row.get(8)?);
+        let fair_usage = FairUsage::from_columns(row.get(9)?, row.get(10)?, row.get(11)?);
+        let initial_bytes = row.get::<_, Option<u64>>(12)?.unwrap_or(0);
+        let bucket = QuotaBucket { id: row.get(5)?, name: row.get(0)?, remaining_bytes: row.get(1)?, initial_bytes, category, expiry: row.get(3)?, source, active_window, fair_usage };
+        Ok(TrashedBucket { bucket, deleted_at: row.get(6)? })
+    })
+        .map(|rows| rows.filter_map(|r| r.ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_category_rules(conn: &Connection, id: &str) -> Vec<CategoryRule> {
+    let Ok(mut stmt) = conn.prepare("SELECT pattern, category, priority FROM category_rules WHERE account_id = ?1") else { return Vec::new(); };
+    stmt.query_map(params![id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i32>(2)?)))
+        .map(|rows| rows.filter_map(|r| r.ok())
+            .filter_map(|(pattern, category, priority)| QuotaType::from_name(&category).map(|category| CategoryRule { pattern, category, priority }))
+            .collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_experiment_assignments(conn: &Connection, id: &str) -> HashMap<String, String> {
+    let Ok(mut stmt) = conn.prepare("SELECT name, variant FROM experiments WHERE account_id = ?1") else { return HashMap::new(); };
+    stmt.query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map(|rows| rows.filter_map(|r| r.ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_family_pools(conn: &Connection) -> Vec<FamilyPool> {
+    let Ok(mut stmt) = conn.prepare("SELECT payload FROM family_pools WHERE payload IS NOT NULL") else { return Vec::new(); };
+    stmt.query_map([], |row| row.get::<_, String>(0))
+        .map(|rows| rows.filter_map(|r| r.ok()).filter_map(|p| serde_json::from_str(&p).ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_account_ids(conn: &Connection) -> Vec<String> {
+    let Ok(mut stmt) = conn.prepare("SELECT id FROM accounts ORDER BY id") else { return Vec::new(); };
+    stmt.query_map([], |row| row.get(0))
+        .map(|rows| rows.filter_map(|r| r.ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn delete_account_rows(conn: &mut Connection, id: &str) -> Result<(), TelcoError> {
+    const TABLES: [&str; 14] = ["buckets", "usage_history", "event_queue", "ad_rewards", "achievements", "experiments",
+        "category_rules", "pending_orders", "snapshots", "command_audit", "subscriptions", "preferences", "insight_config",
+        "expired_buckets"];
+    let tx = conn.savepoint().map_err(db_err)?;
+    tx.execute("DELETE FROM accounts WHERE id = ?1", params![id]).map_err(db_err)?;
+    for table in TABLES {
+        tx.execute(&format!("DELETE FROM {} WHERE account_id = ?1", table), params![id]).map_err(db_err)?;
+    }
+    // A spent token stays spent; only the link to the deleted account goes.
+    tx.execute("UPDATE redeemed_tokens SET account_id = NULL WHERE account_id = ?1", params![id]).map_err(db_err)?;
+    tx.commit().map_err(db_err)
+}
+
+#[cfg(feature = "sqlite")]
+fn load_achievements(conn: &Connection, id: &str) -> Vec<AchievementRecord> {
+    let Ok(mut stmt) = conn.prepare("SELECT kind, unlocked_at FROM achievements WHERE account_id = ?1 ORDER BY unlocked_at") else { return Vec::new(); };
+    stmt.query_map(params![id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?)))
+        .map(|rows| rows.filter_map(|r| r.ok())
+            .filter_map(|(kind, unlocked_at)| Achievement::parse(&kind).map(|achievement| AchievementRecord { achievement, unlocked_at }))
+            .collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_ad_rewards(conn: &Connection, id: &str) -> Vec<(u64, u64)> {
+    let Ok(mut stmt) = conn.prepare("SELECT timestamp, bytes FROM ad_rewards WHERE account_id = ?1") else { return Vec::new(); };
+    stmt.query_map(params![id], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map(|rows| rows.filter_map(|r| r.ok()).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(feature = "sqlite")]
+fn load_event_queue(conn: &Connection, id: &str) -> Vec<TelcoEvent> {
+    let Ok(mut stmt) = conn.prepare("SELECT payload FROM event_queue WHERE account_id = ?1 ORDER BY seq") else { return Vec::new(); };
+    stmt.query_map(params![id], |row| row.get::<_, String>(0))
+        .map(|rows| rows.filter_map(|r| r.ok()).filter_map(|p| serde_json::from_str(&p).ok()).collect())
+        .unwrap_or_default()
 }
 
 #[cfg(feature = "sqlite")]
 fn load_account_internal(conn: &Connection, id: &str) -> Result<UserAccount, TelcoError> {
-    let mut stmt = conn.prepare("SELECT is_active, locked, last_traffic FROM accounts WHERE id = ?1").ok().ok_or(TelcoError::InternalError)?;
-    let (is_active, locked, last_traffic_bytes) = stmt.query_row(params![id], |row| Ok((row.get::<_, bool>(0)?, row.get::<_, bool>(1)?, row.get::<_, u64>(2)?)))
-        .unwrap_or((true, false, 0));
+    let mut stmt = conn.prepare("SELECT is_active, locked, last_traffic, registration, grace_used, grace_allowance, grace_started, throttled, wallet_cents FROM accounts WHERE id = ?1").map_err(db_err)?;
+    let (is_active, locked, last_traffic_bytes, registration, grace, throttled, wallet_cents) = stmt.query_row(params![id], |row| {
+        let grace = match (row.get::<_, Option<u64>>(4)?, row.get::<_, Option<u64>>(5)?, row.get::<_, Option<u64>>(6)?) {
+            (Some(used_bytes), Some(allowance_bytes), Some(started_at)) => Some(GraceState { used_bytes, allowance_bytes, started_at }),
+            _ => None,
+        };
+        Ok((row.get::<_, bool>(0)?, row.get::<_, bool>(1)?, row.get::<_, u64>(2)?, row.get::<_, Option<String>>(3)?, grace,
+            row.get::<_, Option<bool>>(7)?.unwrap_or(false), row.get::<_, Option<u64>>(8)?.unwrap_or(0)))
+    })
+        .unwrap_or((true, false, 0, None, None, false, 0));
 
-    let mut stmt = conn.prepare("SELECT name, remaining_bytes, category, expiry FROM buckets WHERE account_id = ?1").ok().ok_or(TelcoError::InternalError)?;
+    let mut stmt = conn.prepare("SELECT name, remaining_bytes, category, expiry, source, bucket_id, window_start, window_end, fup_threshold, fup_used, fup_period_start, initial_bytes FROM buckets WHERE account_id = ?1 AND deleted_at IS NULL ORDER BY id").map_err(db_err)?;
     let buckets: Vec<QuotaBucket> = stmt.query_map(params![id], |row| {
         let cat_str: String = row.get(2)?;
-        let category = match cat_str.as_str() { "Video" => QuotaType::Video, "Social" => QuotaType::Social, _ => QuotaType::General };
-        Ok(QuotaBucket { name: row.get(0)?, remaining_bytes: row.get(1)?, category, expiry: row.get(3)? })
-    }).ok().ok_or(TelcoError::InternalError)?.filter_map(|b| b.ok()).collect();
+        let category = QuotaType::from_name(&cat_str).unwrap_or(QuotaType::General);
+        let source = BucketSource::parse(&row.get::<_, Option<String>>(4)?.unwrap_or_default());
+        // Buckets stored before ids existed get one now; it sticks once the account is next persisted.
+        let id = row.get::<_, Option<String>>(5)?.unwrap_or_else(new_id);
+        let active_window = ValidityWindow::from_columns(row.get(6)?, row.get(7)?);
+        let fair_usage = FairUsage::from_columns(row.get(8)?, row.get(9)?, row.get(10)?);
+        let initial_bytes = row.get::<_, Option<u64>>(11)?.unwrap_or(0);
+        Ok(QuotaBucket { id, name: row.get(0)?, remaining_bytes: row.get(1)?, initial_bytes, category, expiry: row.get(3)?, source, active_window, fair_usage })
+    }).map_err(db_err)?.filter_map(|b| b.ok()).collect();
 
-    Ok(UserAccount { 
+    let mut account = UserAccount { 
         id: id.to_string(), 
         is_active, 
         biometric_locked: locked, 
-        buckets: buckets.clone(), 
+        buckets, 
         last_traffic_bytes,
-        data_balance_bytes: buckets.iter().map(|b| b.remaining_bytes).sum(),
-        current_latency_ms: 46,
-    })
+        registration: RegistrationState::parse(&registration.unwrap_or_default()),
+        grace,
+        throttled,
+        wallet_cents,
+        ..UserAccount::fresh(id)
+    };
+    account.refresh_balances();
+    Ok(account)
 }
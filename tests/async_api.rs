@@ -0,0 +1,35 @@
+#![cfg(feature = "tokio")]
+
+use telco_core::*;
+
+// Panics after every usage, standing in for a bug in the blocking method behind an `*_async` call.
+struct PanickingHooks;
+
+impl LifecycleHooks for PanickingHooks {
+    fn before_purchase(&self, _request: PurchaseRequest) -> PurchaseDecision {
+        PurchaseDecision::Allow
+    }
+
+    fn after_usage(&self, _units: u64, _category: QuotaType, _account: UserAccount) {
+        panic!("hook exploded");
+    }
+
+    fn on_cycle_rollover(&self, _rolled_over: Vec<QuotaBucket>, _forfeited_bytes: u64) {}
+}
+
+#[tokio::test]
+async fn async_methods_resolve_and_report_panics() {
+    let sim = TelcoSimulator::ephemeral("async".to_string());
+    sim.grant_bucket("Data".to_string(), 1_000_000, QuotaType::General, BucketSource::Promo).unwrap();
+    sim.clone().simulate_usage_async(250_000, QuotaType::General).await.unwrap();
+    assert_eq!(sim.clone().get_account_info_async().await.unwrap().data_balance_bytes, 750_000);
+
+    sim.set_lifecycle_hooks(Box::new(PanickingHooks));
+    match sim.clone().simulate_usage_async(1000, QuotaType::General).await {
+        Err(TelcoError::InternalError(reason)) => assert!(reason.contains("hook exploded"), "{}", reason),
+        other => panic!("expected InternalError, got {:?}", other),
+    }
+    // The simulator stays usable after a panicked call.
+    sim.clear_lifecycle_hooks();
+    assert!(sim.get_account_info_async().await.is_ok());
+}
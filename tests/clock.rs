@@ -0,0 +1,60 @@
+use telco_core::*;
+
+// A fresh database path in the temp dir, with anything left there by an earlier run (WAL, journals) removed.
+fn temp_db(name: &str) -> String {
+    let file = format!("telco_core_{}_{}.db", name, std::process::id());
+    let dir = std::env::temp_dir();
+    for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&file) { let _ = std::fs::remove_file(entry.path()); }
+    }
+    dir.join(file).display().to_string()
+}
+
+#[test]
+fn test_clock_expires_buckets_on_its_own_simulator() {
+    let path = temp_db("test_clock");
+    let clock = TestClock::new(1_000_000_000_000);
+    let sim = TelcoSimulator::with_clock("clocked".to_string(), path.clone(), Box::new(clock.clone())).unwrap();
+    let other = TelcoSimulator::new("wall".to_string(), path.clone()).unwrap();
+    for s in [&sim, &other] {
+        s.grant_bucket("Promo".to_string(), 1_000_000, QuotaType::General, BucketSource::Promo).unwrap();
+    }
+    let expiry = sim.get_account_info().unwrap().buckets[0].expiry;
+    assert!(expiry > 1_000_000_000 && expiry < other.get_account_info().unwrap().buckets[0].expiry);
+
+    clock.set(expiry * 1000 - 1000);
+    assert!(sim.process_expirations().is_empty());
+    assert_eq!(sim.get_account_info().unwrap().buckets.len(), 1);
+
+    clock.advance(1000);
+    sim.process_expirations();
+    assert!(sim.get_account_info().unwrap().buckets.is_empty());
+    other.process_expirations();
+    assert_eq!(other.get_account_info().unwrap().buckets.len(), 1);
+
+    sim.close().unwrap();
+    other.close().unwrap();
+    drop((sim, other));
+    temp_db("test_clock");
+}
+
+#[test]
+fn scenarios_only_move_their_own_simulator() {
+    let path = temp_db("scenario_clock");
+    let clock = TestClock::new(1_000_000_000_000);
+    let sims = ["ahead", "behind"].map(|id| TelcoSimulator::with_clock(id.to_string(), path.clone(), Box::new(clock.clone())).unwrap());
+    for sim in &sims {
+        sim.grant_bucket("Promo".to_string(), 1_000_000, QuotaType::General, BucketSource::Promo).unwrap();
+    }
+
+    let report = sims[0].run_scenario(load_scenario("at t+8d tick".to_string()).unwrap());
+    assert_eq!((report.steps_run, report.elapsed_secs), (1, 8 * 86400));
+    assert!(sims[0].get_account_info().unwrap().buckets.is_empty());
+    sims[1].process_expirations();
+    assert_eq!(sims[1].get_account_info().unwrap().buckets.len(), 1);
+    assert_eq!(clock.now_ms(), 1_000_000_000_000);
+
+    for sim in &sims { sim.close().unwrap(); }
+    drop(sims);
+    temp_db("scenario_clock");
+}
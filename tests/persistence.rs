@@ -0,0 +1,171 @@
+#![cfg(feature = "sqlite")]
+
+use telco_core::*;
+
+// A fresh database path in the temp dir, with anything left there by an earlier run (WAL, journals) removed.
+fn temp_db(name: &str) -> String {
+    let file = format!("telco_core_{}_{}.db", name, std::process::id());
+    let dir = std::env::temp_dir();
+    for entry in std::fs::read_dir(&dir).into_iter().flatten().flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&file) { let _ = std::fs::remove_file(entry.path()); }
+    }
+    dir.join(file).display().to_string()
+}
+
+fn bucket_ids(sim: &TelcoSimulator) -> Vec<String> {
+    sim.get_account_info().unwrap().buckets.into_iter().map(|b| b.id).collect()
+}
+
+#[test]
+fn removed_buckets_round_trip() {
+    let path = temp_db("round_trip");
+    let (kept, cancelled, restored_away) = {
+        let sim = TelcoSimulator::new("round-trip".to_string(), path.clone()).unwrap();
+        sim.grant_bucket("Kept".to_string(), 1_000_000, QuotaType::General, BucketSource::Promo).unwrap();
+        sim.grant_bucket("Cancelled".to_string(), 2_000_000, QuotaType::Social, BucketSource::Promo).unwrap();
+        let id = |name: &str| sim.get_account_info().unwrap().buckets.into_iter().find(|b| b.name == name).unwrap().id;
+        let (kept, cancelled) = (id("Kept"), id("Cancelled"));
+        sim.simulate_usage(250_000, QuotaType::General).unwrap();
+        sim.cancel_bucket(cancelled.clone()).unwrap();
+        // Buckets a snapshot restore drops are gone for good, not trashed.
+        sim.create_snapshot("before".to_string()).unwrap();
+        sim.grant_bucket("Restored away".to_string(), 3_000_000, QuotaType::Video, BucketSource::Promo).unwrap();
+        let restored_away = id("Restored away");
+        sim.restore_snapshot("before".to_string()).unwrap();
+        sim.close().unwrap();
+        (kept, cancelled, restored_away)
+    };
+
+    let sim = TelcoSimulator::new("round-trip".to_string(), path.clone()).unwrap();
+    let account = sim.get_account_info().unwrap();
+    let ids: Vec<String> = account.buckets.iter().map(|b| b.id.clone()).collect();
+    assert!(ids.contains(&kept));
+    assert_eq!(account.buckets.iter().find(|b| b.id == kept).unwrap().remaining_bytes, 750_000);
+    assert!(!ids.contains(&cancelled));
+    assert!(!ids.contains(&restored_away));
+    let trash: Vec<String> = sim.list_trashed_buckets().into_iter().map(|t| t.bucket.id).collect();
+    assert_eq!(trash, vec![cancelled.clone()]);
+
+    sim.restore_bucket(cancelled.clone()).unwrap();
+    sim.close().unwrap();
+    drop(sim);
+
+    let sim = TelcoSimulator::new("round-trip".to_string(), path.clone()).unwrap();
+    assert!(bucket_ids(&sim).contains(&cancelled));
+    assert!(sim.list_trashed_buckets().is_empty());
+    sim.close().unwrap();
+    drop(sim);
+    temp_db("round_trip");
+}
+
+#[test]
+fn registration_round_trip() {
+    let path = temp_db("registration");
+    for state in [RegistrationState::Roaming, RegistrationState::EmergencyOnly, RegistrationState::Searching, RegistrationState::Denied] {
+        let sim = TelcoSimulator::new("registration".to_string(), path.clone()).unwrap();
+        if sim.get_account_info().unwrap().registration != RegistrationState::Searching {
+            sim.set_registration_state(RegistrationState::Searching).unwrap();
+        }
+        if state != RegistrationState::Searching { sim.set_registration_state(state).unwrap(); }
+        sim.close().unwrap();
+        drop(sim);
+        let sim = TelcoSimulator::new("registration".to_string(), path.clone()).unwrap();
+        assert_eq!(sim.get_account_info().unwrap().registration, state);
+        sim.close().unwrap();
+    }
+
+    let sim = TelcoSimulator::new("registration".to_string(), path.clone()).unwrap();
+    sim.set_flight_mode(true);
+    sim.close().unwrap();
+    drop(sim);
+    let sim = TelcoSimulator::new("registration".to_string(), path).unwrap();
+    assert!(sim.is_flight_mode());
+    sim.close().unwrap();
+    drop(sim);
+    temp_db("registration");
+}
+
+// Rows within a single minute an hour back, so compaction and pruning have something to merge.
+fn record_past_usage(sim: &TelcoSimulator, amounts: &[u64]) {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let minute = (now - 3600) / 60 * 60;
+    let events = amounts.iter().enumerate()
+        .map(|(i, &bytes)| UsageEvent { bytes, category: QuotaType::General, timestamp: Some(minute + i as u64) })
+        .collect();
+    assert_eq!(sim.simulate_usage_batch(events).unwrap().applied, amounts.len() as u32);
+}
+
+fn history_total(sim: &TelcoSimulator) -> (usize, u64) {
+    let history = sim.get_historical_usage(1000).unwrap();
+    (history.len(), history.iter().map(|r| r.amount).sum())
+}
+
+// Closing and reopening is how a test waits for the writer to catch up.
+fn reopen(sim: std::sync::Arc<TelcoSimulator>, path: &str) -> std::sync::Arc<TelcoSimulator> {
+    let id = sim.get_account_info().unwrap().id;
+    sim.close().unwrap();
+    drop(sim);
+    TelcoSimulator::new(id, path.to_string()).unwrap()
+}
+
+#[test]
+fn compaction_only_touches_its_account() {
+    let path = temp_db("compaction");
+    for id in ["compact-a", "compact-b"] {
+        let sim = TelcoSimulator::new(id.to_string(), path.clone()).unwrap();
+        sim.grant_bucket("History".to_string(), 10_000_000, QuotaType::General, BucketSource::Promo).unwrap();
+        record_past_usage(&sim, &[1000, 2000, 3000]);
+        sim.close().unwrap();
+    }
+
+    let a = TelcoSimulator::new("compact-a".to_string(), path.clone()).unwrap();
+    let b = TelcoSimulator::new("compact-b".to_string(), path.clone()).unwrap();
+    let report = a.compact_usage_history(60).unwrap();
+    assert_eq!((report.rows_before, report.rows_after), (3, 1));
+    assert_eq!(history_total(&a), (1, 6000));
+    assert_eq!(b.get_historical_usage(100).unwrap().len(), 3);
+    a.close().unwrap();
+    b.close().unwrap();
+    drop((a, b));
+    temp_db("compaction");
+}
+
+// A snapshot restore drops the usage recorded after the snapshot, even once compaction or pruning has rewritten it,
+// and keeps everything from before.
+fn restore_after_history_rewrite(name: &str, rewrite: impl Fn(&TelcoSimulator)) {
+    let path = temp_db(name);
+    let sim = TelcoSimulator::new(name.to_string(), path.clone()).unwrap();
+    sim.grant_bucket("History".to_string(), 10_000_000, QuotaType::General, BucketSource::Promo).unwrap();
+    record_past_usage(&sim, &[1000, 2000, 3000]);
+    let sim = reopen(sim, &path);
+    sim.create_snapshot("before".to_string()).unwrap();
+    record_past_usage(&sim, &[400, 500]);
+    let sim = reopen(sim, &path);
+    assert_eq!(history_total(&sim).1, 6900);
+
+    rewrite(&sim);
+    assert_eq!(history_total(&sim).1, 6900);
+    sim.restore_snapshot("before".to_string()).unwrap();
+    let sim = reopen(sim, &path);
+    assert_eq!(history_total(&sim).1, 6000);
+    sim.close().unwrap();
+    drop(sim);
+    temp_db(name);
+}
+
+#[test]
+fn snapshot_restore_survives_compaction() {
+    restore_after_history_rewrite("snapshot_compaction", |sim| {
+        let report = sim.compact_usage_history(60).unwrap();
+        assert_eq!((report.rows_before, report.rows_after), (5, 2));
+    });
+}
+
+#[test]
+fn snapshot_restore_survives_pruning() {
+    restore_after_history_rewrite("snapshot_pruning", |sim| {
+        sim.set_retention_policy(RetentionPolicy { max_rows: None, max_age_secs: Some(60) });
+        let report = sim.prune_history().unwrap();
+        assert_eq!((report.rows_pruned, report.rollup_rows), (5, 2));
+    });
+}